@@ -0,0 +1,531 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::validation::{self, ValidationErrors};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/*
+ * Address DESIGN
+ *
+ * Structured postal address of users and organizations (farms,
+ * dealerships). Supported countries are listed in `COUNTRIES`, with
+ * their postal code patterns and address layout.
+ *
+ * Postal code patterns: # is a digit, A is a letter, space and - are
+ * separators. Input is matched without separators, and formatted
+ * with the ones of the pattern, so "94901" becomes "949 01" in SK.
+ *
+ * Free text addresses stored before this model are loaded with the
+ * text as street, and with empty country, postal code and city. They
+ * fail validation, so the user is asked to complete them.
+ */
+
+/// # Address layout
+/// Order of lines on an envelope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    /// Street, then postal code and city: 1011 Budapest
+    PostalCodeFirst,
+    /// City, street, postal code in its own line (Hungarian)
+    CityFirst,
+    /// Street, then city and postal code: Springfield 12345
+    PostalCodeLast,
+}
+
+struct Country {
+    code: &'static str,
+    name: &'static str,
+    postal_codes: &'static [&'static str],
+    layout: Layout,
+}
+
+const COUNTRIES: &[Country] = &[
+    Country {
+        code: "HU",
+        name: "Hungary",
+        postal_codes: &["####"],
+        layout: Layout::CityFirst,
+    },
+    Country {
+        code: "AT",
+        name: "Austria",
+        postal_codes: &["####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "CZ",
+        name: "Czechia",
+        postal_codes: &["### ##"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "DE",
+        name: "Germany",
+        postal_codes: &["#####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "FR",
+        name: "France",
+        postal_codes: &["#####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "GB",
+        name: "United Kingdom",
+        postal_codes: &[
+            "A# #AA", "A## #AA", "AA# #AA", "AA## #AA", "A#A #AA", "AA#A #AA",
+        ],
+        layout: Layout::PostalCodeLast,
+    },
+    Country {
+        code: "HR",
+        name: "Croatia",
+        postal_codes: &["#####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "IT",
+        name: "Italy",
+        postal_codes: &["#####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "NL",
+        name: "Netherlands",
+        postal_codes: &["#### AA"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "PL",
+        name: "Poland",
+        postal_codes: &["##-###"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "RO",
+        name: "Romania",
+        postal_codes: &["######"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "RS",
+        name: "Serbia",
+        postal_codes: &["#####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "SI",
+        name: "Slovenia",
+        postal_codes: &["####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "SK",
+        name: "Slovakia",
+        postal_codes: &["### ##"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "UA",
+        name: "Ukraine",
+        postal_codes: &["#####"],
+        layout: Layout::PostalCodeFirst,
+    },
+    Country {
+        code: "US",
+        name: "United States",
+        postal_codes: &["#####", "#####-####"],
+        layout: Layout::PostalCodeLast,
+    },
+];
+
+fn find_country(code: &str) -> Option<&'static Country> {
+    COUNTRIES
+        .iter()
+        .find(|country| country.code.eq_ignore_ascii_case(code.trim()))
+}
+
+/// # Supported countries
+/// Country code and name pairs, for form select lists.
+pub fn get_countries() -> Vec<(&'static str, &'static str)> {
+    COUNTRIES
+        .iter()
+        .map(|country| (country.code, country.name))
+        .collect()
+}
+
+/// # Normalize country
+/// Uppercase ISO 3166 code of a supported country.
+pub fn normalize_country(code: &str) -> Result<String, String> {
+    match find_country(code) {
+        Some(country) => Ok(country.code.to_owned()),
+        None if code.trim().is_empty() => Err("Country is required.".to_owned()),
+        None => Err(format!("Country {} is not supported.", code.trim())),
+    }
+}
+
+fn is_separator(ch: char) -> bool {
+    ch == ' ' || ch == '-'
+}
+
+/// Postal code formatted by `pattern`, if it matches
+fn apply_pattern(pattern: &str, compact: &[char]) -> Option<String> {
+    let placeholders = pattern.chars().filter(|ch| !is_separator(*ch)).count();
+    if placeholders != compact.len() {
+        return None;
+    }
+    let mut chars = compact.iter();
+    let mut result = String::new();
+    for placeholder in pattern.chars() {
+        if is_separator(placeholder) {
+            result.push(placeholder);
+            continue;
+        }
+        let ch = *chars.next()?;
+        let matches = match placeholder {
+            '#' => ch.is_ascii_digit(),
+            _ => ch.is_ascii_uppercase(),
+        };
+        if !matches {
+            return None;
+        }
+        result.push(ch);
+    }
+    Some(result)
+}
+
+/// # Normalize postal code
+/// Postal code checked and formatted by the patterns of the country.
+/// ```rust
+/// use core_lib::address::normalize_postal_code;
+/// assert_eq!(normalize_postal_code("HU", " 6725 "), Ok("6725".to_owned()));
+/// assert_eq!(normalize_postal_code("SK", "94901"), Ok("949 01".to_owned()));
+/// assert_eq!(normalize_postal_code("GB", "sw1a1aa"), Ok("SW1A 1AA".to_owned()));
+/// assert_eq!(normalize_postal_code("HU", "67250").is_err(), true);
+/// ```
+pub fn normalize_postal_code(country: &str, postal_code: &str) -> Result<String, String> {
+    let country = match find_country(country) {
+        Some(country) => country,
+        None => return Err("Postal code cannot be checked without a valid country.".to_owned()),
+    };
+    let compact: Vec<char> = postal_code
+        .trim()
+        .to_uppercase()
+        .chars()
+        .filter(|ch| !is_separator(*ch))
+        .collect();
+    if compact.is_empty() {
+        return Err("Postal code is required.".to_owned());
+    }
+    country
+        .postal_codes
+        .iter()
+        .find_map(|pattern| apply_pattern(pattern, &compact))
+        .ok_or_else(|| {
+            format!(
+                "Postal code of {} must look like {} (# is a digit, A is a letter).",
+                country.name,
+                country.postal_codes.join(" or ")
+            )
+        })
+}
+
+/// # GPS location
+/// WGS 84 coordinates in degrees, like the location of a farm.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GeoLocation {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl GeoLocation {
+    /// # New location
+    /// Latitude must be between -90 and 90, longitude between -180
+    /// and 180.
+    /// ```rust
+    /// use core_lib::address::GeoLocation;
+    /// assert_eq!(GeoLocation::new(46.253, 20.1414).is_ok(), true);
+    /// assert_eq!(GeoLocation::new(91.0, 20.1414).is_err(), true);
+    /// ```
+    pub fn new(latitude: f64, longitude: f64) -> Result<GeoLocation, String> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            Err("Latitude must be between -90 and 90.".to_owned())
+        } else if !(-180.0..=180.0).contains(&longitude) {
+            Err("Longitude must be between -180 and 180.".to_owned())
+        } else {
+            Ok(GeoLocation {
+                latitude,
+                longitude,
+            })
+        }
+    }
+    /// # Parse location
+    /// From "latitude, longitude" text, as copied from map apps.
+    pub fn parse(text: &str) -> Result<GeoLocation, String> {
+        let parts: Vec<&str> = text.split(',').map(|part| part.trim()).collect();
+        let coordinates: Vec<f64> = parts.iter().filter_map(|part| part.parse().ok()).collect();
+        if parts.len() != 2 || coordinates.len() != 2 {
+            return Err("Location must be given as latitude, longitude.".to_owned());
+        }
+        GeoLocation::new(coordinates[0], coordinates[1])
+    }
+    pub fn get_latitude(&self) -> f64 {
+        self.latitude
+    }
+    pub fn get_longitude(&self) -> f64 {
+        self.longitude
+    }
+}
+
+impl fmt::Display for GeoLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.6}, {:.6}", self.latitude, self.longitude)
+    }
+}
+
+/// # Postal address
+/// Validated when created, so a stored address is complete, except
+/// the free text ones loaded from before the structured model.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "StoredAddress")]
+pub struct Address {
+    country: String,
+    postal_code: String,
+    city: String,
+    street: String,
+    location: Option<GeoLocation>,
+}
+
+/// Address fields, as stored by `Address`
+#[derive(Deserialize)]
+struct AddressFields {
+    country: String,
+    postal_code: String,
+    city: String,
+    street: String,
+    #[serde(default)]
+    location: Option<GeoLocation>,
+}
+
+/// Structured address, or the free text one stored before
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredAddress {
+    Structured(AddressFields),
+    FreeText(String),
+}
+
+impl From<StoredAddress> for Address {
+    fn from(address: StoredAddress) -> Self {
+        match address {
+            StoredAddress::Structured(fields) => Address {
+                country: fields.country,
+                postal_code: fields.postal_code,
+                city: fields.city,
+                street: fields.street,
+                location: fields.location,
+            },
+            StoredAddress::FreeText(text) => Address {
+                country: String::new(),
+                postal_code: String::new(),
+                city: String::new(),
+                street: text,
+                location: None,
+            },
+        }
+    }
+}
+
+impl Address {
+    /// # New address
+    /// Validates every field, and returns all the errors at once.
+    /// Fields are trimmed, the country code and postal code are
+    /// normalized.
+    /// ```rust
+    /// use core_lib::address::Address;
+    /// let address = Address::new("hu", "6725", "Szeged", "Tisza Lajos körút 1.").unwrap();
+    /// assert_eq!(address.get_country(), "HU");
+    /// assert_eq!(address.to_string(), "Szeged\nTisza Lajos körút 1.\n6725\nHungary");
+    /// let errors = Address::new("HU", "123", "", "Fő utca 1.").unwrap_err();
+    /// assert_eq!(errors.get("postal_code").is_some(), true);
+    /// assert_eq!(errors.get("city").is_some(), true);
+    /// ```
+    pub fn new(
+        country: &str,
+        postal_code: &str,
+        city: &str,
+        street: &str,
+    ) -> Result<Address, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let country = errors.check("country", normalize_country(country));
+        let postal_code = match country {
+            Some(ref country) => {
+                errors.check("postal_code", normalize_postal_code(country, postal_code))
+            }
+            None => None,
+        };
+        let city = errors.check("city", validation::validate_length(city, "City", 2, 100));
+        let street = errors.check(
+            "street",
+            validation::validate_length(street, "Street", 3, 200),
+        );
+        match (country, postal_code, city, street) {
+            (Some(country), Some(postal_code), Some(city), Some(street)) if errors.is_empty() => {
+                Ok(Address {
+                    country,
+                    postal_code,
+                    city,
+                    street,
+                    location: None,
+                })
+            }
+            _ => Err(errors),
+        }
+    }
+    /// # With location
+    /// Same address with GPS location, like the farm yard.
+    pub fn with_location(mut self, location: GeoLocation) -> Self {
+        self.location = Some(location);
+        self
+    }
+    /// # Validate address
+    /// Checks a stored address again, free text ones fail.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        Address::new(&self.country, &self.postal_code, &self.city, &self.street).map(|_| ())
+    }
+    /// ISO 3166 country code, empty for free text addresses
+    pub fn get_country(&self) -> &str {
+        &self.country
+    }
+    pub fn get_country_name(&self) -> Option<&'static str> {
+        find_country(&self.country).map(|country| country.name)
+    }
+    pub fn get_postal_code(&self) -> &str {
+        &self.postal_code
+    }
+    pub fn get_city(&self) -> &str {
+        &self.city
+    }
+    pub fn get_street(&self) -> &str {
+        &self.street
+    }
+    pub fn get_location(&self) -> Option<GeoLocation> {
+        self.location
+    }
+    /// # Address lines
+    /// Lines in the postal layout of the country, the last one is the
+    /// country name. Empty lines are left out.
+    pub fn get_lines(&self) -> Vec<String> {
+        let layout = find_country(&self.country).map_or(Layout::PostalCodeFirst, |c| c.layout);
+        let postal_city = |first: &str, second: &str| format!("{} {}", first, second);
+        let mut lines = match layout {
+            Layout::PostalCodeFirst => vec![
+                self.street.clone(),
+                postal_city(&self.postal_code, &self.city),
+            ],
+            Layout::CityFirst => vec![
+                self.city.clone(),
+                self.street.clone(),
+                self.postal_code.clone(),
+            ],
+            Layout::PostalCodeLast => vec![
+                self.street.clone(),
+                postal_city(&self.city, &self.postal_code),
+            ],
+        };
+        if let Some(name) = self.get_country_name() {
+            lines.push(name.to_owned());
+        }
+        lines
+            .into_iter()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+    /// # Single line address
+    /// Lines joined by comma, for lists and search.
+    pub fn to_single_line(&self) -> String {
+        self.get_lines().join(", ")
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_lines().join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postal_codes() {
+        assert_eq!(
+            normalize_postal_code("nl", "1234ab"),
+            Ok("1234 AB".to_owned())
+        );
+        assert_eq!(
+            normalize_postal_code("PL", "61 245"),
+            Ok("61-245".to_owned())
+        );
+        assert_eq!(
+            normalize_postal_code("US", "123456789"),
+            Ok("12345-6789".to_owned())
+        );
+        assert_eq!(
+            normalize_postal_code("GB", "M1 1AE"),
+            Ok("M1 1AE".to_owned())
+        );
+        assert!(normalize_postal_code("GB", "1M 1AE").is_err());
+        assert!(normalize_postal_code("AT", "").is_err());
+        assert!(normalize_postal_code("XX", "1234").is_err());
+        assert!(normalize_country("XX").is_err());
+        assert!(normalize_country(" ").is_err());
+    }
+
+    #[test]
+    fn test_address() {
+        let location = GeoLocation::parse("46.2530, 20.1414").unwrap();
+        assert!(GeoLocation::parse("46.2530").is_err());
+        assert!(GeoLocation::parse("46.2530, east").is_err());
+        let address = Address::new("AT", " 1010 ", " Wien ", "Stephansplatz 1")
+            .unwrap()
+            .with_location(location);
+        assert_eq!(address.get_postal_code(), "1010");
+        assert_eq!(address.get_city(), "Wien");
+        assert_eq!(
+            address.to_single_line(),
+            "Stephansplatz 1, 1010 Wien, Austria"
+        );
+        assert!(address.validate().is_ok());
+
+        // Stored and loaded again
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), address);
+
+        // Free text address from before the structured model
+        let address: Address = serde_json::from_str("\"Szeged, Fő tér 1.\"").unwrap();
+        assert_eq!(address.get_street(), "Szeged, Fő tér 1.");
+        assert_eq!(address.get_country(), "");
+        assert_eq!(address.to_single_line(), "Szeged, Fő tér 1.");
+        let errors = address.validate().unwrap_err();
+        assert!(errors.get("country").is_some());
+    }
+}
@@ -18,6 +18,7 @@
 use lettre::smtp::authentication::Credentials;
 use lettre::{SmtpClient, Transport};
 use lettre_email::Email;
+use std::env;
 
 // TODO: Refactor to split email for production and test use.
 // For test use, it should behave like a real email service,
@@ -64,6 +65,32 @@ pub fn send_new_email(
     }
 }
 
+/// # Send email using environment settings
+/// Same as `send_new_email`, but the SMTP client, credentials and the
+/// sender address are read from the E_CLIENT, E_USERNAME, E_PASSWORD
+/// and E_FROM environment variables.
+pub fn send_email_from_env(
+    to: &str,
+    to_name: &str,
+    subject: &str,
+    body: &str,
+) -> Result<(), String> {
+    let setting = |name: &str| match env::var(name) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(format!("Email setting {} is missing.", name)),
+    };
+    send_new_email(
+        &setting("E_CLIENT")?,
+        &setting("E_USERNAME")?,
+        &setting("E_PASSWORD")?,
+        to,
+        to_name,
+        &setting("E_FROM")?,
+        subject,
+        body,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
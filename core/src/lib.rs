@@ -14,16 +14,28 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+extern crate argon2;
+extern crate base32;
+extern crate base64;
 extern crate bcrypt;
+extern crate chrono;
+extern crate csv;
+extern crate hmac;
 extern crate lettre;
 extern crate lettre_email;
-extern crate rand;
+extern crate rand_core;
+extern crate sha1;
+extern crate sha2;
 
+pub mod address;
 pub mod email;
 pub mod error;
+pub mod organization;
 pub mod prelude;
+pub mod search;
 pub mod storage;
 pub mod user;
+pub mod validation;
 
 pub use error::*;
 pub use user::login::*;
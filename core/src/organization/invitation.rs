@@ -0,0 +1,263 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email;
+use crate::organization::*;
+use crate::storage::{self, get_mut_by_id, StorageObject};
+use crate::user::token::{generate_token, hash_token};
+use crate::user::User;
+use chrono::Duration;
+
+/// Invitation is valid for this many days
+pub const INVITATION_VALID_DAYS: i64 = 7;
+
+/// # Organization invitation
+///
+/// Invite someone by email to join an organization with a given role.
+/// The invitation link contains a random token, the invitation ID is
+/// the hash of the token.
+#[derive(Serialize, Deserialize)]
+pub struct Invitation {
+    id: String,
+    path: Option<String>,
+    organization: Ref<OrganizationV1>,
+    email: String,
+    role: OrganizationRole,
+    invited_by: Ref<UserV2>,
+    created: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    accepted: Option<DateTime<Utc>>,
+}
+
+impl Invitation {
+    pub fn get_organization(&self) -> &Ref<OrganizationV1> {
+        &self.organization
+    }
+    pub fn get_email(&self) -> &str {
+        &self.email
+    }
+    pub fn get_invited_by(&self) -> &Ref<UserV2> {
+        &self.invited_by
+    }
+    pub fn get_role(&self) -> OrganizationRole {
+        self.role
+    }
+    pub fn is_accepted(&self) -> bool {
+        self.accepted.is_some()
+    }
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires
+    }
+}
+
+impl StorageObject for Invitation {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Invite to organization
+///
+/// Only owners and managers can invite, and only owners can invite
+/// new owners. Returns the token, use it in the invitation link.
+/// Send the email with `send_invitation_email`.
+pub fn invite<T: User>(
+    invitations: &mut Storage<Invitation>,
+    organization: &OrganizationV1,
+    inviter: &T,
+    email: &str,
+    role: OrganizationRole,
+) -> Result<String, String> {
+    let organization_id = match organization.get_organization_id() {
+        Some(id) => id,
+        None => return Err("Organization ID is not set.".to_owned()),
+    };
+    let inviter_id = match inviter.get_user_id() {
+        Some(id) => id,
+        None => return Err("Inviter user ID is not set.".to_owned()),
+    };
+    match organization.get_member_role(&inviter_id) {
+        Some(inviter_role) if inviter_role.can_invite() => {
+            if role == OrganizationRole::Owner && !inviter_role.can_manage_members() {
+                return Err("Only owners can invite new owners.".to_owned());
+            }
+        }
+        _ => return Err("User has no permission to invite.".to_owned()),
+    }
+    if !email.contains('@') || !email.contains('.') {
+        return Err("Wrong email format!".to_owned());
+    }
+    let token = generate_token()?;
+    let created = Utc::now();
+    let invitation = Invitation {
+        id: hash_token(&token),
+        path: None,
+        organization: Ref::new(&organization_id),
+        email: email.trim().to_owned(),
+        role,
+        invited_by: Ref::new(&inviter_id),
+        created,
+        expires: created + Duration::days(INVITATION_VALID_DAYS),
+        accepted: None,
+    };
+    storage::add_to_storage(invitations, invitation)?;
+    Ok(token)
+}
+
+/// # Send invitation email
+/// `link` is the full invitation URL containing the token.
+pub fn send_invitation_email(
+    invitation: &Invitation,
+    organization_name: &str,
+    link: &str,
+) -> Result<(), String> {
+    email::send_email_from_env(
+        &invitation.email,
+        &invitation.email,
+        &format!("Invitation to {}", organization_name),
+        &format!(
+            "Hi! You are invited to join {}. To accept it, please visit: {}\n\
+             The invitation expires at {}.",
+            organization_name,
+            link,
+            invitation.expires.format("%Y-%m-%d %H:%M UTC")
+        ),
+    )
+}
+
+/// # Accept invitation
+///
+/// The invitation must be valid, and the user email must match the
+/// invited email address. The user becomes a member with the invited
+/// role.
+pub fn accept_invitation<T: User>(
+    invitations: &mut Storage<Invitation>,
+    organizations: &mut Storage<OrganizationV1>,
+    token: &str,
+    user: &T,
+) -> Result<(), String> {
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err("User ID is not set.".to_owned()),
+    };
+    let invitation = match get_mut_by_id(invitations, &hash_token(token)) {
+        Some(invitation) => invitation,
+        None => return Err("Invitation not found.".to_owned()),
+    };
+    if invitation.is_accepted() {
+        return Err("Invitation is already accepted.".to_owned());
+    }
+    if invitation.is_expired() {
+        return Err("Invitation is expired.".to_owned());
+    }
+    let email_matches = user
+        .get_user_email()
+        .is_some_and(|email| email.eq_ignore_ascii_case(&invitation.email));
+    if !email_matches {
+        return Err("Invitation was sent to another email address.".to_owned());
+    }
+    let organization = match get_mut_by_id(organizations, invitation.organization.get_id()) {
+        Some(organization) => organization,
+        None => return Err("Organization not found.".to_owned()),
+    };
+    organization.add_member(&user_id, invitation.role)?;
+    organization.save()?;
+    invitation.accepted = Some(Utc::now());
+    invitation.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    fn user(id: &str, email: &str) -> UserV2 {
+        let mut user = UserV2::new();
+        user.set_user_id(id).unwrap();
+        user.set_user_email(email).unwrap();
+        user
+    }
+
+    #[test]
+    fn test_invitation() {
+        let mut invitations =
+            storage::load_storage::<Invitation>("../data/invitation_invitations").unwrap();
+        let mut organizations =
+            storage::load_storage::<OrganizationV1>("../data/invitation_organizations").unwrap();
+        let owner = user("owner_user", "owner@farm.com");
+        let member = user("member_user", "member@farm.com");
+        let mut organization = OrganizationV1::new();
+        organization.set_organization_id("kovacs_farm").unwrap();
+        organization
+            .add_member("owner_user", OrganizationRole::Owner)
+            .unwrap();
+        organization
+            .add_member("member_user", OrganizationRole::Member)
+            .unwrap();
+        storage::add_to_storage(&mut organizations, organization).unwrap();
+        let organization = &organizations.data[0];
+
+        // Members cannot invite
+        assert!(invite(
+            &mut invitations,
+            organization,
+            &member,
+            "new@farm.com",
+            OrganizationRole::Member
+        )
+        .is_err());
+        let token = invite(
+            &mut invitations,
+            organization,
+            &owner,
+            "new@farm.com",
+            OrganizationRole::Manager,
+        )
+        .unwrap();
+
+        // Wrong user email
+        let other = user("other_user", "other@farm.com");
+        assert!(accept_invitation(&mut invitations, &mut organizations, &token, &other).is_err());
+        let new_user = user("new_user", "NEW@farm.com");
+        accept_invitation(&mut invitations, &mut organizations, &token, &new_user).unwrap();
+        assert_eq!(
+            organizations.data[0].get_member_role("new_user"),
+            Some(OrganizationRole::Manager)
+        );
+        // The token itself is not stored
+        assert!(storage::get_by_id(&invitations, &token).is_none());
+        // Single use
+        assert!(
+            accept_invitation(&mut invitations, &mut organizations, &token, &new_user).is_err()
+        );
+        invitations.remove();
+        organizations.remove();
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+pub mod invitation;
+pub mod model;
+
+use crate::address::Address;
+use crate::storage::relation::Ref;
+use crate::storage::{get_by_id, Storage};
+use crate::user::model::user_v2::UserV2;
+use chrono::{DateTime, Utc};
+use model::organization_v1::OrganizationV1;
+use serde::{Deserialize, Serialize};
+
+/// # Organization role
+/// Role of a member inside an organization (farm, dealership).
+///  - Owner: everything, including managing members
+///  - Manager: manage content and invite members
+///  - Member: belongs to the organization
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OrganizationRole {
+    Owner,
+    Manager,
+    Member,
+}
+
+impl OrganizationRole {
+    pub fn can_manage_content(&self) -> bool {
+        *self == OrganizationRole::Owner || *self == OrganizationRole::Manager
+    }
+    pub fn can_invite(&self) -> bool {
+        *self == OrganizationRole::Owner || *self == OrganizationRole::Manager
+    }
+    pub fn can_manage_members(&self) -> bool {
+        *self == OrganizationRole::Owner
+    }
+}
+
+/// # Organization member
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Member {
+    pub user: Ref<UserV2>,
+    pub role: OrganizationRole,
+    pub joined: DateTime<Utc>,
+}
+
+pub trait Organization {
+    fn get_organization_id(&self) -> Option<String>;
+    fn set_organization_id(&mut self, id: &str) -> Result<(), String>;
+    fn get_organization_name(&self) -> Option<String>;
+    fn set_organization_name(&mut self, name: &str) -> Result<(), String>;
+    fn get_organization_address(&self) -> Option<Address>;
+    fn set_organization_address(&mut self, address: Address) -> Result<(), String>;
+    fn get_members(&self) -> Vec<Member>;
+    fn get_member_role(&self, user_id: &str) -> Option<OrganizationRole>;
+    fn add_member(&mut self, user_id: &str, role: OrganizationRole) -> Result<(), String>;
+    fn remove_member(&mut self, user_id: &str) -> Result<(), String>;
+    fn set_member_role(&mut self, user_id: &str, role: OrganizationRole) -> Result<(), String>;
+}
+
+/// # Content owner
+///
+/// Content (e.g. machine entries) is owned either by a single user,
+/// or by an organization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ContentOwner {
+    User(Ref<UserV2>),
+    Organization(Ref<OrganizationV1>),
+}
+
+impl ContentOwner {
+    /// # Can user edit owned content?
+    /// The owner user, or an organization owner or manager can.
+    /// ```rust
+    /// use core_lib::organization::ContentOwner;
+    /// use core_lib::organization::model::organization_v1::OrganizationV1;
+    /// use core_lib::storage::load_storage;
+    /// use core_lib::storage::relation::Ref;
+    /// let organizations = load_storage::<OrganizationV1>("../data/doc_content_owner").unwrap();
+    /// let owner = ContentOwner::User(Ref::new("demo_user"));
+    /// assert_eq!(owner.can_edit("demo_user", &organizations), true);
+    /// assert_eq!(owner.can_edit("other_user", &organizations), false);
+    /// organizations.remove();
+    /// ```
+    pub fn can_edit(&self, user_id: &str, organizations: &Storage<OrganizationV1>) -> bool {
+        match self {
+            ContentOwner::User(user) => user.get_id() == user_id,
+            ContentOwner::Organization(organization) => {
+                match get_by_id(organizations, organization.get_id()) {
+                    Some(organization) => organization
+                        .get_member_role(user_id)
+                        .is_some_and(|role| role.can_manage_content()),
+                    None => false,
+                }
+            }
+        }
+    }
+}
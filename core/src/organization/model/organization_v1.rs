@@ -0,0 +1,352 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::address::Address;
+use crate::organization::*;
+use crate::prelude::*;
+use crate::storage;
+use crate::storage::relation::Relation;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct OrganizationV1 {
+    id: Option<String>,
+    path: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    address: Option<Address>,
+    members: Vec<Member>,
+}
+
+impl New for OrganizationV1 {
+    /// # New organization
+    /// generating new organization with None default values,
+    /// and without members.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::organization::model::organization_v1::OrganizationV1;
+    /// let organization = OrganizationV1::new();
+    /// ```
+    fn new() -> Self {
+        OrganizationV1 {
+            id: None,
+            path: None,
+            name: None,
+            address: None,
+            members: Vec::new(),
+        }
+    }
+}
+
+impl OrganizationV1 {
+    /// # Membership relation to users
+    /// Once a user is deleted, its membership is removed. The last
+    /// owner of an organization with other members cannot be deleted,
+    /// the ownership must be handed over first.
+    pub fn user_relation() -> Relation<OrganizationV1, UserV2> {
+        let relation: Relation<OrganizationV1, UserV2> = Relation::nullify(
+            |organization| organization.members.iter().map(|m| &m.user).collect(),
+            |organization, user| organization.members.retain(|m| m.user != *user),
+        );
+        relation.with_check(|organization, user| {
+            let is_owner =
+                organization.get_member_role(user.get_id()) == Some(OrganizationRole::Owner);
+            if is_owner && organization.owner_count() == 1 && organization.members.len() > 1 {
+                Err(format!(
+                    "User is the last owner of {}, hand over the ownership first.",
+                    organization.get_organization_name().unwrap_or_default()
+                ))
+            } else {
+                Ok(())
+            }
+        })
+    }
+    fn owner_count(&self) -> usize {
+        self.members
+            .iter()
+            .filter(|member| member.role == OrganizationRole::Owner)
+            .count()
+    }
+}
+
+impl Organization for OrganizationV1 {
+    fn get_organization_id(&self) -> Option<String> {
+        self.id.clone()
+    }
+    /// # Set organization ID
+    /// Result<(), String>
+    /// Minimum ID length is 5 characters, and it cannot be modified.
+    fn set_organization_id(&mut self, id: &str) -> Result<(), String> {
+        if self.id.is_some() {
+            Err("Organization ID already set! It can't be modified!".to_owned())
+        } else if id.chars().count() < 5 {
+            Err("Organization ID must be at least 5 characters long.".to_owned())
+        } else {
+            self.id = Some(id.to_lowercase());
+            Ok(())
+        }
+    }
+    fn get_organization_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    /// # Set organization name
+    /// Result<(), String>
+    /// Minimum character length is 3
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::organization::Organization;
+    /// use core_lib::organization::model::organization_v1::OrganizationV1;
+    /// let mut organization = OrganizationV1::new();
+    /// assert_eq!(organization.set_organization_name("Kovács Farm"), Ok(()));
+    /// assert_eq!(organization.set_organization_name("KF").is_err(), true);
+    /// ```
+    fn set_organization_name(&mut self, name: &str) -> Result<(), String> {
+        if name.trim().chars().count() < 3 {
+            Err("Organization name must be at least 3 characters long.".to_owned())
+        } else {
+            self.name = Some(name.trim().to_owned());
+            Ok(())
+        }
+    }
+    fn get_organization_address(&self) -> Option<Address> {
+        self.address.clone()
+    }
+    /// # Set organization address
+    /// Result<(), String>
+    /// Address of the farm or dealership, it must be complete.
+    /// ```rust
+    /// use core_lib::address::{Address, GeoLocation};
+    /// use core_lib::prelude::New;
+    /// use core_lib::organization::Organization;
+    /// use core_lib::organization::model::organization_v1::OrganizationV1;
+    /// let mut organization = OrganizationV1::new();
+    /// let address = Address::new("HU", "6725", "Szeged", "Tanya 12.")
+    ///     .unwrap()
+    ///     .with_location(GeoLocation::new(46.253, 20.1414).unwrap());
+    /// assert_eq!(organization.set_organization_address(address), Ok(()));
+    /// ```
+    fn set_organization_address(&mut self, address: Address) -> Result<(), String> {
+        address.validate()?;
+        self.address = Some(address);
+        Ok(())
+    }
+    fn get_members(&self) -> Vec<Member> {
+        self.members.clone()
+    }
+    fn get_member_role(&self, user_id: &str) -> Option<OrganizationRole> {
+        self.members
+            .iter()
+            .find(|member| member.user.get_id() == user_id)
+            .map(|member| member.role)
+    }
+    /// # Add member
+    /// Result<(), String>
+    /// A user can be a member only once.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::organization::*;
+    /// use core_lib::organization::model::organization_v1::OrganizationV1;
+    /// let mut organization = OrganizationV1::new();
+    /// assert_eq!(organization.add_member("demo_user", OrganizationRole::Owner), Ok(()));
+    /// assert_eq!(organization.get_member_role("demo_user"), Some(OrganizationRole::Owner));
+    /// ```
+    fn add_member(&mut self, user_id: &str, role: OrganizationRole) -> Result<(), String> {
+        if self.get_member_role(user_id).is_some() {
+            return Err(format!("User {} is already a member.", user_id));
+        }
+        self.members.push(Member {
+            user: Ref::new(user_id),
+            role,
+            joined: Utc::now(),
+        });
+        Ok(())
+    }
+    /// # Remove member
+    /// Result<(), String>
+    /// The last owner cannot be removed.
+    fn remove_member(&mut self, user_id: &str) -> Result<(), String> {
+        match self.get_member_role(user_id) {
+            None => Err(format!("User {} is not a member.", user_id)),
+            Some(OrganizationRole::Owner) if self.owner_count() == 1 => {
+                Err("The last owner cannot be removed.".to_owned())
+            }
+            Some(_) => {
+                self.members
+                    .retain(|member| member.user.get_id() != user_id);
+                Ok(())
+            }
+        }
+    }
+    /// # Set member role
+    /// Result<(), String>
+    /// The last owner cannot be demoted.
+    fn set_member_role(&mut self, user_id: &str, role: OrganizationRole) -> Result<(), String> {
+        match self.get_member_role(user_id) {
+            None => return Err(format!("User {} is not a member.", user_id)),
+            Some(OrganizationRole::Owner)
+                if role != OrganizationRole::Owner && self.owner_count() == 1 =>
+            {
+                return Err("The last owner cannot be demoted.".to_owned())
+            }
+            Some(_) => (),
+        }
+        for member in &mut self.members {
+            if member.user.get_id() == user_id {
+                member.role = role;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * StorageObject implementation for OrganizationV1
+ */
+impl storage::StorageObject for OrganizationV1 {
+    fn get_id(&self) -> Option<&str> {
+        self.id.as_ref().map(|id| id.as_ref())
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::relation::remove_with_relation;
+    use crate::user::User;
+
+    #[test]
+    fn test_organization_id() {
+        let mut organization = OrganizationV1::new();
+        assert_eq!(organization.get_organization_id(), None);
+        assert!(organization.set_organization_id("abc").is_err()); // should be err
+        assert!(organization.set_organization_id("Kovacs_Farm").is_ok()); // should be ok
+        assert!(organization.set_organization_id("other_farm").is_err()); // should be err
+        assert_eq!(
+            organization.get_organization_id(),
+            Some("kovacs_farm".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_organization_members() {
+        let mut organization = OrganizationV1::new();
+        organization
+            .add_member("owner_user", OrganizationRole::Owner)
+            .unwrap();
+        organization
+            .add_member("member_user", OrganizationRole::Member)
+            .unwrap();
+        assert!(organization
+            .add_member("member_user", OrganizationRole::Manager)
+            .is_err()); // should be err
+        assert_eq!(organization.get_members().len(), 2);
+
+        // Last owner is protected
+        assert!(organization.remove_member("owner_user").is_err());
+        assert!(organization
+            .set_member_role("owner_user", OrganizationRole::Member)
+            .is_err());
+        organization
+            .set_member_role("member_user", OrganizationRole::Owner)
+            .unwrap();
+        assert!(organization
+            .set_member_role("owner_user", OrganizationRole::Manager)
+            .is_ok());
+        assert!(organization.remove_member("owner_user").is_ok());
+        assert!(organization.remove_member("owner_user").is_err());
+        assert_eq!(
+            organization.get_member_role("member_user"),
+            Some(OrganizationRole::Owner)
+        );
+    }
+
+    #[test]
+    fn test_content_owner() {
+        let mut organizations =
+            storage::load_storage::<OrganizationV1>("../data/organization_content").unwrap();
+        let mut organization = OrganizationV1::new();
+        organization.set_organization_id("kovacs_farm").unwrap();
+        organization
+            .add_member("owner_user", OrganizationRole::Owner)
+            .unwrap();
+        organization
+            .add_member("member_user", OrganizationRole::Member)
+            .unwrap();
+        storage::add_to_storage(&mut organizations, organization).unwrap();
+        let owner = ContentOwner::Organization(Ref::new("kovacs_farm"));
+        assert!(owner.can_edit("owner_user", &organizations));
+        assert!(!owner.can_edit("member_user", &organizations));
+        assert!(!owner.can_edit("other_user", &organizations));
+        let owner = ContentOwner::Organization(Ref::new("missing_farm"));
+        assert!(!owner.can_edit("owner_user", &organizations));
+        organizations.remove();
+    }
+
+    #[test]
+    fn test_user_relation() {
+        let mut users = storage::load_storage::<UserV2>("../data/organization_users").unwrap();
+        let mut organizations =
+            storage::load_storage::<OrganizationV1>("../data/organization_relation").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("member_user").unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        let mut organization = OrganizationV1::new();
+        organization.set_organization_id("kovacs_farm").unwrap();
+        organization
+            .add_member("owner_user", OrganizationRole::Owner)
+            .unwrap();
+        organization
+            .add_member("member_user", OrganizationRole::Member)
+            .unwrap();
+        storage::add_to_storage(&mut organizations, organization).unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("owner_user").unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        // The last owner cannot be deleted while there are members
+        assert!(remove_with_relation(
+            &mut users,
+            "owner_user",
+            &mut organizations,
+            &OrganizationV1::user_relation(),
+        )
+        .is_err());
+        assert_eq!(organizations.data[0].get_members().len(), 2);
+        remove_with_relation(
+            &mut users,
+            "member_user",
+            &mut organizations,
+            &OrganizationV1::user_relation(),
+        )
+        .unwrap();
+        assert_eq!(organizations.data[0].get_members().len(), 1);
+        users.remove();
+        organizations.remove();
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::*;
+use std::collections::{BTreeMap, HashMap};
+
+/*
+ * Search DESIGN
+ *
+ * In memory inverted index over chosen fields of storage objects.
+ *  - token -> (object ID -> term frequency)
+ *  - tokens are lowercase and accent folded, so "Árvíztűrő" and
+ *    "arvizturo" are the same token
+ *  - query tokens must all match, either exactly or as a prefix
+ *  - hits are ranked by tf-idf, exact matches weigh more than prefix ones
+ */
+
+/// Weight of a prefix match compared to an exact token match
+const PREFIX_MATCH_WEIGHT: f64 = 0.5;
+
+/// # Fold accents
+/// Lowercase character with Hungarian (and some other common)
+/// accents removed.
+pub(crate) fn fold_char(ch: char) -> char {
+    match ch {
+        'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'ö' | 'ő' | 'õ' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' | 'ű' => 'u',
+        'ç' => 'c',
+        'ñ' => 'n',
+        _ => ch,
+    }
+}
+
+/// # Tokenize text
+/// Split text into lowercase, accent folded tokens.
+/// ```rust
+/// use core_lib::search::tokenize;
+/// assert_eq!(
+///     tokenize("Árvíztűrő tükörfúrógép, 2019."),
+///     vec!["arvizturo", "tukorfurogep", "2019"]
+/// );
+/// ```
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase().chars().map(fold_char).collect())
+        .collect()
+}
+
+/// # Search hit
+/// Object ID with its relevance score. Higher is better.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f64,
+}
+
+/// # Search index
+///
+/// Index chosen fields of storage objects. Fields are given as
+/// functions returning the text to index.
+///
+/// An index belongs to one storage. Objects changed by `add`, `update`
+/// and `remove` are reindexed at once. Call `refresh` before searching
+/// to catch other changes, e.g. a setter followed by `save()`: it
+/// rebuilds the index if the storage changed since it was indexed.
+///
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::search::SearchIndex;
+/// use core_lib::storage::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::User;
+/// let mut storage = load_storage::<UserV2>("../data/doc_search").unwrap();
+/// let mut index: SearchIndex<UserV2> = SearchIndex::new(vec![
+///     |user| user.get_user_name(),
+///     |user| user.get_user_email(),
+/// ]);
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// user.set_user_name("Kovács Péter").unwrap();
+/// index.add(&mut storage, user).unwrap();
+/// assert_eq!(index.search("kovacs")[0].id, "demo_user".to_owned());
+/// assert_eq!(index.search("pet")[0].id, "demo_user".to_owned());
+/// storage.remove();
+/// ```
+pub struct SearchIndex<T> {
+    fields: Vec<fn(&T) -> Option<String>>,
+    // token -> (object ID -> term frequency)
+    tokens: BTreeMap<String, HashMap<String, u32>>,
+    // object ID -> indexed tokens, needed to remove an object
+    objects: HashMap<String, Vec<String>>,
+    // storage revision the index is up to date with
+    revision: Option<u64>,
+}
+
+impl<T> SearchIndex<T>
+where
+    T: StorageObject,
+{
+    pub fn new(fields: Vec<fn(&T) -> Option<String>>) -> Self {
+        SearchIndex {
+            fields,
+            tokens: BTreeMap::new(),
+            objects: HashMap::new(),
+            revision: None,
+        }
+    }
+    /// # Index every object of a storage
+    pub fn index_storage(&mut self, storage: &Storage<T>) {
+        for object in &storage.data {
+            self.index_object(object);
+        }
+        self.revision = Some(get_revision(storage.get_path()));
+    }
+    /// # Rebuild index if the storage changed
+    /// Returns true if the index was rebuilt.
+    pub fn refresh(&mut self, storage: &Storage<T>) -> bool {
+        if self.revision == Some(get_revision(storage.get_path())) {
+            return false;
+        }
+        self.tokens.clear();
+        self.objects.clear();
+        self.index_storage(storage);
+        true
+    }
+    /// Keep the index up to date after its own change, if it was
+    /// up to date before
+    fn follow_revision(&mut self, path: &str, before: u64) {
+        if self.revision == Some(before) {
+            self.revision = Some(get_revision(path));
+        }
+    }
+    /// # Index object
+    /// If the object is already indexed, its old tokens are replaced.
+    /// Objects without ID are not indexed.
+    pub fn index_object(&mut self, object: &T) {
+        let id = match object.get_id() {
+            Some(id) => id.to_owned(),
+            None => return,
+        };
+        self.remove_object(&id);
+        let mut object_tokens: Vec<String> = Vec::new();
+        for field in &self.fields {
+            if let Some(text) = field(object) {
+                object_tokens.extend(tokenize(&text));
+            }
+        }
+        for token in &object_tokens {
+            *self
+                .tokens
+                .entry(token.clone())
+                .or_default()
+                .entry(id.clone())
+                .or_insert(0) += 1;
+        }
+        self.objects.insert(id, object_tokens);
+    }
+    /// # Remove object from index
+    pub fn remove_object(&mut self, id: &str) {
+        if let Some(object_tokens) = self.objects.remove(id) {
+            for token in object_tokens {
+                let is_empty = match self.tokens.get_mut(&token) {
+                    Some(postings) => {
+                        postings.remove(id);
+                        postings.is_empty()
+                    }
+                    None => false,
+                };
+                if is_empty {
+                    self.tokens.remove(&token);
+                }
+            }
+        }
+    }
+    /// # Number of indexed objects
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    /// # Add object to storage and index
+    pub fn add(&mut self, storage: &mut Storage<T>, object: T) -> Result<(), String> {
+        let id = match object.get_id() {
+            Some(id) => id.to_owned(),
+            None => return Err("Storage object without ID cannot be indexed.".to_owned()),
+        };
+        let before = get_revision(storage.get_path());
+        add_to_storage(storage, object)?;
+        if let Some(object) = get_by_id(storage, &id) {
+            self.index_object(object);
+        }
+        self.follow_revision(storage.get_path(), before);
+        Ok(())
+    }
+    /// # Save updated object and reindex it
+    pub fn update(&mut self, object: &T) -> Result<(), String> {
+        let path = object.get_path().unwrap_or_default().to_owned();
+        let before = get_revision(&path);
+        object.save()?;
+        self.index_object(object);
+        self.follow_revision(&path, before);
+        Ok(())
+    }
+    /// # Remove object from storage and index
+    pub fn remove(&mut self, storage: &mut Storage<T>, id: &str) -> Result<T, String> {
+        let before = get_revision(storage.get_path());
+        let object = remove_from_storage(storage, id)?;
+        self.remove_object(id);
+        self.follow_revision(storage.get_path(), before);
+        Ok(object)
+    }
+    /// # Search
+    /// Every query token must match an indexed token exactly or as a
+    /// prefix. Returns hits ordered by score, best first.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let query_tokens = tokenize(query);
+        if query_tokens.is_empty() {
+            return Vec::new();
+        }
+        let object_count = self.objects.len() as f64;
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        for (position, query_token) in query_tokens.iter().enumerate() {
+            // Best score per object for this query token
+            let mut token_scores: HashMap<&str, f64> = HashMap::new();
+            let matching = self
+                .tokens
+                .range(query_token.clone()..)
+                .take_while(|(token, _)| token.starts_with(query_token.as_str()));
+            for (token, postings) in matching {
+                let weight = if token == query_token {
+                    1.0
+                } else {
+                    PREFIX_MATCH_WEIGHT
+                };
+                let idf = (1.0 + object_count / postings.len() as f64).ln();
+                for (id, frequency) in postings {
+                    let score = weight * idf * f64::from(*frequency);
+                    let best = token_scores.entry(id.as_str()).or_insert(0.0);
+                    if score > *best {
+                        *best = score;
+                    }
+                }
+            }
+            if position == 0 {
+                for (id, score) in token_scores {
+                    scores.insert(id.to_owned(), score);
+                }
+            } else {
+                // Keep only objects matching every query token
+                scores.retain(|id, _| token_scores.contains_key(id.as_str()));
+                for (id, score) in scores.iter_mut() {
+                    *score += token_scores[id.as_str()];
+                }
+            }
+        }
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .map(|(id, score)| SearchHit { id, score })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        hits
+    }
+    /// # Search and return storage objects
+    pub fn search_storage<'a>(&self, storage: &'a Storage<T>, query: &str) -> Vec<&'a T> {
+        self.search(query)
+            .iter()
+            .filter_map(|hit| get_by_id(storage, &hit.id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::model::user_v2::UserV2;
+    use crate::user::User;
+
+    fn user(id: &str, name: &str) -> UserV2 {
+        let mut user = UserV2::new();
+        user.set_user_id(id).unwrap();
+        user.set_user_name(name).unwrap();
+        user
+    }
+
+    fn index() -> SearchIndex<UserV2> {
+        SearchIndex::new(vec![|user| user.get_user_name()])
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Árvíztűrő TÜKÖRFÚRÓGÉP"),
+            vec!["arvizturo", "tukorfurogep"]
+        );
+        assert_eq!(tokenize("  John-Deere 6R "), vec!["john", "deere", "6r"]);
+        assert_eq!(tokenize(" ,. ").len(), 0);
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let mut index = index();
+        index.index_object(&user("user_1", "Nagy Péter"));
+        index.index_object(&user("user_2", "Kis Péter"));
+        index.index_object(&user("user_3", "Nagy Nagy Anna"));
+        index.index_object(&user("user_4", "Petra Szabó"));
+        // Every query token must match
+        let hits = index.search("nagy péter");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, "user_1");
+        // More occurrence, higher score
+        assert_eq!(index.search("nagy")[0].id, "user_3");
+        // Prefix match
+        assert_eq!(index.search("pet").len(), 3);
+        // Exact match goes before prefix match
+        index.index_object(&user("user_5", "Pet Kovács"));
+        assert_eq!(index.search("pet")[0].id, "user_5");
+        assert_eq!(index.search("").len(), 0);
+        assert_eq!(index.search("missing").len(), 0);
+    }
+
+    #[test]
+    fn test_index_update_remove() {
+        let mut storage = load_storage::<UserV2>("../data/search_update").unwrap();
+        let mut index = index();
+        index
+            .add(&mut storage, user("user_1", "Nagy Péter"))
+            .unwrap();
+        index.add(&mut storage, user("user_2", "Kis Anna")).unwrap();
+        assert_eq!(index.len(), 2);
+
+        let item = get_mut_by_id(&mut storage, "user_1").unwrap();
+        item.set_user_name("Nagy Pál").unwrap();
+        index
+            .update(get_by_id(&storage, "user_1").unwrap())
+            .unwrap();
+        assert_eq!(index.search("peter").len(), 0);
+        assert_eq!(index.search("pal").len(), 1);
+
+        index.remove(&mut storage, "user_2").unwrap();
+        assert_eq!(index.search("anna").len(), 0);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.search_storage(&storage, "nagy").len(), 1);
+        storage.remove();
+    }
+
+    #[test]
+    fn test_index_refresh() {
+        let mut storage = load_storage::<UserV2>("../data/search_refresh").unwrap();
+        add_to_storage(&mut storage, user("user_1", "Nagy Péter")).unwrap();
+        let mut index = index();
+        index.index_storage(&storage);
+        assert!(!index.refresh(&storage));
+        index.add(&mut storage, user("user_2", "Kis Anna")).unwrap();
+        assert!(!index.refresh(&storage));
+
+        // Changed without the index
+        let item = get_mut_by_id(&mut storage, "user_1").unwrap();
+        item.set_user_name("Nagy Pál").unwrap();
+        item.save().unwrap();
+        remove_from_storage(&mut storage, "user_2").unwrap();
+        assert!(index.refresh(&storage));
+        assert_eq!(index.search("peter").len(), 0);
+        assert_eq!(index.search("pal").len(), 1);
+        assert_eq!(index.len(), 1);
+        storage.remove();
+    }
+}
@@ -15,11 +15,25 @@
 // You should have received a copy of the GNU General Public License
 // along with Project A.  If not, see <http://www.gnu.org/licenses/>.
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+pub mod compact;
+pub mod relation;
+pub mod transfer;
+
+/// Storage object file extension
+pub const STORAGE_EXTENSION: &str = "yml";
+
+/// Extension of the temp file used while saving a storage object
+pub const TEMP_EXTENSION: &str = "tmp";
 
 /**
  * Storage DESIGN
@@ -47,9 +61,114 @@ pub trait StorageObject {
 pub struct Storage<T> {
     path: &'static str,
     pub data: Vec<T>,
+    load_duration: Duration,
+}
+
+/// Save counters of a storage folder
+#[derive(Debug, Clone, Copy, Default)]
+struct SaveCounter {
+    saves: u64,
+    save_failures: u64,
+}
+
+/// Save counters by storage path, since the process started
+static SAVE_COUNTERS: Mutex<BTreeMap<String, SaveCounter>> = Mutex::new(BTreeMap::new());
+
+fn count_save(path: &str, success: bool) {
+    if let Ok(mut counters) = SAVE_COUNTERS.lock() {
+        let counter = counters.entry(path.to_owned()).or_default();
+        if success {
+            counter.saves += 1;
+        } else {
+            counter.save_failures += 1;
+        }
+    }
+}
+
+/// Revisions by storage path, changed on every save and remove
+static REVISIONS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+fn bump_revision(path: &str) {
+    if let Ok(mut revisions) = REVISIONS.lock() {
+        *revisions.entry(path.to_owned()).or_insert(0) += 1;
+    }
+}
+
+/// # Storage revision
+/// Changes whenever an object of the storage is saved or removed,
+/// e.g. to know whether a search index is out of date.
+pub fn get_revision(path: &str) -> u64 {
+    REVISIONS
+        .lock()
+        .ok()
+        .and_then(|revisions| revisions.get(path).copied())
+        .unwrap_or(0)
+}
+
+fn get_save_counter(path: &str) -> SaveCounter {
+    SAVE_COUNTERS
+        .lock()
+        .ok()
+        .and_then(|counters| counters.get(path).copied())
+        .unwrap_or_default()
+}
+
+/// # Storage statistics
+///
+/// Serializable snapshot about a storage, e.g. for an admin page.
+/// Saves and save failures are counted per storage folder since the
+/// process started, for every object saved by `save_storage_object`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StorageStats {
+    pub path: String,
+    pub object_count: usize,
+    pub total_bytes: u64,
+    pub load_duration_ms: u64,
+    pub saves: u64,
+    pub save_failures: u64,
+    pub last_modified: Option<DateTime<Utc>>,
 }
 
 impl<T> Storage<T> {
+    /// # Get storage path
+    /// Folder path where the storage objects are saved.
+    pub fn get_path(&self) -> &'static str {
+        self.path
+    }
+    /// # Storage statistics
+    ///
+    /// Object count, size on disk, load duration, save counters and
+    /// the last modification time of the storage files.
+    ///
+    /// ```rust
+    /// use core_lib::storage::*;
+    /// use serde::{Deserialize, Serialize};
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Animal {
+    ///     id: u32,
+    ///     name: String,
+    /// }
+    /// let storage = load_storage::<Animal>("../data/doc_stats").unwrap();
+    /// let stats = storage.stats().unwrap();
+    /// assert_eq!(stats.object_count, 0);
+    /// assert_eq!(stats.total_bytes, 0);
+    /// storage.remove();
+    /// ```
+    pub fn stats(&self) -> Result<StorageStats, String> {
+        let mut total_bytes: u64 = 0;
+        let mut last_modified: Option<SystemTime> = None;
+        collect_folder_stats(Path::new(self.path), &mut total_bytes, &mut last_modified)?;
+        let counter = get_save_counter(self.path);
+        Ok(StorageStats {
+            path: self.path.to_owned(),
+            object_count: self.data.len(),
+            total_bytes,
+            load_duration_ms: self.load_duration.as_millis() as u64,
+            saves: counter.saves,
+            save_failures: counter.save_failures,
+            last_modified: last_modified.map(DateTime::<Utc>::from),
+        })
+    }
     // TODO: Doc comment + usage!
     pub fn remove(&self) -> bool {
         if Path::new(&self.path).exists() {
@@ -89,9 +208,11 @@ pub fn load_storage<'a, T>(path: &'static str) -> Result<Storage<T>, String>
 where
     for<'de> T: Deserialize<'de> + 'a,
 {
+    let load_started = Instant::now();
     let mut storage: Storage<T> = Storage {
         path,
         data: Vec::new(),
+        load_duration: Duration::default(),
     };
     if !Path::new(path).exists() {
         match fs::create_dir_all(path) {
@@ -105,25 +226,33 @@ where
     } else {
         let files_to_read = fs::read_dir(path)
             .expect("Error during reading folder..")
-            .filter_map(|entry| {
-                entry.ok().and_then(|e| {
-                    e.path()
-                        .file_name()
-                        .and_then(|n| n.to_str().map(|s| String::from(s)))
-                })
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            // Only storage object files, skip folders, temp and other files
+            .filter(|file_path| {
+                file_path.is_file()
+                    && file_path.extension().and_then(|ext| ext.to_str()) == Some(STORAGE_EXTENSION)
             })
-            .collect::<Vec<String>>();
-        for file_name in files_to_read {
+            .collect::<Vec<PathBuf>>();
+        for file_path in files_to_read {
             let mut content_temp = String::new();
-            File::open(Path::new(&format!("{}/{}", path, &file_name)))
-                .unwrap()
-                .read_to_string(&mut content_temp)
-                .unwrap();
-            storage
-                .data
-                .push(deserialize_object::<T>(&content_temp).unwrap());
+            if File::open(&file_path)
+                .and_then(|mut file| file.read_to_string(&mut content_temp))
+                .is_err()
+            {
+                return Err(format!("Error while reading storage file {:?}.", file_path));
+            }
+            match deserialize_object::<T>(&content_temp) {
+                Ok(object) => storage.data.push(object),
+                Err(_) => {
+                    return Err(format!(
+                        "Error while deserializing storage file {:?}.",
+                        file_path
+                    ))
+                }
+            }
         }
     }
+    storage.load_duration = load_started.elapsed();
     Ok(storage)
 }
 
@@ -238,6 +367,118 @@ where
     }
 }
 
+/// # Get StorageObject by ID
+///
+/// Find a storage object by its ID, returns None if there is no
+/// object with the given ID.
+///
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::storage::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::User;
+/// let mut storage = load_storage::<UserV2>("../data/doc_get_by_id").unwrap();
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// add_to_storage(&mut storage, user).unwrap();
+/// assert_eq!(get_by_id(&storage, "demo_user").is_some(), true);
+/// assert_eq!(get_by_id(&storage, "other_user").is_none(), true);
+/// storage.remove();
+/// ```
+pub fn get_by_id<'a, T>(storage: &'a Storage<T>, id: &str) -> Option<&'a T>
+where
+    T: StorageObject,
+{
+    storage.data.iter().find(|item| item.get_id() == Some(id))
+}
+
+/// # Get mutable StorageObject by ID
+///
+/// Same as `get_by_id`, but returns a mutable reference.
+/// Do not forget to call `save()` after modifying the object.
+pub fn get_mut_by_id<'a, T>(storage: &'a mut Storage<T>, id: &str) -> Option<&'a mut T>
+where
+    T: StorageObject,
+{
+    storage
+        .data
+        .iter_mut()
+        .find(|item| item.get_id() == Some(id))
+}
+
+/// # Remove StorageObject from Storage
+///
+/// Remove the object with the given ID from the storage, and delete
+/// its file from the storage folder. Returns the removed object.
+///
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::storage::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::User;
+/// let mut storage = load_storage::<UserV2>("../data/doc_remove").unwrap();
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// add_to_storage(&mut storage, user).unwrap();
+/// let removed = remove_from_storage(&mut storage, "demo_user").unwrap();
+/// assert_eq!(removed.get_user_id(), Some("demo_user".to_owned()));
+/// assert_eq!(storage.data.len(), 0);
+/// storage.remove();
+/// ```
+pub fn remove_from_storage<T>(storage: &mut Storage<T>, id: &str) -> Result<T, String>
+where
+    T: StorageObject,
+{
+    let index = match storage
+        .data
+        .iter()
+        .position(|item| item.get_id() == Some(id))
+    {
+        Some(index) => index,
+        None => return Err(format!("Storage object {} not found.", id)),
+    };
+    let file_path = object_file_path(storage.path, id);
+    if Path::new(&file_path).exists() && fs::remove_file(&file_path).is_err() {
+        return Err(format!("Error while removing storage object file {}.", id));
+    }
+    bump_revision(storage.path);
+    Ok(storage.data.remove(index))
+}
+
+/// Sum file sizes and find the latest modification time in a folder
+fn collect_folder_stats(
+    path: &Path,
+    total_bytes: &mut u64,
+    last_modified: &mut Option<SystemTime>,
+) -> Result<(), String> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Err(format!("Error while reading storage folder {:?}.", path)),
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            collect_folder_stats(&entry.path(), total_bytes, last_modified)?;
+            continue;
+        }
+        *total_bytes += metadata.len();
+        if let Ok(modified) = metadata.modified() {
+            if last_modified.map_or(true, |last| modified > last) {
+                *last_modified = Some(modified);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Storage object file path from storage path and object ID
+fn object_file_path(path: &str, id: &str) -> String {
+    format!("{}/{}.{}", path, id, STORAGE_EXTENSION)
+}
+
 /// # Serialize object<T> -> Result<String, String>
 /// Serialize a given object to String
 /// ```rust
@@ -284,24 +525,34 @@ where
     }
 }
 
-/**
- * Save storage into object!
- * TODO: Doc comments + example code
- */
+/// # Save storage object
+///
+/// Save object into its storage folder as `{id}.yml`.
+/// Content is written into a temp file first, and then renamed,
+/// so a failed save never leaves a half written object file.
+/// Orphaned temp files can be removed by `compact::compact_storage`.
 pub fn save_storage_object<T>(storage_object: &T) -> Result<(), String>
 where
     T: StorageObject + Serialize,
 {
-    // TODO: Proper error handling please!
-    File::create(&format!(
-        "{}/{}.yml",
-        storage_object.get_path().unwrap(),
-        storage_object.get_id().unwrap(),
-    ))
-    .unwrap()
-    .write(serialize_object::<T>(storage_object).unwrap().as_bytes())
-    .unwrap();
-    Ok(())
+    let (path, id) = match (storage_object.get_path(), storage_object.get_id()) {
+        (Some(path), Some(id)) => (path, id),
+        _ => return Err("Storage object path or ID is not set.".to_owned()),
+    };
+    let content = serialize_object::<T>(storage_object)?;
+    let file_path = object_file_path(path, id);
+    let temp_file_path = format!("{}.{}", file_path, TEMP_EXTENSION);
+    let result = File::create(&temp_file_path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .and_then(|_| fs::rename(&temp_file_path, &file_path));
+    count_save(path, result.is_ok());
+    if result.is_ok() {
+        bump_revision(path);
+    }
+    match result {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Error while saving storage object {}.", id)),
+    }
 }
 
 #[cfg(test)]
@@ -407,6 +658,51 @@ mod tests {
         storage.remove();
     }
 
+    #[test]
+    fn test_storage_stats() {
+        #[derive(Serialize, Deserialize)]
+        struct Example {
+            id: String,
+            path: String,
+        }
+        impl StorageObject for Example {
+            fn get_id(&self) -> Option<&str> {
+                Some(&self.id)
+            }
+            fn save(&self) -> Result<(), String> {
+                save_storage_object(self)
+            }
+            fn reload(&mut self) -> Result<(), String> {
+                Ok(())
+            }
+            fn get_path(&self) -> Option<&str> {
+                Some(&self.path)
+            }
+            fn set_path(&mut self, path: &str) -> Result<(), String> {
+                self.path = path.to_owned();
+                Ok(())
+            }
+        }
+        let mut storage = load_storage::<Example>("data/stats").unwrap();
+        assert_eq!(storage.stats().unwrap().last_modified, None);
+        for id in &["1", "2"] {
+            let example = Example {
+                id: id.to_string(),
+                path: "".to_owned(),
+            };
+            add_to_storage(&mut storage, example).unwrap();
+        }
+        // Direct saves are counted too
+        storage.data[0].save().unwrap();
+        let stats = storage.stats().unwrap();
+        assert_eq!(stats.object_count, 2);
+        assert_eq!(stats.saves, 3);
+        assert_eq!(stats.save_failures, 0);
+        assert!(stats.total_bytes > 0);
+        assert!(stats.last_modified.is_some());
+        storage.remove();
+    }
+
     #[test]
     fn test_storage_load_save() {
         #[derive(Serialize, Deserialize)]
@@ -0,0 +1,200 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::*;
+
+/// # Compaction report
+/// File and folder paths touched by the compaction.
+/// Invalid files cannot be deserialized, they are kept for manual check.
+/// Other files are neither storage objects nor temp files, and kept.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct CompactionReport {
+    pub removed_temp_files: Vec<String>,
+    pub removed_directories: Vec<String>,
+    pub removed_mismatched_files: Vec<String>,
+    pub invalid_files: Vec<String>,
+    pub other_files: Vec<String>,
+}
+
+/// # Is temp file?
+/// Our own save temp files, and common editor backup and swap files.
+/// `*.bak` files are user made backups, they are not temp files.
+/// ```rust
+/// use core_lib::storage::compact::is_temp_file;
+/// assert!(is_temp_file("user_1.yml.tmp"));
+/// assert!(is_temp_file("user_1.yml~"));
+/// assert!(is_temp_file(".user_1.yml.swp"));
+/// assert!(!is_temp_file("user_1.yml"));
+/// assert!(!is_temp_file("user_1.yml.bak"));
+/// ```
+pub fn is_temp_file(file_name: &str) -> bool {
+    file_name.ends_with(&format!(".{}", TEMP_EXTENSION))
+        || file_name.ends_with('~')
+        || file_name.ends_with(".swp")
+        || file_name.ends_with(".swo")
+        || file_name.starts_with(".#")
+}
+
+/// # Compact storage folder
+///
+/// Clean up a storage folder:
+///  1) remove orphaned temp files (e.g. from an interrupted save)
+///  2) remove storage object files whose ID does not match the file
+///     name, as they are stale copies of an object
+///  3) remove empty sub directories
+///
+/// Run it before `load_storage`, as objects from removed files are
+/// not removed from an already loaded storage.
+///
+/// ```rust
+/// use core_lib::storage::compact::compact_storage;
+/// use core_lib::user::model::user_v2::UserV2;
+/// let report = compact_storage::<UserV2>("../data/doc_compact").unwrap();
+/// assert_eq!(report.removed_temp_files.len(), 0);
+/// ```
+pub fn compact_storage<T>(path: &str) -> Result<CompactionReport, String>
+where
+    for<'de> T: StorageObject + Deserialize<'de>,
+{
+    let mut report = CompactionReport::default();
+    if Path::new(path).exists() {
+        compact_folder::<T>(Path::new(path), true, &mut report)?;
+    }
+    Ok(report)
+}
+
+fn compact_folder<T>(
+    folder: &Path,
+    is_root: bool,
+    report: &mut CompactionReport,
+) -> Result<(), String>
+where
+    for<'de> T: StorageObject + Deserialize<'de>,
+{
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect::<Vec<PathBuf>>(),
+        Err(_) => return Err(format!("Error while reading storage folder {:?}.", folder)),
+    };
+    for entry in entries {
+        let display = entry.to_string_lossy().to_string();
+        if entry.is_dir() {
+            compact_folder::<T>(&entry, false, report)?;
+            continue;
+        }
+        let file_name = entry
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if is_temp_file(&file_name) {
+            remove_file(&entry)?;
+            report.removed_temp_files.push(display);
+            continue;
+        }
+        if entry.extension().and_then(|ext| ext.to_str()) != Some(STORAGE_EXTENSION) {
+            report.other_files.push(display);
+            continue;
+        }
+        let object = fs::read_to_string(&entry)
+            .map_err(|_| ())
+            .and_then(|content| deserialize_object::<T>(&content).map_err(|_| ()));
+        match object {
+            Ok(object) => {
+                let file_stem = entry.file_stem().and_then(|stem| stem.to_str());
+                if object.get_id() != file_stem {
+                    remove_file(&entry)?;
+                    report.removed_mismatched_files.push(display);
+                }
+            }
+            Err(_) => report.invalid_files.push(display),
+        }
+    }
+    if !is_root && is_empty_folder(folder) {
+        if fs::remove_dir(folder).is_err() {
+            return Err(format!("Error while removing empty folder {:?}.", folder));
+        }
+        report
+            .removed_directories
+            .push(folder.to_string_lossy().to_string());
+    }
+    Ok(())
+}
+
+fn remove_file(file_path: &Path) -> Result<(), String> {
+    match fs::remove_file(file_path) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Error while removing file {:?}.", file_path)),
+    }
+}
+
+fn is_empty_folder(folder: &Path) -> bool {
+    match fs::read_dir(folder) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::model::user_v2::UserV2;
+    use crate::user::User;
+
+    static TESTDIR_PATH: &str = "../data/compact";
+
+    fn write(file_name: &str, content: &str) {
+        File::create(format!("{}/{}", TESTDIR_PATH, file_name))
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_compact_storage() {
+        let mut storage = load_storage::<UserV2>(TESTDIR_PATH).unwrap();
+        for id in &["user_1", "user_2"] {
+            let mut user = UserV2::new();
+            user.set_user_id(id).unwrap();
+            add_to_storage(&mut storage, user).unwrap();
+        }
+        drop(storage);
+        let timestamps = "created: 2019-01-01T00:00:00Z\nupdated: 2019-01-01T00:00:00Z";
+        write("user_3.yml.tmp", &format!("---\nid: user_3\n{}", timestamps));
+        write("user_1.yml~", &format!("---\nid: user_1\n{}", timestamps));
+        write("user_4.yml", &format!("---\nid: user_2\n{}", timestamps));
+        write("broken.yml", "id: [unclosed");
+        write("readme.txt", "Users");
+        fs::create_dir_all(format!("{}/shard/empty", TESTDIR_PATH)).unwrap();
+
+        // Load skips temp and other files, but fails on the broken one
+        assert!(load_storage::<UserV2>(TESTDIR_PATH).is_err());
+
+        let report = compact_storage::<UserV2>(TESTDIR_PATH).unwrap();
+        assert_eq!(report.removed_temp_files.len(), 2);
+        assert_eq!(report.removed_mismatched_files.len(), 1);
+        assert_eq!(report.removed_directories.len(), 2);
+        assert_eq!(report.invalid_files.len(), 1);
+        assert_eq!(report.other_files.len(), 1);
+
+        fs::remove_file(format!("{}/broken.yml", TESTDIR_PATH)).unwrap();
+        let storage = load_storage::<UserV2>(TESTDIR_PATH).unwrap();
+        assert_eq!(storage.data.len(), 2);
+        storage.remove();
+    }
+}
@@ -0,0 +1,490 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// # Ref<T>
+///
+/// Typed reference to a StorageObject by its ID.
+/// It is saved as a plain ID string, so a `Ref<UserV2>` field
+/// looks like `owner: demo_user` in the YAML file.
+pub struct Ref<T> {
+    id: String,
+    object_type: PhantomData<fn() -> T>,
+}
+
+impl<T> Ref<T> {
+    /// # New reference
+    /// ```rust
+    /// use core_lib::storage::relation::Ref;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let owner: Ref<UserV2> = Ref::new("demo_user");
+    /// assert_eq!(owner.get_id(), "demo_user");
+    /// ```
+    pub fn new(id: &str) -> Self {
+        Ref {
+            id: id.to_owned(),
+            object_type: PhantomData,
+        }
+    }
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl<T> Ref<T>
+where
+    T: StorageObject,
+{
+    /// # Reference from object
+    /// None if the object has no ID yet.
+    pub fn from_object(object: &T) -> Option<Self> {
+        object.get_id().map(|id| Ref::new(id))
+    }
+    /// # Resolve reference
+    /// Find the referenced object in the given storage.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::storage::*;
+    /// use core_lib::storage::relation::Ref;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// use core_lib::user::User;
+    /// let mut storage = load_storage::<UserV2>("../data/doc_ref_resolve").unwrap();
+    /// let mut user = UserV2::new();
+    /// user.set_user_id("demo_user").unwrap();
+    /// add_to_storage(&mut storage, user).unwrap();
+    /// let owner: Ref<UserV2> = Ref::new("demo_user");
+    /// assert!(owner.resolve(&storage).is_some());
+    /// storage.remove();
+    /// ```
+    pub fn resolve<'a>(&self, storage: &'a Storage<T>) -> Option<&'a T> {
+        get_by_id(storage, &self.id)
+    }
+    pub fn resolve_mut<'a>(&self, storage: &'a mut Storage<T>) -> Option<&'a mut T> {
+        get_mut_by_id(storage, &self.id)
+    }
+}
+
+// Manual implementations, as derive would require T to implement them.
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        Ref::new(&self.id)
+    }
+}
+
+impl<T> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Ref<T> {}
+
+impl<T> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ref({})", self.id)
+    }
+}
+
+impl<T> Serialize for Ref<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.id)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Ref<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(|id| Ref::new(&id))
+    }
+}
+
+/// # What to do with referencing objects on delete
+///
+///  - Restrict: deleting a referenced object is an error
+///  - Cascade: referencing objects are deleted as well
+///  - Nullify: the reference is removed from the referencing objects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnDelete {
+    Restrict,
+    Cascade,
+    Nullify,
+}
+
+/// # Relation between two storages
+///
+/// Declares that objects of storage S reference objects of storage T,
+/// and what should happen with S objects once a referenced T object is
+/// deleted. `get_refs` returns the references an S object holds to T,
+/// `nullify` removes a given reference from an S object.
+///
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::storage::*;
+/// use core_lib::storage::relation::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::User;
+/// use serde::{Deserialize, Serialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct Comment {
+///     id: String,
+///     path: String,
+///     author: Option<Ref<UserV2>>,
+/// }
+/// impl StorageObject for Comment {
+///     fn get_id(&self) -> Option<&str> {
+///         Some(&self.id)
+///     }
+///     fn save(&self) -> Result<(), String> {
+///         save_storage_object(self)
+///     }
+///     fn reload(&mut self) -> Result<(), String> {
+///         Ok(())
+///     }
+///     fn get_path(&self) -> Option<&str> {
+///         Some(&self.path)
+///     }
+///     fn set_path(&mut self, path: &str) -> Result<(), String> {
+///         self.path = path.to_owned();
+///         Ok(())
+///     }
+/// }
+/// let mut users = load_storage::<UserV2>("../data/doc_relation_users").unwrap();
+/// let mut comments = load_storage::<Comment>("../data/doc_relation_comments").unwrap();
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// add_to_storage(&mut users, user).unwrap();
+/// let comment = Comment {
+///     id: "1".to_owned(),
+///     path: "".to_owned(),
+///     author: Some(Ref::new("demo_user")),
+/// };
+/// add_to_storage(&mut comments, comment).unwrap();
+/// let relation: Relation<Comment, UserV2> = Relation::nullify(
+///     |comment| comment.author.iter().collect(),
+///     |comment, _| comment.author = None,
+/// );
+/// remove_with_relation(&mut users, "demo_user", &mut comments, &relation).unwrap();
+/// assert!(comments.data[0].author.is_none());
+/// users.remove();
+/// comments.remove();
+/// ```
+pub struct Relation<S, T> {
+    on_delete: OnDelete,
+    get_refs: fn(&S) -> Vec<&Ref<T>>,
+    nullify: Option<fn(&mut S, &Ref<T>)>,
+    check: Option<DeleteCheck<S, T>>,
+}
+
+/// Check of an S object before deleting the T object it refers to
+pub type DeleteCheck<S, T> = fn(&S, &Ref<T>) -> Result<(), String>;
+
+impl<S, T> Relation<S, T>
+where
+    S: StorageObject,
+{
+    /// # Restrict relation
+    /// Referenced T object cannot be deleted while S objects refer to it.
+    pub fn restrict(get_refs: fn(&S) -> Vec<&Ref<T>>) -> Self {
+        Relation {
+            on_delete: OnDelete::Restrict,
+            get_refs,
+            nullify: None,
+            check: None,
+        }
+    }
+    /// # Cascade relation
+    /// S objects referring to a deleted T object are deleted too.
+    pub fn cascade(get_refs: fn(&S) -> Vec<&Ref<T>>) -> Self {
+        Relation {
+            on_delete: OnDelete::Cascade,
+            get_refs,
+            nullify: None,
+            check: None,
+        }
+    }
+    /// # Nullify relation
+    /// References to a deleted T object are removed from S objects.
+    pub fn nullify(get_refs: fn(&S) -> Vec<&Ref<T>>, nullify: fn(&mut S, &Ref<T>)) -> Self {
+        Relation {
+            on_delete: OnDelete::Nullify,
+            get_refs,
+            nullify: Some(nullify),
+            check: None,
+        }
+    }
+    /// # Restrict some deletes
+    /// `check` is called with every S object referring to the deleted
+    /// T object, an error denies the delete, whatever the relation is.
+    pub fn with_check(mut self, check: DeleteCheck<S, T>) -> Self {
+        self.check = Some(check);
+        self
+    }
+    pub fn get_on_delete(&self) -> OnDelete {
+        self.on_delete
+    }
+    /// # IDs of S objects referring to the given T ID
+    pub fn referencing_ids(&self, source: &Storage<S>, target_id: &str) -> Vec<String> {
+        source
+            .data
+            .iter()
+            .filter(|item| {
+                (self.get_refs)(item)
+                    .iter()
+                    .any(|reference| reference.get_id() == target_id)
+            })
+            .filter_map(|item| item.get_id().map(|id| id.to_owned()))
+            .collect()
+    }
+    /// # Check whether the T object can be deleted
+    /// Only a Restrict relation, or the check of the relation can deny
+    /// a delete.
+    pub fn check_delete(&self, source: &Storage<S>, target_id: &str) -> Result<(), String> {
+        if let Some(check) = self.check {
+            let target_ref = Ref::new(target_id);
+            for id in self.referencing_ids(source, target_id) {
+                if let Some(item) = get_by_id(source, &id) {
+                    check(item, &target_ref)?;
+                }
+            }
+        }
+        if self.on_delete != OnDelete::Restrict {
+            return Ok(());
+        }
+        let referencing = self.referencing_ids(source, target_id);
+        if referencing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Object {} cannot be deleted, it is referenced by: {}",
+                target_id,
+                referencing.join(", ")
+            ))
+        }
+    }
+    /// # Apply delete rule on the S storage
+    /// Call it before removing the T object itself.
+    pub fn apply_delete(&self, source: &mut Storage<S>, target_id: &str) -> Result<(), String> {
+        self.check_delete(source, target_id)?;
+        let referencing = self.referencing_ids(source, target_id);
+        match self.on_delete {
+            OnDelete::Restrict => (),
+            OnDelete::Cascade => {
+                for id in referencing {
+                    remove_from_storage(source, &id)?;
+                }
+            }
+            OnDelete::Nullify => {
+                let nullify = match self.nullify {
+                    Some(nullify) => nullify,
+                    None => return Err("Nullify relation without nullify function.".to_owned()),
+                };
+                let target_ref = Ref::new(target_id);
+                for id in referencing {
+                    if let Some(item) = get_mut_by_id(source, &id) {
+                        nullify(item, &target_ref);
+                        item.save()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// # Remove object with referential integrity
+///
+/// Apply the relation on the referencing storage, then remove the
+/// object from its own storage. If the relation restricts the delete,
+/// nothing is changed and an error is returned.
+pub fn remove_with_relation<S, T>(
+    target: &mut Storage<T>,
+    target_id: &str,
+    source: &mut Storage<S>,
+    relation: &Relation<S, T>,
+) -> Result<T, String>
+where
+    S: StorageObject,
+    T: StorageObject,
+{
+    if get_by_id(target, target_id).is_none() {
+        return Err(format!("Storage object {} not found.", target_id));
+    }
+    relation.apply_delete(source, target_id)?;
+    remove_from_storage(target, target_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Machine {
+        id: String,
+        path: String,
+        manufacturer: Option<Ref<Manufacturer>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Manufacturer {
+        id: String,
+        path: String,
+    }
+
+    impl StorageObject for Machine {
+        fn get_id(&self) -> Option<&str> {
+            Some(&self.id)
+        }
+        fn save(&self) -> Result<(), String> {
+            save_storage_object(self)
+        }
+        fn reload(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+        fn get_path(&self) -> Option<&str> {
+            Some(&self.path)
+        }
+        fn set_path(&mut self, path: &str) -> Result<(), String> {
+            self.path = path.to_owned();
+            Ok(())
+        }
+    }
+
+    impl StorageObject for Manufacturer {
+        fn get_id(&self) -> Option<&str> {
+            Some(&self.id)
+        }
+        fn save(&self) -> Result<(), String> {
+            save_storage_object(self)
+        }
+        fn reload(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+        fn get_path(&self) -> Option<&str> {
+            Some(&self.path)
+        }
+        fn set_path(&mut self, path: &str) -> Result<(), String> {
+            self.path = path.to_owned();
+            Ok(())
+        }
+    }
+
+    fn machine_refs(machine: &Machine) -> Vec<&Ref<Manufacturer>> {
+        machine.manufacturer.iter().collect()
+    }
+
+    fn init(
+        manufacturers_path: &'static str,
+        machines_path: &'static str,
+    ) -> (Storage<Manufacturer>, Storage<Machine>) {
+        let mut manufacturers = load_storage::<Manufacturer>(manufacturers_path).unwrap();
+        let mut machines = load_storage::<Machine>(machines_path).unwrap();
+        for id in &["claas", "fendt"] {
+            add_to_storage(
+                &mut manufacturers,
+                Manufacturer {
+                    id: id.to_string(),
+                    path: "".to_owned(),
+                },
+            )
+            .unwrap();
+        }
+        for (id, manufacturer) in &[("lexion", "claas"), ("jaguar", "claas"), ("vario", "fendt")] {
+            add_to_storage(
+                &mut machines,
+                Machine {
+                    id: id.to_string(),
+                    path: "".to_owned(),
+                    manufacturer: Some(Ref::new(manufacturer)),
+                },
+            )
+            .unwrap();
+        }
+        (manufacturers, machines)
+    }
+
+    #[test]
+    fn test_ref_serialize() {
+        let machine = Machine {
+            id: "lexion".to_owned(),
+            path: "".to_owned(),
+            manufacturer: Some(Ref::new("claas")),
+        };
+        let serialized = serialize_object(&machine).unwrap();
+        assert!(serialized.contains("manufacturer: claas"));
+        let machine: Machine = deserialize_object(&serialized).unwrap();
+        assert_eq!(machine.manufacturer, Some(Ref::new("claas")));
+    }
+
+    #[test]
+    fn test_relation_restrict() {
+        let (mut manufacturers, mut machines) =
+            init("../data/relation_restrict_a", "../data/relation_restrict_b");
+        let relation = Relation::restrict(machine_refs);
+        assert!(
+            remove_with_relation(&mut manufacturers, "claas", &mut machines, &relation).is_err()
+        );
+        assert_eq!(manufacturers.data.len(), 2);
+        assert_eq!(machines.data.len(), 3);
+        manufacturers.remove();
+        machines.remove();
+    }
+
+    #[test]
+    fn test_relation_cascade() {
+        let (mut manufacturers, mut machines) =
+            init("../data/relation_cascade_a", "../data/relation_cascade_b");
+        let relation = Relation::cascade(machine_refs);
+        remove_with_relation(&mut manufacturers, "claas", &mut machines, &relation).unwrap();
+        assert_eq!(manufacturers.data.len(), 1);
+        assert_eq!(machines.data.len(), 1);
+        drop(machines);
+        // Check files are removed as well
+        let machines = load_storage::<Machine>("../data/relation_cascade_b").unwrap();
+        assert_eq!(machines.data.len(), 1);
+        manufacturers.remove();
+        machines.remove();
+    }
+
+    #[test]
+    fn test_relation_nullify() {
+        let (mut manufacturers, mut machines) =
+            init("../data/relation_nullify_a", "../data/relation_nullify_b");
+        let relation = Relation::nullify(machine_refs, |machine, _| machine.manufacturer = None);
+        remove_with_relation(&mut manufacturers, "claas", &mut machines, &relation).unwrap();
+        assert_eq!(machines.data.len(), 3);
+        let lexion = get_by_id(&machines, "lexion").unwrap();
+        assert!(lexion.manufacturer.is_none());
+        let vario = get_by_id(&machines, "vario").unwrap();
+        assert!(vario
+            .manufacturer
+            .as_ref()
+            .unwrap()
+            .resolve(&manufacturers)
+            .is_some());
+        manufacturers.remove();
+        machines.remove();
+    }
+}
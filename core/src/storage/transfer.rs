@@ -0,0 +1,511 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::*;
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+
+/*
+ * Import / export DESIGN
+ *
+ *  - JSON lines: one serialized storage object per line
+ *  - Exported objects implement Exportable, its secret fields
+ *    (e.g. password hashes) are never exported.
+ *  - CSV: chosen object fields as columns, with custom header names
+ *  - Import goes through the normal add path (add_to_storage), or
+ *    replaces and saves an existing object with the same ID in
+ *    upsert mode. Imports never hold secret fields, so an upsert
+ *    keeps them from the existing object.
+ *  - Row errors do not stop the import, they are collected into the
+ *    ImportReport.
+ */
+
+/// # CSV column mapping
+/// Object field name and its CSV header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub field: String,
+    pub header: String,
+}
+
+impl Column {
+    pub fn new(field: &str, header: &str) -> Self {
+        Column {
+            field: field.to_owned(),
+            header: header.to_owned(),
+        }
+    }
+}
+
+/// # Exportable object
+/// Serialized fields listed in `SECRET_FIELDS` are left out of every
+/// export, and cannot be chosen as CSV columns.
+pub trait Exportable: Serialize {
+    const SECRET_FIELDS: &'static [&'static str] = &[];
+}
+
+/// Serialize object without its secret fields
+fn export_value<T: Exportable>(object: &T) -> Result<Value, String> {
+    let mut value = match serde_json::to_value(object) {
+        Ok(value) => value,
+        Err(_) => return Err("Error while serializing object.".to_owned()),
+    };
+    if let Some(fields) = value.as_object_mut() {
+        for field in T::SECRET_FIELDS {
+            fields.remove(*field);
+        }
+    }
+    Ok(value)
+}
+
+/// # Import mode
+///  - Create: existing object ID is a row error
+///  - Upsert: existing object is replaced, but its secret fields are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    Create,
+    Upsert,
+}
+
+/// # Import row error
+/// Row numbers start from 1, the CSV header is not counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowError {
+    pub row: usize,
+    pub message: String,
+}
+
+/// # Import report
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// # Export storage as JSON lines
+/// Returns the number of exported objects.
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::storage::*;
+/// use core_lib::storage::transfer::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::User;
+/// let mut storage = load_storage::<UserV2>("../data/doc_export_json").unwrap();
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// user.set_password("SEcretPassWord1234789").unwrap();
+/// add_to_storage(&mut storage, user).unwrap();
+/// let mut output: Vec<u8> = Vec::new();
+/// assert_eq!(export_json_lines(&storage, &mut output), Ok(1));
+/// assert!(!String::from_utf8(output).unwrap().contains("password_hash"));
+/// storage.remove();
+/// ```
+pub fn export_json_lines<T, W>(storage: &Storage<T>, mut writer: W) -> Result<usize, String>
+where
+    T: Exportable,
+    W: Write,
+{
+    for object in &storage.data {
+        let line = match serde_json::to_string(&export_value(object)?) {
+            Ok(line) => line,
+            Err(_) => return Err("Error while serializing object to JSON.".to_owned()),
+        };
+        if writeln!(writer, "{}", line).is_err() {
+            return Err("Error while writing export.".to_owned());
+        }
+    }
+    Ok(storage.data.len())
+}
+
+/// # Export storage as CSV
+/// Only the given columns are exported. Missing or null fields are
+/// empty cells, lists and sub-objects are written as JSON.
+/// Secret fields cannot be exported. Text a spreadsheet would read as
+/// a formula is prefixed with `'`.
+pub fn export_csv<T, W>(
+    storage: &Storage<T>,
+    writer: W,
+    columns: &[Column],
+) -> Result<usize, String>
+where
+    T: Exportable,
+    W: Write,
+{
+    if let Some(column) = columns
+        .iter()
+        .find(|column| T::SECRET_FIELDS.contains(&column.field.as_str()))
+    {
+        return Err(format!("Field {} cannot be exported.", column.field));
+    }
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    if csv_writer
+        .write_record(columns.iter().map(|column| column.header.as_str()))
+        .is_err()
+    {
+        return Err("Error while writing CSV header.".to_owned());
+    }
+    for object in &storage.data {
+        let value = export_value(object)?;
+        let record: Vec<String> = columns
+            .iter()
+            .map(|column| cell_from_value(value.get(&column.field)))
+            .collect();
+        if csv_writer.write_record(&record).is_err() {
+            return Err("Error while writing CSV record.".to_owned());
+        }
+    }
+    if csv_writer.flush().is_err() {
+        return Err("Error while writing export.".to_owned());
+    }
+    Ok(storage.data.len())
+}
+
+/// # Import JSON lines into storage
+/// Each non empty line is deserialized, validated and then created or
+/// upserted. Invalid rows are reported and skipped.
+/// ```rust
+/// use core_lib::storage::*;
+/// use core_lib::storage::transfer::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// let mut storage = load_storage::<UserV2>("../data/doc_import_json").unwrap();
+/// let input = concat!(
+///     "{\"id\":\"demo_user\",\"name\":\"Demo User\",",
+///     "\"created\":\"2019-01-01T00:00:00Z\",\"updated\":\"2019-01-01T00:00:00Z\"}\n",
+///     "not json\n",
+/// );
+/// let report = import_json_lines(&mut storage, input.as_bytes(), ImportMode::Create, |_| Ok(()))
+///     .unwrap();
+/// assert_eq!(report.created, 1);
+/// assert_eq!(report.errors[0].row, 2);
+/// storage.remove();
+/// ```
+pub fn import_json_lines<T, R, F>(
+    storage: &mut Storage<T>,
+    reader: R,
+    mode: ImportMode,
+    validate: F,
+) -> Result<ImportReport, String>
+where
+    for<'de> T: StorageObject + Exportable + Deserialize<'de>,
+    R: Read,
+    F: Fn(&T) -> Result<(), String>,
+{
+    let mut report = ImportReport::default();
+    for (index, line) in BufReader::new(reader).lines().enumerate() {
+        let row = index + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return Err("Error while reading import.".to_owned()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str::<T>(&line) {
+            Ok(object) => import_object(storage, object, mode, &validate, &mut report),
+            Err(error) => Err(format!("Invalid JSON object: {}", error)),
+        };
+        if let Err(message) = result {
+            report.errors.push(RowError { row, message });
+        }
+    }
+    Ok(report)
+}
+
+/// # Import CSV into storage
+/// Columns map the CSV headers to object fields, other CSV columns are
+/// ignored. Empty cells are read as None for optional fields.
+pub fn import_csv<T, R, F>(
+    storage: &mut Storage<T>,
+    reader: R,
+    columns: &[Column],
+    mode: ImportMode,
+    validate: F,
+) -> Result<ImportReport, String>
+where
+    for<'de> T: StorageObject + Exportable + Deserialize<'de>,
+    R: Read,
+    F: Fn(&T) -> Result<(), String>,
+{
+    let mut report = ImportReport::default();
+    let mut csv_reader = csv::Reader::from_reader(reader);
+    let headers = match csv_reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(_) => return Err("Error while reading CSV header.".to_owned()),
+    };
+    // Rename mapped CSV headers to object field names,
+    // unmapped columns get a name no field can have.
+    let mut field_headers: Vec<String> = (0..headers.len())
+        .map(|index| format!("\u{0}column_{}", index))
+        .collect();
+    for column in columns {
+        match headers.iter().position(|header| header == column.header) {
+            Some(index) => field_headers[index] = column.field.clone(),
+            None => return Err(format!("CSV column {} is missing.", column.header)),
+        }
+    }
+    let field_headers = csv::StringRecord::from(field_headers);
+    for (index, record) in csv_reader.records().enumerate() {
+        let row = index + 1;
+        let result = match record {
+            Ok(record) => match record.deserialize::<T>(Some(&field_headers)) {
+                Ok(object) => import_object(storage, object, mode, &validate, &mut report),
+                Err(error) => Err(format!("Invalid CSV record: {}", error)),
+            },
+            Err(error) => Err(format!("Invalid CSV record: {}", error)),
+        };
+        if let Err(message) = result {
+            report.errors.push(RowError { row, message });
+        }
+    }
+    Ok(report)
+}
+
+/// Validate and create or update a single imported object
+fn import_object<T, F>(
+    storage: &mut Storage<T>,
+    mut object: T,
+    mode: ImportMode,
+    validate: &F,
+    report: &mut ImportReport,
+) -> Result<(), String>
+where
+    for<'de> T: StorageObject + Exportable + Deserialize<'de>,
+    F: Fn(&T) -> Result<(), String>,
+{
+    let id = match object.get_id() {
+        Some(id) => id.to_owned(),
+        None => return Err("Object ID is missing.".to_owned()),
+    };
+    validate(&object)?;
+    match storage
+        .data
+        .iter()
+        .position(|item| item.get_id() == Some(&id))
+    {
+        Some(_) if mode == ImportMode::Create => Err(format!("Object {} already exists.", id)),
+        Some(index) => {
+            object = keep_secret_fields(&storage.data[index], object)?;
+            object.set_path(storage.path)?;
+            object.save()?;
+            storage.data[index] = object;
+            report.updated += 1;
+            Ok(())
+        }
+        None => {
+            add_to_storage(storage, object)?;
+            report.created += 1;
+            Ok(())
+        }
+    }
+}
+
+/// Copy the secret fields of the existing object into the imported one
+fn keep_secret_fields<T>(existing: &T, imported: T) -> Result<T, String>
+where
+    for<'de> T: Exportable + Deserialize<'de>,
+{
+    if T::SECRET_FIELDS.is_empty() {
+        return Ok(imported);
+    }
+    let (existing, mut value) = match (
+        serde_json::to_value(existing),
+        serde_json::to_value(imported),
+    ) {
+        (Ok(existing), Ok(value)) => (existing, value),
+        _ => return Err("Error while serializing object.".to_owned()),
+    };
+    if let Some(fields) = value.as_object_mut() {
+        for field in T::SECRET_FIELDS {
+            match existing.get(*field) {
+                Some(secret) => fields.insert((*field).to_owned(), secret.clone()),
+                None => fields.remove(*field),
+            };
+        }
+    }
+    match serde_json::from_value(value) {
+        Ok(object) => Ok(object),
+        Err(_) => Err("Error while deserializing object.".to_owned()),
+    }
+}
+
+/// Cell beginnings spreadsheets read as a formula
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// CSV cell text from a serialized field value
+fn cell_from_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "".to_owned(),
+        Some(Value::String(text)) if text.starts_with(FORMULA_PREFIXES) => format!("'{}", text),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Machine {
+        id: String,
+        #[serde(skip)]
+        path: String,
+        name: String,
+        power: Option<u32>,
+        #[serde(default)]
+        key: Option<String>,
+    }
+
+    impl Machine {
+        fn new(id: &str, name: &str, power: Option<u32>) -> Self {
+            Machine {
+                id: id.to_owned(),
+                path: "".to_owned(),
+                name: name.to_owned(),
+                power,
+                key: None,
+            }
+        }
+    }
+
+    impl Exportable for Machine {
+        const SECRET_FIELDS: &'static [&'static str] = &["key"];
+    }
+
+    impl StorageObject for Machine {
+        fn get_id(&self) -> Option<&str> {
+            Some(&self.id)
+        }
+        fn save(&self) -> Result<(), String> {
+            save_storage_object(self)
+        }
+        fn reload(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+        fn get_path(&self) -> Option<&str> {
+            Some(&self.path)
+        }
+        fn set_path(&mut self, path: &str) -> Result<(), String> {
+            self.path = path.to_owned();
+            Ok(())
+        }
+    }
+
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("id", "ID"),
+            Column::new("name", "Name"),
+            Column::new("power", "Power (HP)"),
+        ]
+    }
+
+    fn validate(machine: &Machine) -> Result<(), String> {
+        if machine.name.is_empty() {
+            Err("Name is required.".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines_roundtrip() {
+        let mut source = load_storage::<Machine>("../data/transfer_json_a").unwrap();
+        add_to_storage(&mut source, Machine::new("1", "Lexion 770", Some(530))).unwrap();
+        add_to_storage(&mut source, Machine::new("2", "Vario 1050", None)).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(export_json_lines(&source, &mut output), Ok(2));
+
+        let mut target = load_storage::<Machine>("../data/transfer_json_b").unwrap();
+        let report =
+            import_json_lines(&mut target, output.as_slice(), ImportMode::Create, validate)
+                .unwrap();
+        assert_eq!(report.created, 2);
+        assert_eq!(report.errors.len(), 0);
+        assert_eq!(get_by_id(&target, "1").unwrap().power, Some(530));
+
+        // Same IDs again: errors in create mode, updates in upsert mode
+        let report =
+            import_json_lines(&mut target, output.as_slice(), ImportMode::Create, validate)
+                .unwrap();
+        assert_eq!(report.errors.len(), 2);
+        get_mut_by_id(&mut target, "1").unwrap().key = Some("S3CR3T".to_owned());
+        let report =
+            import_json_lines(&mut target, output.as_slice(), ImportMode::Upsert, validate)
+                .unwrap();
+        assert_eq!(report.updated, 2);
+        assert_eq!(target.data.len(), 2);
+        // Secret fields are not exported, the stored ones are kept
+        assert_eq!(
+            get_by_id(&target, "1").unwrap().key,
+            Some("S3CR3T".to_owned())
+        );
+        source.remove();
+        target.remove();
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let mut storage = load_storage::<Machine>("../data/transfer_csv_export").unwrap();
+        add_to_storage(&mut storage, Machine::new("1", "Lexion 770", Some(530))).unwrap();
+        add_to_storage(&mut storage, Machine::new("2", "Vario, 1050", None)).unwrap();
+        add_to_storage(&mut storage, Machine::new("3", "=HYPERLINK(\"x\")", None)).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        assert_eq!(export_csv(&storage, &mut output, &columns()), Ok(3));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ID,Name,Power (HP)\n1,Lexion 770,530\n2,\"Vario, 1050\",\n\
+             3,\"'=HYPERLINK(\"\"x\"\")\",\n"
+        );
+        storage.remove();
+    }
+
+    #[test]
+    fn test_csv_import() {
+        let mut storage = load_storage::<Machine>("../data/transfer_csv_import").unwrap();
+        let input = "Name,ID,Power (HP),Comment\n\
+                     Lexion 770,1,530,\n\
+                     Vario 1050,2,,Nice\n\
+                     ,3,100,\n\
+                     Xerion,4,lot,\n";
+        let report = import_csv(
+            &mut storage,
+            input.as_bytes(),
+            &columns(),
+            ImportMode::Create,
+            validate,
+        )
+        .unwrap();
+        assert_eq!(report.created, 2);
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.errors[0].row, 3);
+        assert_eq!(report.errors[1].row, 4);
+        assert_eq!(get_by_id(&storage, "1").unwrap().power, Some(530));
+        assert_eq!(get_by_id(&storage, "2").unwrap().power, None);
+        // Missing mapped column
+        let input = "ID,Name\n1,Lexion\n";
+        assert!(import_csv(
+            &mut storage,
+            input.as_bytes(),
+            &columns(),
+            ImportMode::Upsert,
+            validate
+        )
+        .is_err());
+        storage.remove();
+    }
+}
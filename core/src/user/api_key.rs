@@ -0,0 +1,284 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::relation::Ref;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::model::user_v2::UserV2;
+use crate::user::role::{self, Permission};
+use crate::user::status::AccountStatus;
+use crate::user::token::{generate_token, hash_token};
+use crate::user::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Every API key starts with this, so leaked keys are easy to find
+pub const API_KEY_PREFIX: &str = "pak_";
+/// Length of the key start kept to recognize the key in lists
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+/// # API key
+///
+/// Personal, non-interactive credential of a user, sent as
+/// `Authorization: Bearer <key>`. The ID is the hash of the key, the
+/// key itself is only shown once, on creation.
+/// Scopes limit the key to some of the user permissions, a key
+/// without scopes has every permission of the user.
+#[derive(Serialize, Deserialize)]
+pub struct ApiKey {
+    id: String,
+    path: Option<String>,
+    user: Ref<UserV2>,
+    name: String,
+    prefix: String,
+    scopes: Vec<Permission>,
+    created: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    last_used: Option<DateTime<Utc>>,
+    revoked: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn get_key_id(&self) -> &str {
+        &self.id
+    }
+    pub fn get_user(&self) -> &Ref<UserV2> {
+        &self.user
+    }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    /// Start of the key, e.g. to show it in a list
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+    pub fn get_scopes(&self) -> &[Permission] {
+        &self.scopes
+    }
+    pub fn get_created(&self) -> DateTime<Utc> {
+        self.created
+    }
+    pub fn get_expires(&self) -> Option<DateTime<Utc>> {
+        self.expires
+    }
+    pub fn get_last_used(&self) -> Option<DateTime<Utc>> {
+        self.last_used
+    }
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.is_some()
+    }
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| Utc::now() > expires)
+    }
+    pub fn is_valid(&self) -> bool {
+        !self.is_revoked() && !self.is_expired()
+    }
+    /// The key scopes allow the permission. The user must have
+    /// it as well, see `has_api_permission`.
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.scopes.is_empty() || self.scopes.contains(&permission)
+    }
+}
+
+impl StorageObject for ApiKey {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Create API key
+/// Returns the new key. Show it to the user once, only its hash
+/// is stored.
+/// ```rust
+/// use core_lib::storage::load_storage;
+/// use core_lib::prelude::New;
+/// use core_lib::user::api_key::*;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::role::Permission;
+/// use core_lib::user::User;
+/// let mut keys = load_storage::<ApiKey>("../data/doc_api_keys").unwrap();
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// let key = create_api_key(&mut keys, &user, "backup script", &[Permission::ViewContent], None)
+///     .unwrap();
+/// assert_eq!(key.starts_with(API_KEY_PREFIX), true);
+/// assert_eq!(get_user_api_keys(&keys, "demo_user").len(), 1);
+/// keys.remove();
+/// ```
+pub fn create_api_key<T: User>(
+    keys: &mut Storage<ApiKey>,
+    user: &T,
+    name: &str,
+    scopes: &[Permission],
+    expires: Option<DateTime<Utc>>,
+) -> Result<String, String> {
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err("User ID is not set.".to_owned()),
+    };
+    if name.trim().is_empty() {
+        return Err("API key name is empty.".to_owned());
+    }
+    if expires.is_some_and(|expires| expires <= Utc::now()) {
+        return Err("API key expiry must be in the future.".to_owned());
+    }
+    let key = format!("{}{}", API_KEY_PREFIX, generate_token()?);
+    let mut unique_scopes = Vec::new();
+    for scope in scopes {
+        if !unique_scopes.contains(scope) {
+            unique_scopes.push(*scope);
+        }
+    }
+    storage::add_to_storage(
+        keys,
+        ApiKey {
+            id: hash_token(&key),
+            path: None,
+            user: Ref::new(&user_id),
+            name: name.trim().to_owned(),
+            prefix: key.chars().take(DISPLAY_PREFIX_LENGTH).collect(),
+            scopes: unique_scopes,
+            created: Utc::now(),
+            expires,
+            last_used: None,
+            revoked: None,
+        },
+    )?;
+    Ok(key)
+}
+
+/// # API keys of a user
+/// Revoked and expired keys included.
+pub fn get_user_api_keys<'a>(keys: &'a Storage<ApiKey>, user_id: &str) -> Vec<&'a ApiKey> {
+    keys.data
+        .iter()
+        .filter(|key| key.user.get_id() == user_id)
+        .collect()
+}
+
+/// # Revoke API key
+/// Users can revoke only their own keys. The key is kept, to
+/// show it in the list as revoked.
+pub fn revoke_api_key(
+    keys: &mut Storage<ApiKey>,
+    user_id: &str,
+    key_id: &str,
+) -> Result<(), String> {
+    match storage::get_mut_by_id(keys, key_id) {
+        Some(key) if key.user.get_id() == user_id => {
+            if key.revoked.is_none() {
+                key.revoked = Some(Utc::now());
+                key.save()?;
+            }
+            Ok(())
+        }
+        _ => Err("API key not found.".to_owned()),
+    }
+}
+
+/// # Authenticate API key
+/// Check the key, and record its usage. The key must be valid, and
+/// its user must be active. Returns the key.
+pub fn authenticate_api_key<'a, T>(
+    keys: &'a mut Storage<ApiKey>,
+    users: &Storage<T>,
+    key: &str,
+) -> Result<&'a ApiKey, String>
+where
+    T: User + StorageObject,
+{
+    let api_key = match storage::get_mut_by_id(keys, &hash_token(key.trim())) {
+        Some(api_key) if api_key.is_valid() => api_key,
+        _ => return Err("Invalid API key.".to_owned()),
+    };
+    match storage::get_by_id(users, api_key.user.get_id()) {
+        Some(user) if user.get_user_status() == AccountStatus::Active => (),
+        _ => return Err("Invalid API key.".to_owned()),
+    }
+    api_key.last_used = Some(Utc::now());
+    api_key.save()?;
+    Ok(api_key)
+}
+
+/// # API key permission
+/// Both the user and the key scopes must allow it.
+pub fn has_api_permission<T: User>(user: &T, key: &ApiKey, permission: Permission) -> bool {
+    key.allows(permission) && role::has_permission(user, permission)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::role::Role;
+    use chrono::Duration;
+
+    #[test]
+    fn test_api_key() {
+        let mut users = storage::load_storage::<UserV2>("../data/api_key_users").unwrap();
+        let mut keys = storage::load_storage::<ApiKey>("../data/api_key_keys").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        user.add_user_role(Role::Member).unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        let user = &users.data[0];
+
+        assert!(create_api_key(&mut keys, user, " ", &[], None).is_err());
+        let past = Utc::now() - Duration::days(1);
+        assert!(create_api_key(&mut keys, user, "old", &[], Some(past)).is_err());
+        let key = create_api_key(
+            &mut keys,
+            user,
+            "read only",
+            &[Permission::ViewContent],
+            None,
+        )
+        .unwrap();
+        // Stored hashed
+        assert!(storage::get_by_id(&keys, &key).is_none());
+        assert!(authenticate_api_key(&mut keys, &users, "pak_wrong").is_err());
+        let api_key = authenticate_api_key(&mut keys, &users, &key).unwrap();
+        assert!(api_key.get_last_used().is_some());
+        assert!(has_api_permission(user, api_key, Permission::ViewContent));
+        assert!(!has_api_permission(
+            user,
+            api_key,
+            Permission::CreateContent
+        ));
+        let key_id = api_key.get_key_id().to_owned();
+
+        assert!(revoke_api_key(&mut keys, "other_user", &key_id).is_err());
+        revoke_api_key(&mut keys, "demo_user", &key_id).unwrap();
+        assert!(authenticate_api_key(&mut keys, &users, &key).is_err());
+        assert_eq!(get_user_api_keys(&keys, "demo_user").len(), 1);
+        users.remove();
+        keys.remove();
+    }
+}
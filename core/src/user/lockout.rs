@@ -0,0 +1,289 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::token::hash_token;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Failed logins of an account before it is locked out
+pub const ACCOUNT_LOCKOUT_THRESHOLD: u32 = 5;
+/// Failed logins from one IP address before it is locked out.
+/// Higher than the account limit, many users can share an address.
+pub const IP_LOCKOUT_THRESHOLD: u32 = 20;
+/// Failed logins without backoff, so a typo does not slow down
+pub const FREE_ATTEMPTS: u32 = 2;
+/// First lockout time, it doubles with every further failure
+pub const LOCKOUT_MINUTES: i64 = 15;
+/// Longest lockout time
+pub const MAX_LOCKOUT_HOURS: i64 = 24;
+/// Failures are forgotten after this many hours without a new one
+pub const FAILURE_WINDOW_HOURS: i64 = 24;
+
+/// # Failed login attempts
+///
+/// Failure counter of one account or IP address. The key is
+/// `account:<email>` or `ip:<address>`, the ID is its hash, so any key
+/// is a safe file name. Before the lockout failures block the key
+/// for an exponentially growing backoff time.
+#[derive(Serialize, Deserialize)]
+pub struct LoginAttempts {
+    id: String,
+    path: Option<String>,
+    key: String,
+    failures: u32,
+    last_failure: DateTime<Utc>,
+    blocked_until: DateTime<Utc>,
+    locked_out: bool,
+}
+
+impl LoginAttempts {
+    pub fn get_key(&self) -> &str {
+        &self.key
+    }
+    pub fn get_failures(&self) -> u32 {
+        self.failures
+    }
+    pub fn get_blocked_until(&self) -> DateTime<Utc> {
+        self.blocked_until
+    }
+    /// Locked out, not only waiting for a backoff
+    pub fn is_locked_out(&self) -> bool {
+        self.locked_out && self.is_blocked()
+    }
+    pub fn is_blocked(&self) -> bool {
+        Utc::now() < self.blocked_until
+    }
+}
+
+impl StorageObject for LoginAttempts {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Account key
+/// Accounts are tracked by email, so unknown email addresses are
+/// throttled the same way as registered ones.
+pub fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+/// # IP address key
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip.trim())
+}
+
+/// Wait time after a failure. After the free attempts it doubles from
+/// 1 second before the lockout, and from `LOCKOUT_MINUTES` after it.
+fn backoff(failures: u32, threshold: u32) -> Duration {
+    let max = Duration::hours(MAX_LOCKOUT_HOURS);
+    let wait = if failures <= FREE_ATTEMPTS && failures < threshold {
+        Duration::zero()
+    } else if failures < threshold {
+        Duration::seconds(1 << (failures - FREE_ATTEMPTS - 1).min(20))
+    } else {
+        Duration::minutes(LOCKOUT_MINUTES) * (1 << (failures - threshold).min(20))
+    };
+    if wait > max {
+        max
+    } else {
+        wait
+    }
+}
+
+/// # Check login attempts
+/// Returns the end of the block, if the key is blocked.
+/// ```rust
+/// use core_lib::storage::load_storage;
+/// use core_lib::user::lockout::*;
+/// let mut attempts = load_storage::<LoginAttempts>("../data/doc_login_attempts").unwrap();
+/// let key = account_key("demo@user.com");
+/// assert_eq!(check_login_attempts(&attempts, &key), Ok(()));
+/// for _ in 0..ACCOUNT_LOCKOUT_THRESHOLD {
+///     record_failed_login(&mut attempts, &key, ACCOUNT_LOCKOUT_THRESHOLD).unwrap();
+/// }
+/// assert_eq!(check_login_attempts(&attempts, &key).is_err(), true);
+/// attempts.remove();
+/// ```
+pub fn check_login_attempts(
+    attempts: &Storage<LoginAttempts>,
+    key: &str,
+) -> Result<(), DateTime<Utc>> {
+    match storage::get_by_id(attempts, &hash_token(key)) {
+        Some(item) if item.is_blocked() => Err(item.blocked_until),
+        _ => Ok(()),
+    }
+}
+
+/// # Is locked out
+/// The key is locked out, not only waiting for a backoff.
+pub fn is_locked_out(attempts: &Storage<LoginAttempts>, key: &str) -> bool {
+    storage::get_by_id(attempts, &hash_token(key)).is_some_and(|item| item.is_locked_out())
+}
+
+/// # Record failed login
+/// Count the failure, and block the key for the backoff time.
+/// Returns true, if the key got locked out by this failure, e.g. to
+/// notify the account owner.
+pub fn record_failed_login(
+    attempts: &mut Storage<LoginAttempts>,
+    key: &str,
+    threshold: u32,
+) -> Result<bool, String> {
+    let id = hash_token(key);
+    let now = Utc::now();
+    if storage::get_by_id(attempts, &id).is_none() {
+        storage::add_to_storage(
+            attempts,
+            LoginAttempts {
+                id: id.clone(),
+                path: None,
+                key: key.to_owned(),
+                failures: 0,
+                last_failure: now,
+                blocked_until: now,
+                locked_out: false,
+            },
+        )?;
+    }
+    let item = match storage::get_mut_by_id(attempts, &id) {
+        Some(item) => item,
+        None => return Err("Login attempts not found.".to_owned()),
+    };
+    if !item.is_blocked() && now - item.last_failure > Duration::hours(FAILURE_WINDOW_HOURS) {
+        item.failures = 0;
+        item.locked_out = false;
+    }
+    item.failures += 1;
+    item.last_failure = now;
+    item.blocked_until = now + backoff(item.failures, threshold);
+    let locked_out = item.failures >= threshold && !item.locked_out;
+    if item.failures >= threshold {
+        item.locked_out = true;
+    }
+    item.save()?;
+    Ok(locked_out)
+}
+
+/// # Clear failed logins
+/// After a successful login, or by an admin to unlock an account.
+pub fn clear_failed_logins(attempts: &mut Storage<LoginAttempts>, key: &str) -> Result<(), String> {
+    let id = hash_token(key);
+    if storage::get_by_id(attempts, &id).is_some() {
+        storage::remove_from_storage(attempts, &id)?;
+    }
+    Ok(())
+}
+
+/// # Unlock account
+/// Admin action, removes the lockout of the account email.
+pub fn unlock_account(attempts: &mut Storage<LoginAttempts>, email: &str) -> Result<(), String> {
+    clear_failed_logins(attempts, &account_key(email))
+}
+
+/// # Locked out keys
+/// Account and IP keys currently locked out, e.g. for an admin page.
+pub fn get_locked_out(attempts: &Storage<LoginAttempts>) -> Vec<&LoginAttempts> {
+    attempts
+        .data
+        .iter()
+        .filter(|item| item.is_locked_out())
+        .collect()
+}
+
+/// # Send lockout email
+/// Tell the account owner about the lockout, so they know someone
+/// tried to guess their password.
+pub fn send_lockout_email(to: &str, name: &str, until: DateTime<Utc>) -> Result<(), String> {
+    email::send_email_from_env(
+        to,
+        name,
+        "Your account is temporarily locked",
+        &format!(
+            "Hi {}! There were too many failed login attempts on your account, \
+             so it is locked until {}.\n\
+             If it was not you, please consider changing your password.",
+            name,
+            until.format("%Y-%m-%d %H:%M UTC")
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1, 5), Duration::zero());
+        assert_eq!(backoff(3, 5), Duration::seconds(1));
+        assert_eq!(backoff(4, 5), Duration::seconds(2));
+        assert_eq!(backoff(5, 5), Duration::minutes(LOCKOUT_MINUTES));
+        assert_eq!(backoff(6, 5), Duration::minutes(2 * LOCKOUT_MINUTES));
+        assert_eq!(backoff(50, 5), Duration::hours(MAX_LOCKOUT_HOURS));
+    }
+
+    #[test]
+    fn test_lockout() {
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/lockout_attempts").unwrap();
+        let key = account_key(" Demo@User.com");
+        assert_eq!(key, "account:demo@user.com");
+        for _ in 1..ACCOUNT_LOCKOUT_THRESHOLD {
+            assert_eq!(
+                record_failed_login(&mut attempts, &key, ACCOUNT_LOCKOUT_THRESHOLD),
+                Ok(false)
+            );
+        }
+        assert!(get_locked_out(&attempts).is_empty());
+        assert_eq!(
+            record_failed_login(&mut attempts, &key, ACCOUNT_LOCKOUT_THRESHOLD),
+            Ok(true)
+        );
+        // Only the first lockout is reported
+        assert_eq!(
+            record_failed_login(&mut attempts, &key, ACCOUNT_LOCKOUT_THRESHOLD),
+            Ok(false)
+        );
+        assert_eq!(get_locked_out(&attempts).len(), 1);
+        assert!(is_locked_out(&attempts, &key));
+        assert!(check_login_attempts(&attempts, &key).is_err());
+        // Other keys are not affected
+        assert_eq!(
+            check_login_attempts(&attempts, &ip_key("127.0.0.1")),
+            Ok(())
+        );
+        unlock_account(&mut attempts, "demo@user.com").unwrap();
+        assert_eq!(check_login_attempts(&attempts, &key), Ok(()));
+        attempts.remove();
+    }
+}
@@ -15,28 +15,429 @@
 // You should have received a copy of the GNU General Public License
 // along with Project A.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::prelude::*;
-use crate::user::model::user_v1::UserV1;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::lockout::{self, LoginAttempts};
+use crate::user::password::hasher::HashAlgorithm;
+use crate::user::password::verify_password_from_hash;
+use crate::user::security_log::{
+    record_security_event, SecurityEvent, SecurityEventKind, SecurityEventOutcome,
+};
+use crate::user::session::{ClientInfo, Session};
+use crate::user::signed_token::{validate_signed_token, RevokedToken, TokenKeys};
+use crate::user::status::AccountStatus;
+use crate::user::token::{generate_token, hash_token};
+use crate::user::totp::verify_second_factor;
 use crate::user::User;
+use chrono::{DateTime, Duration, Utc};
+use std::fmt;
+
+/// The second login step must be finished within this many minutes
+pub const SECOND_FACTOR_VALID_MINUTES: i64 = 5;
+
+/// Argon2id hash with the default parameters, verified when there is
+/// no usable hash, so unknown emails take as long as wrong passwords.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$aNjIFqr6mYxx/LM3oSHviw$9aNPhFrn4Buw2K8bzbioWmGTfdkXcg6Z+P29954SSfw";
+
+/// # Login error
+/// Wrong email and wrong password are the same error, so the login
+/// form does not tell which email addresses are registered.
+/// Account status errors are only returned with valid credentials.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginError {
+    InvalidCredentials,
+    AccountPendingVerification,
+    AccountSuspended,
+    AccountDeleted,
+    InvalidSecondFactor,
+    /// Blocked by brute-force protection until the given time
+    TooManyAttempts(DateTime<Utc>),
+    Internal(String),
+}
+
+/// # Login outcome
+/// `LoggedIn` holds the access token. `SecondFactorRequired` holds a
+/// login challenge token, pass it with the TOTP or recovery code to
+/// `login_second_factor`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginOutcome {
+    LoggedIn(String),
+    SecondFactorRequired(String),
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::InvalidCredentials => write!(f, "Wrong email or password."),
+            LoginError::AccountPendingVerification => {
+                write!(f, "Please verify your email address before logging in.")
+            }
+            LoginError::AccountSuspended => write!(f, "This account is suspended."),
+            LoginError::AccountDeleted => write!(f, "This account is deleted."),
+            LoginError::InvalidSecondFactor => write!(f, "Wrong authentication code."),
+            LoginError::TooManyAttempts(until) => write!(
+                f,
+                "Too many failed login attempts, please try again after {}.",
+                until.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            LoginError::Internal(msg) => write!(f, "Login error: {}", msg),
+        }
+    }
+}
+
+impl From<String> for LoginError {
+    fn from(msg: String) -> Self {
+        LoginError::Internal(msg)
+    }
+}
 
 /// # Login function
-/// Logically manage login process. Once the user found, and the password
-/// is valid, then we return the user id, or an error message.
+/// Logically manage login process. Once the user found, the password
+/// is valid and the account is active, then we record the login time,
+/// upgrade the password hash if it is made by an old hasher,
+/// create a new session, and return its access token, or a login error.
+/// If the user has two-factor authentication enabled, then it returns
+/// a login challenge instead, and the login is finished by
+/// `login_second_factor`.
+///
+/// The client info is stored with the session.
+/// Failed logins are counted per account and per client IP address,
+/// see the `lockout` module. A blocked account or address gets
+/// `TooManyAttempts` without checking the password, and the account
+/// owner gets an email when the account is locked out.
+///
+/// Every login attempt and lockout is recorded in the security log.
 /// ```rust
-/// use core_lib::user::login::login;
-/// let login = login("demo@user.com", "demo_password");
+/// use core_lib::storage::load_storage;
+/// use core_lib::user::lockout::LoginAttempts;
+/// use core_lib::user::login::{login, LoginError};
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::security_log::SecurityEvent;
+/// use core_lib::user::session::{ClientInfo, Session};
+/// let mut users = load_storage::<UserV2>("../data/doc_login_users").unwrap();
+/// let mut sessions = load_storage::<Session>("../data/doc_login_sessions").unwrap();
+/// let mut attempts = load_storage::<LoginAttempts>("../data/doc_login_attempts").unwrap();
+/// let mut log = load_storage::<SecurityEvent>("../data/doc_login_log").unwrap();
+/// let login = login(
+///     &mut users,
+///     &mut sessions,
+///     &mut attempts,
+///     &mut log,
+///     "demo@user.com",
+///     "demo_password",
+///     &ClientInfo {
+///         ip: Some("127.0.0.1".to_owned()),
+///         user_agent: None,
+///     },
+/// );
+/// assert_eq!(login, Err(LoginError::InvalidCredentials));
+/// assert_eq!(log.data.len(), 1);
+/// users.remove();
+/// sessions.remove();
+/// attempts.remove();
+/// log.remove();
 /// ```
-pub fn login<'a>(email: &'a str, password: &'a str) -> Result<&'a str, String> {
-    Err("Not implemented".to_owned())
+pub fn login<T>(
+    users: &mut Storage<T>,
+    sessions: &mut Storage<Session>,
+    attempts: &mut Storage<LoginAttempts>,
+    log: &mut Storage<SecurityEvent>,
+    email: &str,
+    password: &str,
+    client: &ClientInfo,
+) -> Result<LoginOutcome, LoginError>
+where
+    T: User + StorageObject,
+{
+    let user_id = users
+        .data
+        .iter()
+        .find(|user| {
+            user.get_user_email()
+                .is_some_and(|user_email| user_email.eq_ignore_ascii_case(email.trim()))
+        })
+        .and_then(|user| user.get_user_id());
+    let account_key = lockout::account_key(email);
+    let was_locked_out = lockout::is_locked_out(attempts, &account_key);
+    let result = authenticate(users, sessions, attempts, email, password, client);
+    let (outcome, detail) = match &result {
+        Ok(LoginOutcome::LoggedIn(_)) => (SecurityEventOutcome::Success, None),
+        Ok(LoginOutcome::SecondFactorRequired(_)) => (
+            SecurityEventOutcome::Success,
+            Some("Second factor required.".to_owned()),
+        ),
+        // Keep the tried email address, if it is not registered
+        Err(error) if user_id.is_none() => (
+            SecurityEventOutcome::Failure,
+            Some(format!("{} ({})", error, email.trim())),
+        ),
+        Err(error) => (SecurityEventOutcome::Failure, Some(error.to_string())),
+    };
+    log_event(
+        log,
+        SecurityEventKind::Login,
+        outcome,
+        user_id.as_deref(),
+        client,
+        detail,
+    );
+    if !was_locked_out && lockout::is_locked_out(attempts, &account_key) {
+        let detail = Some(email.trim().to_owned());
+        let kind = SecurityEventKind::AccountLocked;
+        log_event(
+            log,
+            kind,
+            SecurityEventOutcome::Success,
+            user_id.as_deref(),
+            client,
+            detail,
+        );
+    }
+    result
+}
+
+/// Record a login event. The session is already created by then, so
+/// the login result does not depend on the log, write errors are
+/// ignored.
+fn log_event(
+    log: &mut Storage<SecurityEvent>,
+    kind: SecurityEventKind,
+    outcome: SecurityEventOutcome,
+    user_id: Option<&str>,
+    client: &ClientInfo,
+    detail: Option<String>,
+) {
+    let mut event = SecurityEvent::new(kind, outcome, user_id, client);
+    if let Some(detail) = detail {
+        event = event.with_detail(&detail);
+    }
+    let _ = record_security_event(log, event);
+}
+
+fn authenticate<T>(
+    users: &mut Storage<T>,
+    sessions: &mut Storage<Session>,
+    attempts: &mut Storage<LoginAttempts>,
+    email: &str,
+    password: &str,
+    client: &ClientInfo,
+) -> Result<LoginOutcome, LoginError>
+where
+    T: User + StorageObject,
+{
+    let account_key = lockout::account_key(email);
+    let ip_key = client.ip.as_deref().map(lockout::ip_key);
+    lockout::check_login_attempts(attempts, &account_key).map_err(LoginError::TooManyAttempts)?;
+    if let Some(ip_key) = &ip_key {
+        lockout::check_login_attempts(attempts, ip_key).map_err(LoginError::TooManyAttempts)?;
+    }
+    let user = users.data.iter_mut().find(|user| {
+        user.get_user_email()
+            .is_some_and(|user_email| user_email.eq_ignore_ascii_case(email.trim()))
+    });
+    // Values in an unknown format (e.g. legacy raw passwords) never match
+    let valid = match user.as_ref().and_then(|user| user.get_password_hash()) {
+        Some(hash) if HashAlgorithm::detect(&hash).is_some() => {
+            verify_password_from_hash(password, &hash)?
+        }
+        _ => {
+            let _ = verify_password_from_hash(password, DUMMY_HASH);
+            false
+        }
+    };
+    let user = match user {
+        Some(user) if valid => user,
+        user => {
+            if let Some(ip_key) = &ip_key {
+                lockout::record_failed_login(attempts, ip_key, lockout::IP_LOCKOUT_THRESHOLD)?;
+            }
+            let locked_out = lockout::record_failed_login(
+                attempts,
+                &account_key,
+                lockout::ACCOUNT_LOCKOUT_THRESHOLD,
+            )?;
+            if let (true, Some(user)) = (locked_out, user) {
+                notify_lockout(attempts, &account_key, &*user);
+            }
+            return Err(LoginError::InvalidCredentials);
+        }
+    };
+    lockout::clear_failed_logins(attempts, &account_key)?;
+    match user.get_user_status() {
+        AccountStatus::Active => (),
+        AccountStatus::PendingVerification => return Err(LoginError::AccountPendingVerification),
+        AccountStatus::Suspended => return Err(LoginError::AccountSuspended),
+        AccountStatus::Deleted => return Err(LoginError::AccountDeleted),
+    }
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err(LoginError::Internal("User ID is not set.".to_owned())),
+    };
+    // Upgrade old hashes, while we know the password
+    user.rehash_password(password)?;
+    let token = generate_token()?;
+    if user.get_user_totp().is_some_and(|totp| totp.enabled) {
+        user.save()?;
+        let session = Session::new_pending(&token, &user_id).with_client(client);
+        storage::add_to_storage(sessions, session)?;
+        return Ok(LoginOutcome::SecondFactorRequired(token));
+    }
+    user.record_user_login();
+    user.save()?;
+    let session = Session::new(&token, &user_id).with_client(client);
+    storage::add_to_storage(sessions, session)?;
+    Ok(LoginOutcome::LoggedIn(token))
+}
+
+/// Email the account owner about a lockout. The login result does
+/// not depend on the email, so sending errors are ignored.
+fn notify_lockout<T: User>(attempts: &Storage<LoginAttempts>, account_key: &str, user: &T) {
+    if let (Err(until), Some(email)) = (
+        lockout::check_login_attempts(attempts, account_key),
+        user.get_user_email(),
+    ) {
+        let name = user.get_user_name().unwrap_or_else(|| email.clone());
+        let _ = lockout::send_lockout_email(&email, &name, until);
+    }
+}
+
+/// # Login second step
+/// Finish a login started by `login`, using the login challenge and
+/// a TOTP or recovery code. A wrong code can be retried until the
+/// challenge expires. Wrong codes count as failed logins of the
+/// account. On success the challenge is removed, and a new access
+/// token is returned. The result is recorded in the security log.
+pub fn login_second_factor<T>(
+    users: &mut Storage<T>,
+    sessions: &mut Storage<Session>,
+    attempts: &mut Storage<LoginAttempts>,
+    log: &mut Storage<SecurityEvent>,
+    challenge: &str,
+    code: &str,
+) -> Result<String, LoginError>
+where
+    T: User + StorageObject,
+{
+    let (user_id, client) = match storage::get_by_id(sessions, &hash_token(challenge)) {
+        Some(session) if session.is_pending_second_factor() => (
+            session.get_user().get_id().to_owned(),
+            session.get_client().clone(),
+        ),
+        // Not a login attempt of a known user
+        _ => return Err(LoginError::InvalidCredentials),
+    };
+    let account_key = match storage::get_by_id(users, &user_id) {
+        Some(user) => lockout::account_key(&user.get_user_email().unwrap_or_default()),
+        None => String::new(),
+    };
+    let was_locked_out = lockout::is_locked_out(attempts, &account_key);
+    let result = verify_login_challenge(users, sessions, attempts, challenge, code);
+    let (outcome, detail) = match &result {
+        Ok(_) => (SecurityEventOutcome::Success, None),
+        Err(error) => (SecurityEventOutcome::Failure, Some(error.to_string())),
+    };
+    let kind = SecurityEventKind::SecondFactor;
+    log_event(log, kind, outcome, Some(&user_id), &client, detail);
+    if !was_locked_out && lockout::is_locked_out(attempts, &account_key) {
+        let kind = SecurityEventKind::AccountLocked;
+        log_event(
+            log,
+            kind,
+            SecurityEventOutcome::Success,
+            Some(&user_id),
+            &client,
+            None,
+        );
+    }
+    result
+}
+
+fn verify_login_challenge<T>(
+    users: &mut Storage<T>,
+    sessions: &mut Storage<Session>,
+    attempts: &mut Storage<LoginAttempts>,
+    challenge: &str,
+    code: &str,
+) -> Result<String, LoginError>
+where
+    T: User + StorageObject,
+{
+    // Sessions are stored by the hash of their token
+    let challenge = &hash_token(challenge);
+    let (user_id, client) = match storage::get_by_id(sessions, challenge) {
+        Some(session) if session.is_pending_second_factor() => {
+            if Utc::now() - session.get_created() > Duration::minutes(SECOND_FACTOR_VALID_MINUTES) {
+                storage::remove_from_storage(sessions, challenge)?;
+                return Err(LoginError::InvalidCredentials);
+            }
+            (
+                session.get_user().get_id().to_owned(),
+                session.get_client().clone(),
+            )
+        }
+        _ => return Err(LoginError::InvalidCredentials),
+    };
+    let user = match storage::get_mut_by_id(users, &user_id) {
+        Some(user) if user.get_user_status() == AccountStatus::Active => user,
+        _ => {
+            storage::remove_from_storage(sessions, challenge)?;
+            return Err(LoginError::InvalidCredentials);
+        }
+    };
+    let account_key = lockout::account_key(&user.get_user_email().unwrap_or_default());
+    if let Err(until) = lockout::check_login_attempts(attempts, &account_key) {
+        storage::remove_from_storage(sessions, challenge)?;
+        return Err(LoginError::TooManyAttempts(until));
+    }
+    if verify_second_factor(user, code).is_err() {
+        let locked_out = lockout::record_failed_login(
+            attempts,
+            &account_key,
+            lockout::ACCOUNT_LOCKOUT_THRESHOLD,
+        )?;
+        if locked_out {
+            notify_lockout(attempts, &account_key, &*user);
+        }
+        return Err(LoginError::InvalidSecondFactor);
+    }
+    lockout::clear_failed_logins(attempts, &account_key)?;
+    user.record_user_login();
+    user.save()?;
+    storage::remove_from_storage(sessions, challenge)?;
+    let token = generate_token()?;
+    storage::add_to_storage(
+        sessions,
+        Session::new(&token, &user_id).with_client(&client),
+    )?;
+    Ok(token)
 }
 
 /// # Logout function
-/// Check the user login status, and try to log out. If the user is valid,
-/// and logged in, then removes from the logged-in list. The controller
+/// Check the user login status, and try to log out. If the token is valid,
+/// then removes its session, and returns the user id. The controller
 /// should delete the user-token from the browser. If the user tries to
-/// access the system using the
-pub fn logout<'a>(token: &'a str) -> Result<String, String> {
-    Err("Not implemented".to_owned())
+/// access the system using the same token again, it will be refused.
+pub fn logout(sessions: &mut Storage<Session>, token: &str) -> Result<String, String> {
+    match storage::remove_from_storage(sessions, &hash_token(token)) {
+        Ok(session) => Ok(session.get_user().get_id().to_owned()),
+        Err(_) => Err("Invalid access token.".to_owned()),
+    }
+}
+
+/// # Logout user everywhere
+/// Removes every session of the user, e.g. after a password reset.
+/// Returns the number of removed sessions.
+pub fn logout_user(sessions: &mut Storage<Session>, user_id: &str) -> Result<usize, String> {
+    let ids: Vec<String> = sessions
+        .data
+        .iter()
+        .filter(|session| session.get_user().get_id() == user_id)
+        .map(|session| session.get_session_id())
+        .collect();
+    for id in &ids {
+        storage::remove_from_storage(sessions, id)?;
+    }
+    Ok(ids.len())
 }
 
 /// # Validate access token
@@ -44,25 +445,432 @@ pub fn logout<'a>(token: &'a str) -> Result<String, String> {
 /// and its in the logged-in list, then return Ok(user-id), if the
 /// token is unvalid, or its not in the logged-in list, then return
 /// Err("Error message").
-pub fn validate_access_token<'a>(token: &'a str) -> Result<String, String> {
-    Err("Not implemented".to_owned())
+/// With a revocation list and signing keys, a signed token is valid
+/// too, if it is not revoked. Pass None where only a login is valid,
+/// and use `validate_signed_token` to check the scopes of a signed
+/// token. The user must still be active, so suspending an account
+/// logs it out everywhere. Login challenges are not access tokens.
+pub fn validate_access_token<T>(
+    users: &Storage<T>,
+    sessions: &Storage<Session>,
+    signed: Option<(&Storage<RevokedToken>, &TokenKeys)>,
+    token: &str,
+) -> Result<String, String>
+where
+    T: User + StorageObject,
+{
+    let session = match (storage::get_by_id(sessions, &hash_token(token)), signed) {
+        (Some(session), _) if !session.is_pending_second_factor() => session,
+        (None, Some((revocations, keys))) => {
+            return validate_signed_token(users, revocations, keys, token)
+                .map(|claims| claims.sub)
+                .map_err(|_| "Invalid access token.".to_owned())
+        }
+        _ => return Err("Invalid access token.".to_owned()),
+    };
+    match storage::get_by_id(users, session.get_user().get_id()) {
+        Some(user) if user.get_user_status() == AccountStatus::Active => {
+            Ok(session.get_user().get_id().to_owned())
+        }
+        _ => Err("Invalid access token.".to_owned()),
+    }
+}
+
+/// # Touch session
+/// Update the last seen time of a session.
+pub fn touch_session(sessions: &mut Storage<Session>, token: &str) -> Result<(), String> {
+    match storage::get_mut_by_id(sessions, &hash_token(token)) {
+        Some(session) => {
+            session.touch();
+            session.save()
+        }
+        None => Err("Invalid access token.".to_owned()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prelude::*;
+    use crate::user::model::user_v2::UserV2;
+    use crate::user::password::hasher::*;
+    use crate::user::security_log::{find_security_events, SecurityEventFilter};
+    use crate::user::signed_token::{issue_access_token, SigningKey, TokenKeys};
+    use crate::user::totp::*;
+
+    fn load(name: &'static str, sessions: &'static str) -> (Storage<UserV2>, Storage<Session>) {
+        let mut users = storage::load_storage::<UserV2>(name).unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_email("demo@user.com").unwrap();
+        user.set_password("SEcretPassWord1234789").unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        (users, storage::load_storage::<Session>(sessions).unwrap())
+    }
+
+    fn logged_in(outcome: Result<LoginOutcome, LoginError>) -> String {
+        match outcome {
+            Ok(LoginOutcome::LoggedIn(token)) => token,
+            outcome => panic!("Unexpected login outcome: {:?}", outcome),
+        }
+    }
+
     #[test]
     fn test_login() {
-        assert_eq!(login("email", "password").is_ok(), false); // Should be false.
+        let (mut users, mut sessions) = load("../data/login_users", "../data/login_sessions");
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/login_attempts").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/login_log").unwrap();
+        assert_eq!(
+            login(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                "demo@user.com",
+                "wrong_password",
+                &ClientInfo::default()
+            ),
+            Err(LoginError::InvalidCredentials)
+        );
+        assert_eq!(
+            login(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                "other@user.com",
+                "SEcretPassWord1234789",
+                &ClientInfo::default(),
+            ),
+            Err(LoginError::InvalidCredentials)
+        );
+        // New users are pending verification
+        assert_eq!(
+            login(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                "demo@user.com",
+                "SEcretPassWord1234789",
+                &ClientInfo::default(),
+            ),
+            Err(LoginError::AccountPendingVerification)
+        );
+        let user = storage::get_mut_by_id(&mut users, "demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        let token = logged_in(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "Demo@User.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ));
+        assert_eq!(
+            validate_access_token(&users, &sessions, None, &token),
+            Ok("demo_user".to_owned())
+        );
+        let user = storage::get_mut_by_id(&mut users, "demo_user").unwrap();
+        user.set_user_status(AccountStatus::Suspended).unwrap();
+        assert!(validate_access_token(&users, &sessions, None, &token).is_err());
+        assert_eq!(
+            login(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                "demo@user.com",
+                "SEcretPassWord1234789",
+                &ClientInfo::default(),
+            ),
+            Err(LoginError::AccountSuspended)
+        );
+        users.remove();
+        sessions.remove();
+        attempts.remove();
+        log.remove();
     }
 
     #[test]
     fn test_logout() {
-        assert_eq!(logout("token").is_ok(), false);
+        let (mut users, mut sessions) = load("../data/logout_users", "../data/logout_sessions");
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/logout_attempts").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/logout_log").unwrap();
+        let user = storage::get_mut_by_id(&mut users, "demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        let token = logged_in(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ));
+        assert_eq!(logout(&mut sessions, &token), Ok("demo_user".to_owned()));
+        assert!(logout(&mut sessions, &token).is_err());
+        assert!(validate_access_token(&users, &sessions, None, &token).is_err());
+        logged_in(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ));
+        logged_in(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ));
+        assert_eq!(logout_user(&mut sessions, "demo_user"), Ok(2));
+        assert!(sessions.data.is_empty());
+        users.remove();
+        sessions.remove();
+        attempts.remove();
+        log.remove();
+    }
+
+    #[test]
+    fn test_login_rehash() {
+        let (mut users, mut sessions) = load("../data/rehash_users", "../data/rehash_sessions");
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/rehash_attempts").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/rehash_log").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("bcrypt_user").unwrap();
+        user.set_user_email("bcrypt@user.com").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        // Simulate a hash made by an old hasher
+        let old_hash = BcryptHasher { cost: 4 }
+            .hash("SEcretPassWord1234789")
+            .unwrap();
+        let yaml = storage::serialize_object(storage::get_by_id(&users, "bcrypt_user").unwrap())
+            .unwrap()
+            .replace(
+                "password_hash: ~",
+                &format!("password_hash: \"{}\"", old_hash),
+            );
+        let user: UserV2 = storage::deserialize_object(&yaml).unwrap();
+        *storage::get_mut_by_id(&mut users, "bcrypt_user").unwrap() = user;
+
+        logged_in(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "bcrypt@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ));
+        let hash = storage::get_by_id(&users, "bcrypt_user")
+            .unwrap()
+            .get_password_hash()
+            .unwrap();
+        assert_eq!(HashAlgorithm::detect(&hash), Some(HashAlgorithm::Argon2id));
+        assert!(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "bcrypt@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        )
+        .is_ok());
+        users.remove();
+        sessions.remove();
+        attempts.remove();
+        log.remove();
+    }
+
+    #[test]
+    fn test_login_legacy_hash() {
+        let (mut users, mut sessions) = load("../data/legacy_users", "../data/legacy_sessions");
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/legacy_attempts").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/legacy_log").unwrap();
+        // Raw password saved as hash by an old version
+        let yaml =
+            storage::serialize_object(storage::get_by_id(&users, "demo_user").unwrap()).unwrap();
+        let hash = storage::get_by_id(&users, "demo_user")
+            .unwrap()
+            .get_password_hash()
+            .unwrap();
+        let yaml = yaml.replace(&hash, "SEcretPassWord1234789");
+        let mut user: UserV2 = storage::deserialize_object(&yaml).unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        *storage::get_mut_by_id(&mut users, "demo_user").unwrap() = user;
+        assert_eq!(
+            login(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                "demo@user.com",
+                "SEcretPassWord1234789",
+                &ClientInfo::default(),
+            ),
+            Err(LoginError::InvalidCredentials)
+        );
+        // Counted as a failed login
+        assert_eq!(attempts.data[0].get_failures(), 1);
+        // Unknown emails are checked against a hash of the same cost
+        assert!(!Argon2Hasher::default().needs_rehash(DUMMY_HASH));
+        users.remove();
+        sessions.remove();
+        attempts.remove();
+        log.remove();
+    }
+
+    #[test]
+    fn test_login_backoff() {
+        let (mut users, mut sessions) = load("../data/backoff_users", "../data/backoff_sessions");
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/backoff_attempts").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/backoff_log").unwrap();
+        let user = storage::get_mut_by_id(&mut users, "demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        let client = ClientInfo {
+            ip: Some("127.0.0.1".to_owned()),
+            user_agent: None,
+        };
+        for _ in 0..=lockout::FREE_ATTEMPTS {
+            assert_eq!(
+                login(
+                    &mut users,
+                    &mut sessions,
+                    &mut attempts,
+                    &mut log,
+                    "demo@user.com",
+                    "wrong_password",
+                    &client
+                ),
+                Err(LoginError::InvalidCredentials)
+            );
+        }
+        // Blocked even with the right password
+        let result = login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        );
+        assert!(matches!(result, Err(LoginError::TooManyAttempts(_))));
+        // Every attempt is logged
+        let failures = SecurityEventFilter {
+            user_id: Some("demo_user".to_owned()),
+            outcome: Some(SecurityEventOutcome::Failure),
+            ..SecurityEventFilter::default()
+        };
+        assert_eq!(
+            find_security_events(&log, &failures, 10).len(),
+            lockout::FREE_ATTEMPTS as usize + 2
+        );
+        // The IP address is blocked for other accounts too
+        assert!(lockout::check_login_attempts(&attempts, &lockout::ip_key("127.0.0.1")).is_err());
+        users.remove();
+        sessions.remove();
+        attempts.remove();
+        log.remove();
     }
 
     #[test]
     fn test_validate_token() {
-        assert_eq!(validate_access_token("token").is_ok(), false);
+        let (mut users, sessions) = load("../data/token_users", "../data/token_sessions");
+        assert!(validate_access_token(&users, &sessions, None, "token").is_err());
+
+        let user = storage::get_mut_by_id(&mut users, "demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        // A signed API token is not a login
+        let keys = TokenKeys::new(vec![SigningKey {
+            kid: "test".to_owned(),
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+        }])
+        .unwrap();
+        let token = issue_access_token(&keys, &users.data[0], &[]).unwrap();
+        assert!(validate_access_token(&users, &sessions, None, &token).is_err());
+        users.remove();
+        sessions.remove();
+    }
+
+    #[test]
+    fn test_login_second_factor() {
+        let (mut users, mut sessions) = load("../data/totp_users", "../data/totp_sessions");
+        let mut attempts = storage::load_storage::<LoginAttempts>("../data/totp_attempts").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/totp_log").unwrap();
+        let user = storage::get_mut_by_id(&mut users, "demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        let enrollment = begin_totp_enrollment(user, "Project A").unwrap();
+        let now = Utc::now().timestamp() as u64;
+        let code = totp_code(&enrollment.secret, now - TOTP_PERIOD).unwrap();
+        let recovery_codes = confirm_totp_enrollment(user, &code).unwrap();
+        let challenge = match login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ) {
+            Ok(LoginOutcome::SecondFactorRequired(challenge)) => challenge,
+            outcome => panic!("Unexpected login outcome: {:?}", outcome),
+        };
+        // The challenge is not an access token
+        assert!(validate_access_token(&users, &sessions, None, &challenge).is_err());
+        assert_eq!(
+            login_second_factor(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                &challenge,
+                "000000"
+            ),
+            Err(LoginError::InvalidSecondFactor)
+        );
+        let token = login_second_factor(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            &challenge,
+            &recovery_codes[0],
+        )
+        .unwrap();
+        assert_eq!(
+            validate_access_token(&users, &sessions, None, &token),
+            Ok("demo_user".to_owned())
+        );
+        // The challenge is single use
+        assert_eq!(
+            login_second_factor(
+                &mut users,
+                &mut sessions,
+                &mut attempts,
+                &mut log,
+                &challenge,
+                &recovery_codes[1]
+            ),
+            Err(LoginError::InvalidCredentials)
+        );
+        users.remove();
+        sessions.remove();
+        attempts.remove();
+        log.remove();
     }
 }
@@ -15,23 +15,61 @@
 // You should have received a copy of the GNU General Public License
 // along with Project A.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod api_key;
+pub mod lockout;
 pub mod login;
 pub mod model;
 pub mod password;
+pub mod privacy;
+pub mod profile;
+pub mod registration;
+pub mod reset;
+pub mod role;
+pub mod security_log;
+pub mod session;
+pub mod signed_token;
+pub mod status;
+pub mod token;
+pub mod totp;
 pub mod user;
+pub mod verification;
+
+pub use registration::register;
+
+use crate::address::Address;
+use chrono::{DateTime, Utc};
+use role::Role;
+use status::{AccountStatus, StatusChange};
+use totp::TotpSettings;
 
 pub trait User {
     fn get_user_id(&self) -> Option<String>;
     fn set_user_id(&mut self, user_id: &str) -> Result<(), String>;
     fn get_user_name(&self) -> Option<String>;
     fn set_user_name(&mut self, name: &str) -> Result<(), String>;
-    fn get_user_address(&self) -> Option<String>;
-    fn set_user_address(&mut self, address: &str) -> Result<(), String>;
+    fn get_user_address(&self) -> Option<Address>;
+    fn set_user_address(&mut self, address: Address) -> Result<(), String>;
     fn get_user_email(&self) -> Option<String>;
     fn set_user_email(&mut self, email: &str) -> Result<(), String>;
+    fn get_user_pending_email(&self) -> Option<String>;
+    fn is_user_email_verified(&self) -> bool;
+    fn confirm_user_email(&mut self, email: &str) -> Result<(), String>;
     fn get_user_phone(&self) -> Option<String>;
     fn set_user_phone(&mut self, phone: &str) -> Result<(), String>;
     fn get_password_hash(&self) -> Option<String>;
     fn set_password(&mut self, password: &str) -> Result<(), String>;
-    fn reset_password(&mut self) -> Result<(), String>;
+    fn rehash_password(&mut self, password: &str) -> Result<bool, String>;
+    fn get_user_roles(&self) -> Vec<Role>;
+    fn add_user_role(&mut self, role: Role) -> Result<(), String>;
+    fn remove_user_role(&mut self, role: Role) -> Result<(), String>;
+    fn get_user_status(&self) -> AccountStatus;
+    fn set_user_status(&mut self, status: AccountStatus) -> Result<(), String>;
+    fn get_user_status_history(&self) -> Vec<StatusChange>;
+    fn get_user_created(&self) -> Option<DateTime<Utc>>;
+    fn get_user_updated(&self) -> Option<DateTime<Utc>>;
+    fn get_user_last_login(&self) -> Option<DateTime<Utc>>;
+    fn get_user_password_changed(&self) -> Option<DateTime<Utc>>;
+    fn record_user_login(&mut self);
+    fn get_user_totp(&self) -> Option<TotpSettings>;
+    fn set_user_totp(&mut self, totp: Option<TotpSettings>) -> Result<(), String>;
 }
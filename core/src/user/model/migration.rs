@@ -0,0 +1,137 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::model::user_v1::UserV1;
+use crate::user::model::user_v2::UserV2;
+use serde::de::{Deserializer, Error};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+/// Fields only UserV2 files have
+const USER_V2_FIELDS: &[&str] = &[
+    "created",
+    "updated",
+    "last_login",
+    "password_changed",
+    "password_history",
+    "totp",
+];
+
+/// # Any user version
+/// A user file, as it is on disk. A file with any UserV2 only field
+/// is a UserV2, and it is an error if it cannot be read as one, so it
+/// is never downgraded to UserV1.
+enum AnyUser {
+    // Only read to tell the versions apart
+    #[allow(dead_code)]
+    V2(UserV2),
+    V1(UserV1),
+}
+
+impl<'de> Deserialize<'de> for AnyUser {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        if USER_V2_FIELDS
+            .iter()
+            .any(|field| value.get(field).is_some())
+        {
+            UserV2::deserialize(value)
+                .map(AnyUser::V2)
+                .map_err(D::Error::custom)
+        } else {
+            UserV1::deserialize(value)
+                .map(AnyUser::V1)
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
+/// # Migrate users
+///
+/// Load a user storage containing UserV1 and/or UserV2 files, convert
+/// and save every UserV1 as UserV2, and return the UserV2 storage.
+/// Already migrated users are kept as they are, so it is safe to call
+/// it on every startup. A UserV2 file that cannot be read is an error,
+/// nothing is migrated then.
+/// ```rust
+/// use core_lib::user::model::migration::migrate_users;
+/// let users = migrate_users("../data/doc_migrate_users").unwrap();
+/// assert_eq!(users.data.len(), 0);
+/// users.remove();
+/// ```
+pub fn migrate_users(path: &'static str) -> Result<Storage<UserV2>, String> {
+    let users = storage::load_storage::<AnyUser>(path)?;
+    for user in users.data {
+        if let AnyUser::V1(user) = user {
+            let mut user = UserV2::from(user);
+            user.set_path(path)?;
+            user.save()?;
+        }
+    }
+    storage::load_storage::<UserV2>(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::User;
+
+    #[test]
+    fn test_migrate_users() {
+        // UserV1 files can only be read, so write one by hand
+        std::fs::create_dir_all("../data/migration_users").unwrap();
+        std::fs::write(
+            "../data/migration_users/old_user.yml",
+            "---\nid: old_user\nname: Old User\n",
+        )
+        .unwrap();
+
+        let mut users = migrate_users("../data/migration_users").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("new_user").unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        let created = storage::get_by_id(&users, "old_user")
+            .unwrap()
+            .get_user_created();
+
+        // Migrating again keeps both users as they are
+        let users = migrate_users("../data/migration_users").unwrap();
+        assert_eq!(users.data.len(), 2);
+        let user = storage::get_by_id(&users, "old_user").unwrap();
+        assert_eq!(user.get_user_name(), Some("Old User".to_owned()));
+        assert_eq!(user.get_user_created(), created);
+        users.remove();
+    }
+
+    #[test]
+    fn test_migrate_broken_user_v2() {
+        std::fs::create_dir_all("../data/migration_broken_users").unwrap();
+        let content = "---\nid: new_user\ntotp:\n  secret: ABC\n  enabled: true\n\
+                       created: \"2019-01-01T00:00:00Z\"\nupdated: \"2019-01-01T00:00:00Z\"\n";
+        let file = "../data/migration_broken_users/new_user.yml";
+        std::fs::write(file, content).unwrap();
+        // Not read as UserV1, and left as it is
+        assert!(migrate_users("../data/migration_broken_users").is_err());
+        assert_eq!(std::fs::read_to_string(file).unwrap(), content);
+        std::fs::remove_dir_all("../data/migration_broken_users").unwrap();
+    }
+}
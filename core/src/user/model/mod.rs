@@ -15,4 +15,6 @@
 // You should have received a copy of the GNU General Public License
 // along with Project A.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod migration;
 pub mod user_v1;
+pub mod user_v2;
@@ -0,0 +1,793 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::address::Address;
+use crate::prelude::*;
+use crate::storage;
+use crate::user::model::user_v1::UserV1;
+use crate::user::password::hasher::HashAlgorithm;
+use crate::user::password::policy::PasswordPolicy;
+use crate::user::password::*;
+use crate::user::role::Role;
+use crate::user::status::*;
+use crate::user::totp::TotpSettings;
+use crate::user::User;
+use crate::validation;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// # User model version 2
+/// The user model. Compared to `UserV1` it has audit timestamps,
+/// maintained by the setters and by login.
+#[derive(Serialize, Deserialize)]
+pub struct UserV2 {
+    id: Option<String>,
+    path: Option<String>,
+    name: Option<String>,
+    address: Option<Address>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    #[serde(default)]
+    pending_email: Option<String>,
+    phone: Option<String>,
+    password_hash: Option<String>,
+    /// Previous password hashes, newest first
+    #[serde(default)]
+    password_history: Vec<String>,
+    #[serde(default)]
+    totp: Option<TotpSettings>,
+    #[serde(default)]
+    roles: Vec<Role>,
+    #[serde(default)]
+    status: AccountStatus,
+    #[serde(default)]
+    status_history: Vec<StatusChange>,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+    last_login: Option<DateTime<Utc>>,
+    password_changed: Option<DateTime<Utc>>,
+}
+
+impl New for UserV2 {
+    /// # New user
+    /// generating new user with None default values,
+    /// created and updated now.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// use core_lib::user::User;
+    /// let user = UserV2::new();
+    /// ```
+    fn new() -> Self {
+        let now = Utc::now();
+        UserV2 {
+            id: None,
+            path: None,
+            name: None,
+            address: None,
+            email: None,
+            email_verified: false,
+            pending_email: None,
+            phone: None,
+            password_hash: None,
+            password_history: Vec::new(),
+            totp: None,
+            roles: Vec::new(),
+            status: AccountStatus::PendingVerification,
+            status_history: Vec::new(),
+            created: now,
+            updated: now,
+            last_login: None,
+            password_changed: None,
+        }
+    }
+}
+
+impl User for UserV2 {
+    /// # Get user ID
+    /// Some(String) or None
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::model::user_v2::*;
+    /// use core_lib::user::User;
+    /// let mut user = UserV2::new();
+    /// user.set_user_id("example").unwrap();
+    /// let user_id = user.get_user_id();
+    /// ```
+    fn get_user_id(&self) -> Option<String> {
+        self.id.clone()
+    }
+    /// # Set user ID
+    /// Result<(), String>
+    /// Minimum user ID length is 5 characters
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::model::user_v2::*;
+    /// use core_lib::user::User;;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.set_user_id("demo_id"), Ok(()));
+    /// ```
+    fn set_user_id(&mut self, user_id: &str) -> Result<(), String> {
+        if self.id.is_some() {
+            Err("UserID already set! It can't be modified!".to_owned())
+        } else if user_id.len() <= 5 {
+            Err("UserID length should be bigger then 5 characters.".to_owned())
+        } else {
+            // Here we set ID as all lowecase
+            self.id = Some(user_id.to_lowercase());
+            self.touch();
+            Ok(())
+        }
+    }
+    /// # Get user name
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::*;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.get_user_name(), None);
+    /// user.set_user_name("demo_user").unwrap();
+    /// assert_eq!(user.get_user_name(), Some("demo_user".to_owned()));
+    /// ```
+    fn get_user_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    /// # Set user name
+    /// Result<(), String>
+    /// 5 to 100 characters, surrounding whitespace is trimmed
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.set_user_name("Demo User"), Ok(()));
+    /// ```
+    fn set_user_name(&mut self, name: &str) -> Result<(), String> {
+        self.name = Some(validation::validate_user_name(name)?);
+        self.touch();
+        Ok(())
+    }
+    /// # Get user address
+    /// Option<Address>
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let user = UserV2::new();
+    /// assert_eq!(user.get_user_address(), None);
+    /// ```
+    fn get_user_address(&self) -> Option<Address> {
+        self.address.clone()
+    }
+    /// # Set user address
+    /// Result<(), String>
+    /// Address must be complete, free text addresses are rejected.
+    /// ```rust
+    /// use core_lib::address::Address;
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// let address = Address::new("HU", "6725", "Szeged", "Tisza Lajos körút 1.").unwrap();
+    /// assert_eq!(user.set_user_address(address), Ok(()));
+    /// ```
+    fn set_user_address(&mut self, address: Address) -> Result<(), String> {
+        address.validate()?;
+        self.address = Some(address);
+        self.touch();
+        Ok(())
+    }
+    /// # Get user email
+    /// Option<String>
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.get_user_email(), None);
+    /// ```
+    fn get_user_email(&self) -> Option<String> {
+        self.email.clone()
+    }
+    /// # Set user email
+    /// Result<(), String>
+    /// Email is validated and normalized by `validation::normalize_email`.
+    /// The email is unverified. Once the email is verified, a new email
+    /// is pending, and the old one is kept until the new one is verified.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.set_user_email("user@company.com"), Ok(()));
+    /// assert_eq!(user.is_user_email_verified(), false);
+    /// ```
+    fn set_user_email(&mut self, email: &str) -> Result<(), String> {
+        let email = validation::normalize_email(email)?;
+        if self.email_verified {
+            if self.email.as_deref() == Some(email.as_str()) {
+                self.pending_email = None;
+            } else {
+                self.pending_email = Some(email);
+            }
+        } else {
+            self.email = Some(email);
+        }
+        self.touch();
+        Ok(())
+    }
+    /// # Get pending user email
+    /// Changed email address, waiting for verification.
+    fn get_user_pending_email(&self) -> Option<String> {
+        self.pending_email.clone()
+    }
+    fn is_user_email_verified(&self) -> bool {
+        self.email_verified
+    }
+    /// # Confirm user email
+    /// Result<(), String>
+    /// The email must be the pending email, or the current unverified one.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// user.set_user_email("user@company.com").unwrap();
+    /// assert_eq!(user.confirm_user_email("other@company.com").is_err(), true);
+    /// assert_eq!(user.confirm_user_email("user@company.com"), Ok(()));
+    /// assert_eq!(user.is_user_email_verified(), true);
+    /// ```
+    fn confirm_user_email(&mut self, email: &str) -> Result<(), String> {
+        if self.pending_email.as_deref() == Some(email) {
+            self.email = self.pending_email.take();
+            self.email_verified = true;
+            self.touch();
+            Ok(())
+        } else if !self.email_verified && self.email.as_deref() == Some(email) {
+            self.email_verified = true;
+            self.touch();
+            Ok(())
+        } else {
+            Err("This email address is not waiting for verification.".to_owned())
+        }
+    }
+    /// # Get user phone
+    /// Option<String>
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.get_user_phone(), None);
+    /// ```
+    fn get_user_phone(&self) -> Option<String> {
+        self.phone.clone()
+    }
+    /// # Set user phone
+    /// Result<(), String>
+    /// Stored in E.164 format, national numbers are taken as
+    /// `validation::DEFAULT_PHONE_COUNTRY` numbers.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.set_user_phone("+749 (39) 4759 33279"), Ok(()));
+    /// assert_eq!(user.get_user_phone(), Some("+74939475933279".to_owned()));
+    /// ```
+    fn set_user_phone(&mut self, phone: &str) -> Result<(), String> {
+        self.phone = Some(validation::normalize_phone(
+            phone,
+            validation::DEFAULT_PHONE_COUNTRY,
+        )?);
+        self.touch();
+        Ok(())
+    }
+    /// # Get user password as hash
+    /// Option<String>
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.get_password_hash(), None);
+    /// ```
+    fn get_password_hash(&self) -> Option<String> {
+        self.password_hash.clone()
+    }
+    /// # Set user password
+    /// Result<(), String>
+    /// Password must follow the active password policy, and cannot
+    /// contain the user name or email. The last N passwords, set by
+    /// the policy `history_size`, cannot be used again. Stored values
+    /// that are not a known hash are not checked, nor kept.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.set_password("PAssword1234789"), Ok(()));
+    /// ```
+    fn set_password(&mut self, password: &str) -> Result<(), String> {
+        validate_password_for_user(password, self)?;
+        let history_size = PasswordPolicy::active()?.history_size;
+        // Legacy raw passwords cannot be verified, and are dropped
+        if !self.password_hash.as_deref().is_some_and(is_known_hash) {
+            self.password_hash = None;
+        }
+        // The current password is the first of the last N passwords
+        let last_hashes = self
+            .password_hash
+            .iter()
+            .chain(self.password_history.iter())
+            .take(history_size)
+            .filter(|hash| is_known_hash(hash));
+        for hash in last_hashes {
+            if verify_password_from_hash(password, hash)? {
+                return Err(format!(
+                    "Password cannot be one of your last {} passwords.",
+                    history_size
+                ));
+            }
+        }
+        if let Some(hash) = self.password_hash.take() {
+            self.password_history.insert(0, hash);
+            self.password_history
+                .truncate(history_size.saturating_sub(1));
+        }
+        self.password_hash = Some(hash_password(password)?);
+        self.touch();
+        self.password_changed = Some(self.updated);
+        Ok(())
+    }
+
+    /// # Rehash password
+    /// Result<bool, String>
+    /// Upgrade the stored hash to the active hasher, using the valid
+    /// password, e.g. at login. True if the hash is upgraded.
+    /// The password is the same, so it is not a password change.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// user.set_password("PAssword1234789").unwrap();
+    /// assert_eq!(user.rehash_password("PAssword1234789"), Ok(false));
+    /// ```
+    fn rehash_password(&mut self, password: &str) -> Result<bool, String> {
+        match &self.password_hash {
+            Some(hash) if password_needs_rehash(hash) => {
+                if !verify_password_from_hash(password, hash)? {
+                    return Err("Wrong password.".to_owned());
+                }
+                self.password_hash = Some(hash_password(password)?);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+    /// # Get user roles
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let user = UserV2::new();
+    /// assert_eq!(user.get_user_roles().len(), 0);
+    /// ```
+    fn get_user_roles(&self) -> Vec<Role> {
+        self.roles.clone()
+    }
+    /// # Add user role
+    /// Result<(), String>
+    /// Role can be added only once.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// use core_lib::user::role::Role;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.add_user_role(Role::Admin), Ok(()));
+    /// assert_eq!(user.add_user_role(Role::Admin).is_err(), true);
+    /// ```
+    fn add_user_role(&mut self, role: Role) -> Result<(), String> {
+        if self.roles.contains(&role) {
+            Err(format!("User already has the {:?} role.", role))
+        } else {
+            self.roles.push(role);
+            self.touch();
+            Ok(())
+        }
+    }
+    /// # Remove user role
+    /// Result<(), String>
+    fn remove_user_role(&mut self, role: Role) -> Result<(), String> {
+        if self.roles.contains(&role) {
+            self.roles.retain(|item| *item != role);
+            self.touch();
+            Ok(())
+        } else {
+            Err(format!("User does not have the {:?} role.", role))
+        }
+    }
+    /// # Get user status
+    /// New users are pending verification.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// use core_lib::user::status::AccountStatus;
+    /// let user = UserV2::new();
+    /// assert_eq!(user.get_user_status(), AccountStatus::PendingVerification);
+    /// ```
+    fn get_user_status(&self) -> AccountStatus {
+        self.status
+    }
+    /// # Set user status
+    /// Result<(), String>
+    /// Only allowed transitions, see `AccountStatus::can_transition_to`.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// use core_lib::user::status::AccountStatus;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.set_user_status(AccountStatus::Suspended).is_err(), true);
+    /// assert_eq!(user.set_user_status(AccountStatus::Active), Ok(()));
+    /// assert_eq!(user.get_user_status_history().len(), 1);
+    /// ```
+    fn set_user_status(&mut self, status: AccountStatus) -> Result<(), String> {
+        change_status(&mut self.status, &mut self.status_history, status)?;
+        self.touch();
+        Ok(())
+    }
+    fn get_user_status_history(&self) -> Vec<StatusChange> {
+        self.status_history.clone()
+    }
+    fn get_user_created(&self) -> Option<DateTime<Utc>> {
+        Some(self.created)
+    }
+    fn get_user_updated(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated)
+    }
+    fn get_user_last_login(&self) -> Option<DateTime<Utc>> {
+        self.last_login
+    }
+    fn get_user_password_changed(&self) -> Option<DateTime<Utc>> {
+        self.password_changed
+    }
+    /// # Record user login
+    /// Sets the last login time. It is not a profile update, so the
+    /// updated time is kept.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// let mut user = UserV2::new();
+    /// assert_eq!(user.get_user_last_login(), None);
+    /// user.record_user_login();
+    /// assert_eq!(user.get_user_last_login().is_some(), true);
+    /// ```
+    fn record_user_login(&mut self) {
+        self.last_login = Some(Utc::now());
+    }
+    /// # Get user TOTP settings
+    /// None if 2FA is not enabled, or enrollment is not started.
+    fn get_user_totp(&self) -> Option<TotpSettings> {
+        self.totp.clone()
+    }
+    /// # Set user TOTP settings
+    /// Use the functions of the `totp` module, they keep the settings
+    /// consistent. It changes at every 2FA login, so it is not
+    /// an update of the user.
+    fn set_user_totp(&mut self, totp: Option<TotpSettings>) -> Result<(), String> {
+        self.totp = totp;
+        Ok(())
+    }
+}
+
+impl UserV2 {
+    fn touch(&mut self) {
+        self.updated = Utc::now();
+    }
+    /// # Anonymise user
+    /// Removes every personal data and credential, and sets the status
+    /// to Deleted. The ID is kept, so references to the user in shared
+    /// content stay valid.
+    /// ```rust
+    /// use core_lib::prelude::New;
+    /// use core_lib::user::User;
+    /// use core_lib::user::model::user_v2::UserV2;
+    /// use core_lib::user::status::AccountStatus;
+    /// let mut user = UserV2::new();
+    /// user.set_user_id("demo_user").unwrap();
+    /// user.set_user_email("demo@user.com").unwrap();
+    /// user.anonymise();
+    /// assert_eq!(user.get_user_id(), Some("demo_user".to_owned()));
+    /// assert_eq!(user.get_user_email(), None);
+    /// assert_eq!(user.get_user_status(), AccountStatus::Deleted);
+    /// ```
+    pub fn anonymise(&mut self) {
+        self.name = None;
+        self.address = None;
+        self.email = None;
+        self.email_verified = false;
+        self.pending_email = None;
+        self.phone = None;
+        self.password_hash = None;
+        self.password_history.clear();
+        self.totp = None;
+        self.roles.clear();
+        self.last_login = None;
+        if self.status != AccountStatus::Deleted {
+            // Any status can be changed to Deleted
+            let _ = self.set_user_status(AccountStatus::Deleted);
+        }
+        self.touch();
+    }
+}
+
+/// Hash made by a supported hasher
+fn is_known_hash(hash: &str) -> bool {
+    HashAlgorithm::detect(hash).is_some()
+}
+
+impl From<UserV1> for UserV2 {
+    /// # Migrate UserV1 to UserV2
+    /// UserV1 has no timestamps, so created and updated are set to
+    /// the migration time. A password hash that is not a known hash
+    /// (a raw password saved by old versions) is cleared, the user has
+    /// to reset the password.
+    fn from(user: UserV1) -> Self {
+        let now = Utc::now();
+        UserV2 {
+            id: user.id,
+            path: user.path,
+            name: user.name,
+            address: user.address,
+            email: user.email,
+            email_verified: user.email_verified,
+            pending_email: user.pending_email,
+            phone: user.phone,
+            password_hash: user.password_hash.filter(|hash| is_known_hash(hash)),
+            password_history: Vec::new(),
+            totp: None,
+            roles: user.roles,
+            status: user.status,
+            status_history: user.status_history,
+            created: now,
+            updated: now,
+            last_login: None,
+            password_changed: None,
+        }
+    }
+}
+
+/**
+ * StorageObject implementation for UserV2
+ */
+/// Credentials are never exported
+impl storage::transfer::Exportable for UserV2 {
+    const SECRET_FIELDS: &'static [&'static str] = &["password_hash", "password_history", "totp"];
+}
+
+impl storage::StorageObject for UserV2 {
+    fn get_id(&self) -> Option<&str> {
+        self.id.as_ref().map(|id| id.as_ref())
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::reset::{request_password_reset, PasswordReset};
+    use std::env;
+
+    #[test]
+    fn test_user_audit_fields() {
+        let mut user = UserV2::new();
+        let created = user.get_user_created().unwrap();
+        assert_eq!(user.get_user_updated(), Some(created));
+        assert_eq!(user.get_user_password_changed(), None);
+        assert!(user.set_user_name("abc").is_err());
+        assert_eq!(user.get_user_updated(), Some(created));
+        user.set_user_name("Demo User").unwrap();
+        assert!(user.get_user_updated().unwrap() >= created);
+        user.set_password("PAssword1234789").unwrap();
+        assert_eq!(user.get_user_password_changed(), user.get_user_updated());
+        user.record_user_login();
+        assert!(user.get_user_last_login().is_some());
+        assert_eq!(user.get_user_created(), Some(created));
+    }
+
+    #[test]
+    fn test_password_history() {
+        let mut user = UserV2::new();
+        let passwords = ["FIrstPass12", "SEcondPass12", "THirdPass12", "FOurthPass12"];
+        for password in &passwords {
+            user.set_password(password).unwrap();
+        }
+        // Current password, and the previous ones
+        assert!(user.set_password("FOurthPass12").is_err());
+        assert!(user.set_password("SEcondPass12").is_err());
+        user.set_password("FIfthPass12").unwrap();
+        user.set_password("SIxthPass12").unwrap();
+        // 5 passwords later it can be used again
+        assert!(user.set_password("FIrstPass12").is_ok());
+        assert_eq!(user.password_history.len(), 4);
+    }
+
+    #[test]
+    fn test_set_password_legacy_hash() {
+        let mut user = UserV2::new();
+        // Raw password saved as hash by an old version
+        user.password_hash = Some("LEgacyPass12".to_owned());
+        assert_eq!(user.set_password("LEgacyPass12"), Ok(()));
+        assert!(user.password_history.is_empty());
+        assert!(
+            verify_password_from_hash("LEgacyPass12", &user.get_password_hash().unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_user_v1() {
+        let user: UserV1 = storage::deserialize_object(
+            "---\nid: demo_user\nemail: demo@user.com\npassword_hash: hash\nroles:\n  - Editor",
+        )
+        .unwrap();
+        let user = UserV2::from(user);
+        assert_eq!(user.get_user_id(), Some("demo_user".to_owned()));
+        assert_eq!(user.get_user_email(), Some("demo@user.com".to_owned()));
+        assert_eq!(user.get_user_roles(), vec![Role::Editor]);
+        assert_eq!(user.get_user_status(), AccountStatus::Active);
+        // Not a known hash, the password has to be reset
+        assert_eq!(user.get_password_hash(), None);
+        assert_eq!(user.get_user_last_login(), None);
+        // Users saved before roles existed have no roles
+        let user: UserV1 = storage::deserialize_object("---\nid: demo_user").unwrap();
+        assert_eq!(UserV2::from(user).get_user_roles(), vec![]);
+    }
+
+    #[test]
+    fn test_user_id() {
+        let mut user: UserV2 = UserV2::new();
+        // At this point ID should be None;
+        assert_eq!(user.get_user_id(), None);
+        user.set_user_id("Demo_user").unwrap();
+        // This should return an Err(..)
+        // Let's test is
+        assert_eq!(user.set_user_id("demo_user_new_set_new_id").is_err(), true);
+        // Now the user should have Some("demo_user" as String) as ID.
+        // Test that it's not overwritten, and all letter is lovercase
+        assert_eq!(user.get_user_id(), Some("demo_user".to_owned()));
+    }
+
+    #[test]
+    fn test_user_email() {
+        let mut user: UserV2 = UserV2::new();
+
+        // Check assertions
+        assert_eq!(user.set_user_id("demo_user").is_ok(), true); // should be ok
+        assert_eq!(user.set_user_email("demo@demo.com").is_ok(), true); // should be ok
+        assert_eq!(user.set_user_email("wohoo").is_err(), true); // should be err
+        assert_eq!(user.set_user_email("demo@company.com").is_ok(), true); // should be ok
+
+        // Check email wether email is correct
+        assert_eq!(user.get_user_email(), Some("demo@company.com".to_owned()));
+    }
+
+    #[test]
+    fn test_user_name() {
+        let mut user: UserV2 = UserV2::new();
+        assert_eq!(user.get_user_name(), None);
+        assert_eq!(user.set_user_name("abc").is_err(), true); // should be err
+        assert_eq!(user.set_user_name("Demo User").is_ok(), true); // should be ok
+        assert_eq!(user.set_user_name("Hello World").is_ok(), true); // should be ok
+        assert_eq!(user.get_user_name(), Some("Hello World".to_owned())) // should be ok
+    }
+
+    #[test]
+    fn test_user_address() {
+        let mut user: UserV2 = UserV2::new();
+        let address = Address::new("DE", "10115", "Berlin", "Invalidenstraße 117").unwrap();
+        let free_text: Address = serde_json::from_str("\"addr\"").unwrap();
+        assert_eq!(user.get_user_address(), None);
+        assert_eq!(user.set_user_address(address.clone()).is_ok(), true); // should be ok
+        assert_eq!(user.set_user_address(free_text).is_err(), true); // should be err
+        assert_eq!(user.get_user_address(), Some(address))
+    }
+
+    #[test]
+    fn test_user_phone() {
+        let mut user: UserV2 = UserV2::new();
+        let phone_number: &str = "+99 (701) 479 397129";
+        assert_eq!(user.get_user_phone(), None);
+        assert_eq!(user.set_user_phone(phone_number).is_ok(), true); // should be ok
+        assert_eq!(user.set_user_phone("phn").is_err(), true); // should be err
+        assert_eq!(user.get_user_phone(), Some("+99701479397129".to_owned()));
+    }
+
+    #[test]
+    fn test_user_set_password() {
+        let mut user: UserV2 = UserV2::new();
+        let password: &str = "HelloWorld749";
+        assert_eq!(user.get_password_hash(), None); // should be None
+        assert_eq!(user.set_password("pass").is_err(), true); // should be err
+        assert_eq!(user.set_password("PAss7").is_err(), true); // should be err
+        assert_eq!(user.set_password("password").is_err(), true); // should be err
+        assert_eq!(user.set_password("Password").is_err(), true); // should be err
+        assert_eq!(user.set_password("PAssword").is_err(), true); // should be err
+        assert_eq!(user.set_password("PAssword7").is_ok(), true); // should be ok
+        assert_eq!(user.set_password(password).is_ok(), true); // should be ok
+        assert_eq!(
+            verify_password_from_hash(password, user.get_password_hash().unwrap().as_ref())
+                .unwrap(),
+            true
+        );
+    }
+    #[test]
+    #[ignore]
+    fn test_reset_password() {
+        let mut users = storage::load_storage::<UserV2>("../data/reset_password_users").unwrap();
+        let mut resets =
+            storage::load_storage::<PasswordReset>("../data/reset_password_tokens").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_email(&env::var("E_TO_TEST_EMAIL").unwrap())
+            .unwrap();
+        user.set_user_name(&env::var("E_TO_TEST_NAME").unwrap())
+            .unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        assert_eq!(
+            request_password_reset(
+                &mut resets,
+                &users,
+                &env::var("E_TO_TEST_EMAIL").unwrap(),
+                "http://localhost:8000/reset_password/"
+            )
+            .is_ok(),
+            true
+        );
+        users.remove();
+        resets.remove();
+    }
+
+    #[test]
+    fn test_user_roles() {
+        let mut user: UserV2 = UserV2::new();
+        assert_eq!(user.get_user_roles(), vec![]);
+        assert!(user.remove_user_role(Role::Member).is_err()); // should be err
+        assert!(user.add_user_role(Role::Member).is_ok()); // should be ok
+        assert!(user.add_user_role(Role::Editor).is_ok()); // should be ok
+        assert!(user.add_user_role(Role::Member).is_err()); // should be err
+        assert!(user.remove_user_role(Role::Member).is_ok()); // should be ok
+        assert_eq!(user.get_user_roles(), vec![Role::Editor]);
+    }
+}
@@ -15,27 +15,30 @@
 // You should have received a copy of the GNU General Public License
 // along with Project A.  If not, see <http://www.gnu.org/licenses/>.
 
-use bcrypt::{hash, verify};
-use rand::Rng;
+pub mod generator;
+pub mod hasher;
+pub mod policy;
+
+use crate::user::User;
+use generator::PasswordGenerator;
+use hasher::{active_hasher, verify_any};
+use policy::PasswordPolicy;
 
 /// # Hash password
 /// Get a password string pointer, returns a Result<String, String>
+/// The hash is made by the active hasher, see `hasher::active_hasher`.
 /// ```rust
 /// use core_lib::user::password::hash_password;
 /// let hash = hash_password("purple dog").unwrap();
 /// ```
-pub fn hash_password<'a>(password: &'a str) -> Result<String, String> {
-    //let hashed = hash("hunter2", DEFAULT_COST)?;
-    //let valid = verify("hunter2", &hashed)?;
-    match hash(password, 6) {
-        Ok(hash) => Ok(hash),
-        Err(_) => Err("Error while creating hash from password".to_owned()),
-    }
+pub fn hash_password(password: &str) -> Result<String, String> {
+    active_hasher().hash(password)
 }
 
 /// # Verify password from hash
 /// Gets a password and hash pointer and returns a Result<bool, String>
 /// True if verify succeed, false otherwise.
+/// Works with any supported algorithm, recognised from the hash.
 /// ```rust
 /// use core_lib::user::password::{verify_password_from_hash, hash_password};
 /// let hash = hash_password("purple_dog").unwrap();
@@ -43,91 +46,57 @@ pub fn hash_password<'a>(password: &'a str) -> Result<String, String> {
 ///                         "purple_dog",
 ///                         &hash).unwrap();
 /// ```
-pub fn verify_password_from_hash<'a>(password: &'a str, hash: &'a str) -> Result<bool, String> {
-    match verify(password, &hash) {
-        Ok(result) => Ok(result),
-        Err(_) => Err("Error while trying verify password from hash".to_owned()),
-    }
+pub fn verify_password_from_hash(password: &str, hash: &str) -> Result<bool, String> {
+    verify_any(password, hash)
+}
+
+/// # Password needs rehash
+/// True if the hash is not made by the active hasher with its
+/// current parameters.
+pub fn password_needs_rehash(hash: &str) -> bool {
+    active_hasher().needs_rehash(hash)
 }
 
 /// # Generate random password
-/// Set a length or leave it None.
-/// Returns a random password aA-zZ, 0-9
+/// Set a length or leave it None, the default length is 12, or the
+/// minimum length of the active password policy if it is longer.
+/// Returns a random password satisfying the active password policy,
+/// generated from the OS CSPRNG, see `generator::PasswordGenerator`.
 /// ```rust
-/// use core_lib::user::password::generate_random_password;
+/// use core_lib::user::password::{generate_random_password, validate_password};
 /// let password = generate_random_password(None).unwrap();
+/// assert_eq!(validate_password(&password), Ok(()));
 /// ```
 pub fn generate_random_password(length: Option<u32>) -> Result<String, String> {
-    let mut rng = rand::thread_rng();
-    let mut password = "".to_owned();
-    let chars: Vec<char> = "abcdefghijklmnopqrstuvwxyz0123456789"
-        .to_owned()
-        .chars()
-        .collect();
-    // Generate random string, default length is 12
-    for _ in 0..length.unwrap_or(12) {
-        // Generate random character
-        let random_ch = match chars.get(rng.gen_range(0, chars.len())) {
-            Some(ch) => ch,
-            None => return Err("Error while generating random password!".to_owned()),
-        };
-        // Random uppercase
-        // TODO: uppercase does not work!
-        let random_ch: char = match rng.gen_range(0, 1) {
-            1 => random_ch.clone(),
-            _ => random_ch.clone(),
-        };
-        password.push(random_ch);
-    }
-    return Ok(password);
+    let policy = PasswordPolicy::active()?;
+    let length = match length {
+        Some(length) => length as usize,
+        None => policy.min_length.max(12),
+    };
+    PasswordGenerator::default().generate(length, &policy)
 }
 
 /// # Validate password
-/// Validate password to check it is strong enough.
-/// What we check is *password length*, *uppercase character frequency*,
-/// *lowercase character frequency* and *number frequency*.
+/// Validate password to check it is strong enough, using the active
+/// password policy, see `policy::PasswordPolicy::active`.
 /// ```rust
 /// use core_lib::user::password::validate_password;
 /// assert_eq!(validate_password("DEmoPassWord1234789").is_ok(), true);
 /// ```
 pub fn validate_password(password: &str) -> Result<(), String> {
-    let min_password_len = 7;
-    let min_character_lowercase = 2;
-    let min_character_uppercase = 2;
-    let min_numeric_character = 1;
-    let mut character_lowercase: u32 = 0;
-    let mut character_uppercase: u32 = 0;
-    let mut character_numeric: u32 = 0;
-    for ch in password.chars() {
-        // count numeric characters
-        if ch.is_numeric() {
-            character_numeric += 1;
-        }
-        // count lowercase characters
-        if ch.is_lowercase() {
-            character_lowercase += 1;
-        }
-        // count uppercase characters
-        if ch.is_uppercase() {
-            character_uppercase += 1;
-        }
-    }
-    if password.len() >= min_password_len
-        && character_numeric >= min_numeric_character
-        && character_lowercase >= min_character_lowercase
-        && character_uppercase >= min_character_uppercase
-    {
-        Ok(())
-    } else {
-        Err(format!(
-            "Password should be min {} length, should contain min {}
-            lowercase letter, min {} uppercase letter, min {} number",
-            min_password_len,
-            min_character_lowercase,
-            min_character_uppercase,
-            min_numeric_character
-        ))
-    }
+    PasswordPolicy::active()?.validate(password, &[])
+}
+
+/// # Validate password for user
+/// Same as `validate_password`, but the password cannot contain
+/// the user name or email either.
+pub fn validate_password_for_user<T: User>(password: &str, user: &T) -> Result<(), String> {
+    let personal: Vec<String> = vec![user.get_user_name(), user.get_user_email()]
+        .into_iter()
+        .flatten()
+        .collect();
+    let personal: Vec<&str> = personal.iter().map(|item| item.as_ref()).collect();
+    PasswordPolicy::active()?.validate(password, &personal)
 }
 
 // Tests
@@ -155,9 +124,13 @@ mod tests {
     #[test]
     fn test_random_generator() {
         assert_eq!(generate_random_password(None).unwrap().len(), 12); // This should be true
-        assert_eq!(generate_random_password(Some(5)).unwrap().len(), 5); // This should be true
-        assert_eq!(generate_random_password(Some(0)).unwrap().len(), 0); // This should be true
+        assert!(generate_random_password(Some(5)).is_err()); // shorter than the policy allows
+        assert!(generate_random_password(Some(0)).is_err()); // should be err
         assert_eq!(generate_random_password(Some(7)).unwrap().len(), 7); // This should be true
+        for _ in 0..20 {
+            let password = generate_random_password(None).unwrap();
+            assert!(validate_password(&password).is_ok()); // should be ok
+        }
     }
     #[test]
     fn test_validate_password() {
@@ -0,0 +1,158 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::user::password::policy::PasswordPolicy;
+use rand_core::{OsRng, RngCore};
+
+/// # Random index
+/// Uniform random number in 0..n from the OS CSPRNG, without modulo
+/// bias.
+pub(crate) fn random_below(n: usize) -> usize {
+    let n = n as u32;
+    // Largest multiple of n, values above it are rejected
+    let zone = u32::MAX - u32::MAX % n;
+    loop {
+        let value = OsRng.next_u32();
+        if value < zone {
+            return (value % n) as usize;
+        }
+    }
+}
+
+/// # Password generator
+///
+/// Generates passwords from configurable alphabets using the OS
+/// CSPRNG. Every character class the policy requires is included.
+/// Leave an alphabet empty to not use that class.
+#[derive(Debug, Clone)]
+pub struct PasswordGenerator {
+    pub lowercase: String,
+    pub uppercase: String,
+    pub digits: String,
+    pub symbols: String,
+}
+
+impl Default for PasswordGenerator {
+    /// Similar looking characters (l, I, O, 0, 1) are left out.
+    fn default() -> Self {
+        PasswordGenerator {
+            lowercase: "abcdefghijkmnopqrstuvwxyz".to_owned(),
+            uppercase: "ABCDEFGHJKLMNPQRSTUVWXYZ".to_owned(),
+            digits: "23456789".to_owned(),
+            symbols: "!#$%&*+-=?@_".to_owned(),
+        }
+    }
+}
+
+impl PasswordGenerator {
+    /// # Generate password
+    /// The password satisfies the policy. Error if the length is too
+    /// short for the policy, or a required alphabet is empty.
+    /// ```rust
+    /// use core_lib::user::password::generator::PasswordGenerator;
+    /// use core_lib::user::password::policy::PasswordPolicy;
+    /// let policy = PasswordPolicy::default();
+    /// let password = PasswordGenerator::default().generate(16, &policy).unwrap();
+    /// assert_eq!(password.chars().count(), 16);
+    /// assert_eq!(policy.validate(&password, &[]), Ok(()));
+    /// ```
+    pub fn generate(&self, length: usize, policy: &PasswordPolicy) -> Result<String, String> {
+        let classes: Vec<(Vec<char>, usize)> = vec![
+            (self.lowercase.chars().collect(), policy.min_lowercase),
+            (self.uppercase.chars().collect(), policy.min_uppercase),
+            (self.digits.chars().collect(), policy.min_digits),
+            (self.symbols.chars().collect(), policy.min_symbols),
+        ];
+        if classes
+            .iter()
+            .any(|(alphabet, required)| alphabet.is_empty() && *required > 0)
+        {
+            return Err("An alphabet required by the password policy is empty.".to_owned());
+        }
+        let required: usize = classes.iter().map(|(_, required)| required).sum();
+        if length < required || length < policy.min_length || length > policy.max_length {
+            return Err(format!(
+                "Password length {} does not fit the password policy.",
+                length
+            ));
+        }
+        let all: Vec<char> = classes
+            .iter()
+            .flat_map(|(alphabet, _)| alphabet.iter().cloned())
+            .collect();
+        if all.is_empty() {
+            return Err("Password alphabets are empty.".to_owned());
+        }
+        // A random password can still be on the common list, in theory
+        for _ in 0..10 {
+            let mut password: Vec<char> = Vec::with_capacity(length);
+            for (alphabet, required) in &classes {
+                for _ in 0..*required {
+                    password.push(alphabet[random_below(alphabet.len())]);
+                }
+            }
+            while password.len() < length {
+                password.push(all[random_below(all.len())]);
+            }
+            // Fisher-Yates shuffle, so required characters are not in front
+            for i in (1..password.len()).rev() {
+                password.swap(i, random_below(i + 1));
+            }
+            let password: String = password.into_iter().collect();
+            if policy.validate(&password, &[]).is_ok() {
+                return Ok(password);
+            }
+        }
+        Err("Error while generating random password!".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_below() {
+        let mut seen = [false; 5];
+        for _ in 0..200 {
+            seen[random_below(5)] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn test_generate() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            min_symbols: 2,
+            ..PasswordPolicy::default()
+        };
+        let generator = PasswordGenerator::default();
+        for _ in 0..50 {
+            let password = generator.generate(8, &policy).unwrap();
+            assert_eq!(policy.validate(&password, &[]), Ok(()));
+        }
+        assert!(generator.generate(7, &policy).is_err());
+        let generator = PasswordGenerator {
+            symbols: String::new(),
+            ..PasswordGenerator::default()
+        };
+        assert!(generator.generate(12, &policy).is_err());
+        let password = generator.generate(12, &PasswordPolicy::default()).unwrap();
+        assert!(password.chars().all(|ch| ch.is_alphanumeric()));
+    }
+}
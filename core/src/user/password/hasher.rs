@@ -0,0 +1,213 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use argon2::password_hash::{PasswordHash, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand_core::OsRng;
+use std::convert::TryFrom;
+use std::env;
+
+/// # Password hasher
+/// Hash and verify passwords with one algorithm and its parameters.
+pub trait PasswordHasher {
+    fn hash(&self, password: &str) -> Result<String, String>;
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, String>;
+    /// True if the hash was made by another algorithm, or with other
+    /// parameters than this hasher uses.
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+/// # Hash algorithm
+/// Algorithm of a stored hash, recognised from its prefix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Bcrypt,
+    Argon2id,
+}
+
+impl HashAlgorithm {
+    /// # Detect hash algorithm
+    /// ```rust
+    /// use core_lib::user::password::hasher::HashAlgorithm;
+    /// assert_eq!(HashAlgorithm::detect("$2y$06$abc"), Some(HashAlgorithm::Bcrypt));
+    /// assert_eq!(HashAlgorithm::detect("$argon2id$v=19$abc"), Some(HashAlgorithm::Argon2id));
+    /// assert_eq!(HashAlgorithm::detect("plaintext"), None);
+    /// ```
+    pub fn detect(hash: &str) -> Option<HashAlgorithm> {
+        if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+            Some(HashAlgorithm::Bcrypt)
+        } else if hash.starts_with("$argon2id$") {
+            Some(HashAlgorithm::Argon2id)
+        } else {
+            None
+        }
+    }
+}
+
+/// # Bcrypt hasher
+/// Cost is the log2 of the iterations, between 4 and 31.
+pub struct BcryptHasher {
+    pub cost: u32,
+}
+
+impl Default for BcryptHasher {
+    fn default() -> Self {
+        BcryptHasher { cost: 12 }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        bcrypt::hash(password, self.cost)
+            .map_err(|_| "Error while creating hash from password".to_owned())
+    }
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, String> {
+        bcrypt::verify(password, hash)
+            .map_err(|_| "Error while trying verify password from hash".to_owned())
+    }
+    fn needs_rehash(&self, hash: &str) -> bool {
+        // $2b$12$... the cost is the second field
+        match HashAlgorithm::detect(hash) {
+            Some(HashAlgorithm::Bcrypt) => {
+                hash.split('$')
+                    .nth(2)
+                    .and_then(|cost| cost.parse::<u32>().ok())
+                    != Some(self.cost)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// # Argon2id hasher
+/// Memory cost in KiB, iterations and parallelism.
+pub struct Argon2Hasher {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Hasher {
+    fn default() -> Self {
+        Argon2Hasher {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Argon2Hasher {
+    fn argon2(&self) -> Result<Argon2<'static>, String> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|err| format!("Wrong Argon2 parameters: {}", err))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+impl PasswordHasher for Argon2Hasher {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        use argon2::PasswordHasher as _;
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()?
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| "Error while creating hash from password".to_owned())
+    }
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, String> {
+        use argon2::PasswordVerifier as _;
+        let hash = PasswordHash::new(hash)
+            .map_err(|_| "Error while trying verify password from hash".to_owned())?;
+        // Parameters are read from the hash itself
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    }
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let hash = match PasswordHash::new(hash) {
+            Ok(hash) if hash.algorithm == Algorithm::Argon2id.ident() => hash,
+            _ => return true,
+        };
+        match Params::try_from(&hash) {
+            Ok(params) => {
+                params.m_cost() != self.memory_kib
+                    || params.t_cost() != self.iterations
+                    || params.p_cost() != self.parallelism
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// # Active password hasher
+/// Used for new hashes. Set `PASSWORD_HASHER=bcrypt` to use bcrypt,
+/// with `BCRYPT_COST` as cost, otherwise it is Argon2id.
+pub fn active_hasher() -> Box<dyn PasswordHasher> {
+    match env::var("PASSWORD_HASHER") {
+        Ok(ref name) if name == "bcrypt" => Box::new(BcryptHasher {
+            cost: env::var("BCRYPT_COST")
+                .ok()
+                .and_then(|cost| cost.parse().ok())
+                .unwrap_or_else(|| BcryptHasher::default().cost),
+        }),
+        _ => Box::new(Argon2Hasher::default()),
+    }
+}
+
+/// # Verify with any hasher
+/// The algorithm is recognised from the stored hash.
+pub fn verify_any(password: &str, hash: &str) -> Result<bool, String> {
+    match HashAlgorithm::detect(hash) {
+        Some(HashAlgorithm::Bcrypt) => BcryptHasher::default().verify(password, hash),
+        Some(HashAlgorithm::Argon2id) => Argon2Hasher::default().verify(password, hash),
+        None => Err("Unknown password hash algorithm".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bcrypt_hasher() {
+        let hasher = BcryptHasher { cost: 4 };
+        let hash = hasher.hash("purple_dog").unwrap();
+        assert_eq!(HashAlgorithm::detect(&hash), Some(HashAlgorithm::Bcrypt));
+        assert_eq!(verify_any("purple_dog", &hash), Ok(true));
+        assert_eq!(verify_any("wrong_password", &hash), Ok(false));
+        assert!(!hasher.needs_rehash(&hash));
+        assert!(BcryptHasher { cost: 5 }.needs_rehash(&hash));
+        assert!(Argon2Hasher::default().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn test_argon2_hasher() {
+        let hasher = Argon2Hasher {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let hash = hasher.hash("purple_dog").unwrap();
+        assert_eq!(HashAlgorithm::detect(&hash), Some(HashAlgorithm::Argon2id));
+        assert_eq!(verify_any("purple_dog", &hash), Ok(true));
+        assert_eq!(verify_any("wrong_password", &hash), Ok(false));
+        assert!(!hasher.needs_rehash(&hash));
+        assert!(Argon2Hasher::default().needs_rehash(&hash));
+        assert!(BcryptHasher::default().needs_rehash(&hash));
+        assert!(verify_any("purple_dog", "purple_dog").is_err());
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::search::{fold_char, tokenize};
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+
+/// Bundled list of the most common passwords, one per line, lowercase
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// # Password policy
+///
+/// Rules a new password must follow. Lengths are counted in
+/// characters. Missing fields in the config file get the default value.
+/// ```yaml
+/// min_length: 10
+/// min_symbols: 1
+/// history_size: 10
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub min_lowercase: usize,
+    pub min_uppercase: usize,
+    pub min_digits: usize,
+    pub min_symbols: usize,
+    /// Refuse passwords from the bundled common password list
+    pub deny_common: bool,
+    /// Refuse passwords containing the user name or email
+    pub deny_personal: bool,
+    /// Refuse the last N passwords of the user, 0 disables it
+    pub history_size: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 7,
+            max_length: 128,
+            min_lowercase: 2,
+            min_uppercase: 2,
+            min_digits: 1,
+            min_symbols: 0,
+            deny_common: true,
+            deny_personal: true,
+            history_size: 5,
+        }
+    }
+}
+
+/// # Password strength
+/// Estimated entropy in bits, and a 0-4 score for displaying it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PasswordStrength {
+    pub entropy_bits: f64,
+    pub score: u8,
+    pub label: &'static str,
+}
+
+impl PasswordPolicy {
+    /// # Load policy
+    /// Load the policy from a YAML file.
+    pub fn load(path: &str) -> Result<PasswordPolicy, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Error while reading password policy {}: {}", path, err))?;
+        storage::deserialize_object(&content)
+    }
+    /// # Active policy
+    /// Loaded from the YAML file set in the `PASSWORD_POLICY`
+    /// environment variable, or the default policy.
+    pub fn active() -> Result<PasswordPolicy, String> {
+        match env::var("PASSWORD_POLICY") {
+            Ok(path) => PasswordPolicy::load(&path),
+            Err(_) => Ok(PasswordPolicy::default()),
+        }
+    }
+    /// # Validate password
+    /// `personal` is the user name, email etc. that the password cannot
+    /// contain. Returns every failed rule in the error message.
+    /// ```rust
+    /// use core_lib::user::password::policy::PasswordPolicy;
+    /// let policy = PasswordPolicy::default();
+    /// assert_eq!(policy.validate("DEmoPassWord1234789", &[]), Ok(()));
+    /// assert_eq!(policy.validate("DEmoPassWord1234789", &["Demo User"]).is_err(), true);
+    /// assert_eq!(policy.validate("PAssword1", &[]).is_err(), true);
+    /// ```
+    pub fn validate(&self, password: &str, personal: &[&str]) -> Result<(), String> {
+        let mut errors: Vec<String> = Vec::new();
+        let length = password.chars().count();
+        let count = |f: fn(&char) -> bool| password.chars().filter(f).count();
+        if length < self.min_length {
+            errors.push(format!(
+                "Password must be at least {} characters long.",
+                self.min_length
+            ));
+        }
+        if length > self.max_length {
+            errors.push(format!(
+                "Password must be at most {} characters long.",
+                self.max_length
+            ));
+        }
+        if count(|ch| ch.is_lowercase()) < self.min_lowercase {
+            errors.push(format!(
+                "Password must contain at least {} lowercase letter.",
+                self.min_lowercase
+            ));
+        }
+        if count(|ch| ch.is_uppercase()) < self.min_uppercase {
+            errors.push(format!(
+                "Password must contain at least {} uppercase letter.",
+                self.min_uppercase
+            ));
+        }
+        if count(|ch| ch.is_numeric()) < self.min_digits {
+            errors.push(format!(
+                "Password must contain at least {} number.",
+                self.min_digits
+            ));
+        }
+        if count(|ch| is_symbol(*ch)) < self.min_symbols {
+            errors.push(format!(
+                "Password must contain at least {} symbol.",
+                self.min_symbols
+            ));
+        }
+        if self.deny_common && is_common_password(password) {
+            errors.push("This password is too common.".to_owned());
+        }
+        if self.deny_personal && contains_personal(password, personal) {
+            errors.push("Password cannot contain your name or email address.".to_owned());
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(" "))
+        }
+    }
+}
+
+fn is_symbol(ch: char) -> bool {
+    !ch.is_alphanumeric() && !ch.is_whitespace()
+}
+
+/// # Is common password
+/// ```rust
+/// use core_lib::user::password::policy::is_common_password;
+/// assert_eq!(is_common_password("Password1"), true);
+/// assert_eq!(is_common_password("DEmoPassWord1234789"), false);
+/// ```
+pub fn is_common_password(password: &str) -> bool {
+    let password = password.to_lowercase();
+    COMMON_PASSWORDS.lines().any(|common| common == password)
+}
+
+/// Name parts and the email local part, shorter than 3 characters
+/// are ignored. Both sides are accent folded, as in search.
+fn contains_personal(password: &str, personal: &[&str]) -> bool {
+    let password: String = password.to_lowercase().chars().map(fold_char).collect();
+    personal
+        .iter()
+        .flat_map(|item| tokenize(item.split('@').next().unwrap_or_default()))
+        .filter(|part| part.chars().count() >= 3)
+        .any(|part| password.contains(&part))
+}
+
+/// # Password strength
+/// Entropy is estimated from the length and the used character classes.
+/// Common passwords are 0 bits.
+/// ```rust
+/// use core_lib::user::password::policy::password_strength;
+/// assert_eq!(password_strength("password").score, 0);
+/// assert_eq!(password_strength("kT7#mQ2$vL9!xR4&").score, 4);
+/// ```
+pub fn password_strength(password: &str) -> PasswordStrength {
+    let mut pool = 0;
+    if password.chars().any(|ch| ch.is_lowercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|ch| ch.is_uppercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|ch| ch.is_numeric()) {
+        pool += 10;
+    }
+    if password.chars().any(is_symbol) {
+        pool += 33;
+    }
+    if !password.is_ascii() {
+        pool += 100;
+    }
+    let entropy_bits = if pool == 0 || is_common_password(password) {
+        0.0
+    } else {
+        password.chars().count() as f64 * (pool as f64).log2()
+    };
+    let (score, label) = match entropy_bits {
+        bits if bits < 28.0 => (0, "very weak"),
+        bits if bits < 36.0 => (1, "weak"),
+        bits if bits < 60.0 => (2, "fair"),
+        bits if bits < 80.0 => (3, "strong"),
+        _ => (4, "very strong"),
+    };
+    PasswordStrength {
+        entropy_bits,
+        score,
+        label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_validate() {
+        let policy = PasswordPolicy {
+            min_length: 10,
+            min_symbols: 1,
+            ..PasswordPolicy::default()
+        };
+        // Length is counted in characters, not bytes
+        assert!(policy.validate("ÁrvíztűrőT1!", &[]).is_ok());
+        assert!(policy.validate("ÁÉíó1!", &[]).is_err());
+        assert!(policy.validate("PAssword123", &[]).is_err());
+        assert!(policy
+            .validate("KovacsPAss12!", &["Kovács Péter", "kovacs@farm.hu"])
+            .is_err());
+        assert!(policy.validate("KovacsPAss12!", &["Nagy Anna"]).is_ok());
+        // Accents do not matter, on either side
+        assert!(policy.validate("KovacsPAss12!", &["Kovács Péter"]).is_err());
+        assert!(policy.validate("PéterPAss12!", &["Peter Nagy"]).is_err());
+        let error = policy.validate("pass", &[]).unwrap_err();
+        assert!(error.contains("10 characters") && error.contains("symbol"));
+    }
+
+    #[test]
+    fn test_policy_load() {
+        let policy: PasswordPolicy =
+            storage::deserialize_object("---\nmin_length: 12\ndeny_common: false").unwrap();
+        assert_eq!(policy.min_length, 12);
+        assert!(!policy.deny_common);
+        assert_eq!(
+            policy.min_uppercase,
+            PasswordPolicy::default().min_uppercase
+        );
+        assert!(PasswordPolicy::load("../data/missing_policy.yml").is_err());
+    }
+
+    #[test]
+    fn test_password_strength() {
+        assert!(password_strength("abc").score < password_strength("abcDEF123").score);
+        assert_eq!(password_strength("").entropy_bits, 0.0);
+    }
+}
@@ -0,0 +1,526 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::address::Address;
+use crate::organization::invitation::Invitation;
+use crate::organization::model::organization_v1::OrganizationV1;
+use crate::organization::{Organization, OrganizationRole};
+use crate::storage::relation::Relation;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::api_key::{get_user_api_keys, ApiKey};
+use crate::user::lockout::{account_key, clear_failed_logins, LoginAttempts};
+use crate::user::model::user_v2::UserV2;
+use crate::user::reset::PasswordReset;
+use crate::user::role::Role;
+use crate::user::security_log::{
+    anonymise_user_events, get_user_events, record_security_event, SecurityEvent,
+    SecurityEventKind, SecurityEventOutcome,
+};
+use crate::user::session::{get_user_sessions, ClientInfo, Session};
+use crate::user::signed_token::RefreshToken;
+use crate::user::status::{AccountStatus, StatusChange};
+use crate::user::verification::EmailVerification;
+use crate::user::User;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// # User data
+/// Every storage holding data of users, borrowed for an export or
+/// an erasure.
+pub struct UserData<'a> {
+    pub users: &'a mut Storage<UserV2>,
+    pub sessions: &'a mut Storage<Session>,
+    pub email_verifications: &'a mut Storage<EmailVerification>,
+    pub password_resets: &'a mut Storage<PasswordReset>,
+    pub login_attempts: &'a mut Storage<LoginAttempts>,
+    pub api_keys: &'a mut Storage<ApiKey>,
+    pub refresh_tokens: &'a mut Storage<RefreshToken>,
+    pub security_log: &'a mut Storage<SecurityEvent>,
+    pub organizations: &'a mut Storage<OrganizationV1>,
+    pub invitations: &'a mut Storage<Invitation>,
+}
+
+#[derive(Serialize)]
+struct UserExport {
+    id: Option<String>,
+    name: Option<String>,
+    address: Option<Address>,
+    email: Option<String>,
+    email_verified: bool,
+    pending_email: Option<String>,
+    phone: Option<String>,
+    roles: Vec<Role>,
+    status: AccountStatus,
+    status_history: Vec<StatusChange>,
+    two_factor_enabled: bool,
+    created: Option<DateTime<Utc>>,
+    updated: Option<DateTime<Utc>>,
+    last_login: Option<DateTime<Utc>>,
+    password_changed: Option<DateTime<Utc>>,
+}
+
+impl UserExport {
+    fn new<T: User>(user: &T) -> Self {
+        UserExport {
+            id: user.get_user_id(),
+            name: user.get_user_name(),
+            address: user.get_user_address(),
+            email: user.get_user_email(),
+            email_verified: user.is_user_email_verified(),
+            pending_email: user.get_user_pending_email(),
+            phone: user.get_user_phone(),
+            roles: user.get_user_roles(),
+            status: user.get_user_status(),
+            status_history: user.get_user_status_history(),
+            two_factor_enabled: user.get_user_totp().is_some_and(|totp| totp.enabled),
+            created: user.get_user_created(),
+            updated: user.get_user_updated(),
+            last_login: user.get_user_last_login(),
+            password_changed: user.get_user_password_changed(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SessionExport {
+    session_id: String,
+    created: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    client: ClientInfo,
+}
+
+#[derive(Serialize)]
+struct EventExport {
+    time: DateTime<Utc>,
+    kind: SecurityEventKind,
+    outcome: SecurityEventOutcome,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    detail: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ApiKeyExport {
+    name: String,
+    prefix: String,
+    created: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    last_used: Option<DateTime<Utc>>,
+    revoked: bool,
+}
+
+#[derive(Serialize)]
+struct MembershipExport {
+    organization_id: Option<String>,
+    organization_name: Option<String>,
+    role: OrganizationRole,
+    joined: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct InvitationExport {
+    organization_id: String,
+    email: String,
+    role: OrganizationRole,
+    accepted: bool,
+}
+
+/// # Data export
+///
+/// Named JSON files collected for a zip archive.
+/// ```rust
+/// use core_lib::user::privacy::DataExport;
+/// let mut export = DataExport::new();
+/// export.add_json("numbers.json", &vec![1, 2, 3]).unwrap();
+/// assert_eq!(export.get_file_names(), vec!["numbers.json"]);
+/// assert!(export.to_zip().unwrap().starts_with(b"PK"));
+/// ```
+#[derive(Default)]
+pub struct DataExport {
+    files: Vec<(String, String)>,
+}
+
+impl DataExport {
+    pub fn new() -> Self {
+        DataExport { files: Vec::new() }
+    }
+    pub fn add_json<T: Serialize>(&mut self, name: &str, data: &T) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(data)
+            .map_err(|_| format!("Error while serializing {}", name))?;
+        self.files.push((name.to_owned(), json));
+        Ok(())
+    }
+    /// # Add authored content
+    /// Every S object referring to the user through `relation`.
+    pub fn add_referencing<S, T>(
+        &mut self,
+        name: &str,
+        source: &Storage<S>,
+        relation: &Relation<S, T>,
+        user_id: &str,
+    ) -> Result<(), String>
+    where
+        S: StorageObject + Serialize,
+    {
+        let objects: Vec<&S> = relation
+            .referencing_ids(source, user_id)
+            .iter()
+            .filter_map(|id| storage::get_by_id(source, id))
+            .collect();
+        self.add_json(name, &objects)
+    }
+    pub fn get_file_names(&self) -> Vec<&str> {
+        self.files.iter().map(|(name, _)| name.as_str()).collect()
+    }
+    pub fn to_zip(&self) -> Result<Vec<u8>, String> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, json) in &self.files {
+            zip.start_file(name.as_str(), options)
+                .map_err(|_| format!("Error while adding {} to the zip", name))?;
+            zip.write_all(json.as_bytes())
+                .map_err(|_| format!("Error while writing {} to the zip", name))?;
+        }
+        match zip.finish() {
+            Ok(cursor) => Ok(cursor.into_inner()),
+            Err(_) => Err("Error while finishing the zip".to_owned()),
+        }
+    }
+}
+
+/// # Export user data
+///
+/// Collects the user record, sessions, security events, API keys,
+/// organization memberships and sent invitations. Add authored content
+/// of other storages with `DataExport::add_referencing`, then call
+/// `to_zip`.
+pub fn export_user_data(data: &UserData, user_id: &str) -> Result<DataExport, String> {
+    let user = match storage::get_by_id(data.users, user_id) {
+        Some(user) => user,
+        None => return Err(format!("User {} not found.", user_id)),
+    };
+    let mut export = DataExport::new();
+    export.add_json("user.json", &UserExport::new(user))?;
+    let sessions: Vec<SessionExport> = get_user_sessions(data.sessions, user_id)
+        .iter()
+        .map(|session| SessionExport {
+            session_id: session.get_session_id(),
+            created: session.get_created(),
+            last_seen: session.get_last_seen(),
+            client: session.get_client().clone(),
+        })
+        .collect();
+    export.add_json("sessions.json", &sessions)?;
+    let events: Vec<EventExport> = get_user_events(data.security_log, user_id, usize::MAX)
+        .iter()
+        .map(|event| EventExport {
+            time: event.get_time(),
+            kind: event.get_kind(),
+            outcome: event.get_outcome(),
+            ip: event.get_ip().map(|ip| ip.to_owned()),
+            user_agent: event.get_user_agent().map(|agent| agent.to_owned()),
+            detail: event.get_detail().map(|detail| detail.to_owned()),
+        })
+        .collect();
+    export.add_json("security_events.json", &events)?;
+    let api_keys: Vec<ApiKeyExport> = get_user_api_keys(data.api_keys, user_id)
+        .iter()
+        .map(|key| ApiKeyExport {
+            name: key.get_name().to_owned(),
+            prefix: key.get_prefix().to_owned(),
+            created: key.get_created(),
+            expires: key.get_expires(),
+            last_used: key.get_last_used(),
+            revoked: key.is_revoked(),
+        })
+        .collect();
+    export.add_json("api_keys.json", &api_keys)?;
+    let memberships: Vec<MembershipExport> = data
+        .organizations
+        .data
+        .iter()
+        .flat_map(|organization| {
+            organization
+                .get_members()
+                .into_iter()
+                .filter(|member| member.user.get_id() == user_id)
+                .map(move |member| MembershipExport {
+                    organization_id: organization.get_organization_id(),
+                    organization_name: organization.get_organization_name(),
+                    role: member.role,
+                    joined: member.joined,
+                })
+        })
+        .collect();
+    export.add_json("organizations.json", &memberships)?;
+    let invitations: Vec<InvitationExport> = data
+        .invitations
+        .data
+        .iter()
+        .filter(|invitation| invitation.get_invited_by().get_id() == user_id)
+        .map(|invitation| InvitationExport {
+            organization_id: invitation.get_organization().get_id().to_owned(),
+            email: invitation.get_email().to_owned(),
+            role: invitation.get_role(),
+            accepted: invitation.is_accepted(),
+        })
+        .collect();
+    export.add_json("invitations.json", &invitations)?;
+    Ok(export)
+}
+
+/// # Erase user
+///
+/// Removes the personal data of the user from every storage:
+/// - sessions, API keys, refresh tokens, email verifications and
+///   password resets of the user are deleted,
+/// - failed logins and invitations to the user email are deleted,
+/// - organization memberships are removed,
+/// - security events are kept for audit, but anonymised,
+/// - the user record is anonymised and set to Deleted.
+///
+/// The user ID is kept, so content referring to the user (e.g. sent
+/// invitations, content owned by the user) stays valid. The last owner
+/// of an organization with other members cannot be erased, ownership
+/// must be handed over first. An AccountErased event is recorded
+/// without client info.
+pub fn erase_user(data: &mut UserData, user_id: &str) -> Result<(), String> {
+    let email = match storage::get_by_id(data.users, user_id) {
+        Some(user) => user.get_user_email(),
+        None => return Err(format!("User {} not found.", user_id)),
+    };
+    // Checked before anything is deleted, so a refused erasure changes nothing
+    let membership = OrganizationV1::user_relation();
+    membership.check_delete(data.organizations, user_id)?;
+
+    Relation::cascade(|session: &Session| vec![session.get_user()])
+        .apply_delete(data.sessions, user_id)?;
+    Relation::cascade(|key: &ApiKey| vec![key.get_user()]).apply_delete(data.api_keys, user_id)?;
+    Relation::cascade(|token: &RefreshToken| vec![token.get_user()])
+        .apply_delete(data.refresh_tokens, user_id)?;
+    Relation::cascade(|verification: &EmailVerification| vec![verification.get_user()])
+        .apply_delete(data.email_verifications, user_id)?;
+    Relation::cascade(|reset: &PasswordReset| vec![reset.get_user()])
+        .apply_delete(data.password_resets, user_id)?;
+    membership.apply_delete(data.organizations, user_id)?;
+
+    if let Some(email) = &email {
+        clear_failed_logins(data.login_attempts, &account_key(email))?;
+        let invitation_ids: Vec<String> = data
+            .invitations
+            .data
+            .iter()
+            .filter(|invitation| invitation.get_email().eq_ignore_ascii_case(email))
+            .filter_map(|invitation| invitation.get_id().map(|id| id.to_owned()))
+            .collect();
+        for id in invitation_ids {
+            storage::remove_from_storage(data.invitations, &id)?;
+        }
+    }
+    anonymise_user_events(data.security_log, user_id, email.as_deref())?;
+
+    if let Some(user) = storage::get_mut_by_id(data.users, user_id) {
+        user.anonymise();
+        user.save()?;
+    }
+    record_security_event(
+        data.security_log,
+        SecurityEvent::new(
+            SecurityEventKind::AccountErased,
+            SecurityEventOutcome::Success,
+            Some(user_id),
+            &ClientInfo::default(),
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::New;
+    use crate::user::api_key::create_api_key;
+    use crate::user::lockout::record_failed_login;
+    use std::io::Read;
+
+    struct Storages {
+        users: Storage<UserV2>,
+        sessions: Storage<Session>,
+        email_verifications: Storage<EmailVerification>,
+        password_resets: Storage<PasswordReset>,
+        login_attempts: Storage<LoginAttempts>,
+        api_keys: Storage<ApiKey>,
+        refresh_tokens: Storage<RefreshToken>,
+        security_log: Storage<SecurityEvent>,
+        organizations: Storage<OrganizationV1>,
+        invitations: Storage<Invitation>,
+    }
+
+    impl Storages {
+        fn load() -> Self {
+            Storages {
+                users: storage::load_storage("../data/privacy_users").unwrap(),
+                sessions: storage::load_storage("../data/privacy_sessions").unwrap(),
+                email_verifications: storage::load_storage("../data/privacy_verifications")
+                    .unwrap(),
+                password_resets: storage::load_storage("../data/privacy_resets").unwrap(),
+                login_attempts: storage::load_storage("../data/privacy_attempts").unwrap(),
+                api_keys: storage::load_storage("../data/privacy_api_keys").unwrap(),
+                refresh_tokens: storage::load_storage("../data/privacy_refresh_tokens").unwrap(),
+                security_log: storage::load_storage("../data/privacy_security_log").unwrap(),
+                organizations: storage::load_storage("../data/privacy_organizations").unwrap(),
+                invitations: storage::load_storage("../data/privacy_invitations").unwrap(),
+            }
+        }
+        fn data(&mut self) -> UserData<'_> {
+            UserData {
+                users: &mut self.users,
+                sessions: &mut self.sessions,
+                email_verifications: &mut self.email_verifications,
+                password_resets: &mut self.password_resets,
+                login_attempts: &mut self.login_attempts,
+                api_keys: &mut self.api_keys,
+                refresh_tokens: &mut self.refresh_tokens,
+                security_log: &mut self.security_log,
+                organizations: &mut self.organizations,
+                invitations: &mut self.invitations,
+            }
+        }
+        fn remove(&self) {
+            self.users.remove();
+            self.sessions.remove();
+            self.email_verifications.remove();
+            self.password_resets.remove();
+            self.login_attempts.remove();
+            self.api_keys.remove();
+            self.refresh_tokens.remove();
+            self.security_log.remove();
+            self.organizations.remove();
+            self.invitations.remove();
+        }
+    }
+
+    fn add_user(storages: &mut Storages, id: &str, email: &str) {
+        let mut user = UserV2::new();
+        user.set_user_id(id).unwrap();
+        user.set_user_name("Kovacs Janos").unwrap();
+        user.set_user_email(email).unwrap();
+        user.set_password("SEcretPassWord1234789").unwrap();
+        storage::add_to_storage(&mut storages.users, user).unwrap();
+    }
+
+    #[test]
+    fn test_privacy() {
+        let mut storages = Storages::load();
+        add_user(&mut storages, "kovacs_janos", "janos@farm.com");
+        add_user(&mut storages, "other_user", "other@farm.com");
+        let client = ClientInfo {
+            ip: Some("127.0.0.1".to_owned()),
+            user_agent: Some("Firefox".to_owned()),
+        };
+        storage::add_to_storage(
+            &mut storages.sessions,
+            Session::new("token_a", "kovacs_janos").with_client(&client),
+        )
+        .unwrap();
+        storage::add_to_storage(
+            &mut storages.sessions,
+            Session::new("token_b", "other_user"),
+        )
+        .unwrap();
+        let user = storage::get_by_id(&storages.users, "kovacs_janos").unwrap();
+        create_api_key(&mut storages.api_keys, user, "Sensor", &[], None).unwrap();
+        record_security_event(
+            &mut storages.security_log,
+            SecurityEvent::new(
+                SecurityEventKind::Login,
+                SecurityEventOutcome::Success,
+                Some("kovacs_janos"),
+                &client,
+            ),
+        )
+        .unwrap();
+        record_failed_login(
+            &mut storages.login_attempts,
+            &account_key("janos@farm.com"),
+            5,
+        )
+        .unwrap();
+        let mut organization = OrganizationV1::new();
+        organization.set_organization_id("kovacs_farm").unwrap();
+        organization
+            .add_member("kovacs_janos", OrganizationRole::Owner)
+            .unwrap();
+        organization
+            .add_member("other_user", OrganizationRole::Member)
+            .unwrap();
+        storage::add_to_storage(&mut storages.organizations, organization).unwrap();
+
+        // Export
+        let export = export_user_data(&storages.data(), "kovacs_janos").unwrap();
+        let zip = export.to_zip().unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), 6);
+        let mut user_json = String::new();
+        archive
+            .by_name("user.json")
+            .unwrap()
+            .read_to_string(&mut user_json)
+            .unwrap();
+        assert!(user_json.contains("janos@farm.com"));
+        assert!(!user_json.contains("password_hash"));
+        let mut sessions_json = String::new();
+        archive
+            .by_name("sessions.json")
+            .unwrap()
+            .read_to_string(&mut sessions_json)
+            .unwrap();
+        assert!(sessions_json.contains("Firefox"));
+        assert!(!sessions_json.contains("token_a"));
+
+        // The last owner cannot be erased
+        assert!(erase_user(&mut storages.data(), "kovacs_janos").is_err());
+        storages.organizations.data[0]
+            .set_member_role("other_user", OrganizationRole::Owner)
+            .unwrap();
+
+        erase_user(&mut storages.data(), "kovacs_janos").unwrap();
+        let user = storage::get_by_id(&storages.users, "kovacs_janos").unwrap();
+        assert_eq!(user.get_user_status(), AccountStatus::Deleted);
+        assert_eq!(user.get_user_email(), None);
+        assert_eq!(user.get_password_hash(), None);
+        assert_eq!(storages.sessions.data.len(), 1);
+        assert!(storages.api_keys.data.is_empty());
+        assert!(storages.login_attempts.data.is_empty());
+        assert_eq!(
+            storages.organizations.data[0].get_member_role("kovacs_janos"),
+            None
+        );
+        assert_eq!(storages.security_log.data[0].get_ip(), None);
+        assert_eq!(
+            storages.security_log.data[1].get_kind(),
+            SecurityEventKind::AccountErased
+        );
+        assert!(storage::get_by_id(&storages.users, "other_user")
+            .unwrap()
+            .get_user_email()
+            .is_some());
+        storages.remove();
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::address::{Address, GeoLocation};
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::registration::find_user_by_email;
+use crate::user::User;
+use crate::validation::{self, ValidationErrors};
+
+/// # Profile update
+/// User fields as entered in the profile form. Empty phone and
+/// address are left unchanged, the country alone is not an address.
+/// Location is optional "latitude, longitude" text.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileUpdate {
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    pub country: String,
+    pub postal_code: String,
+    pub city: String,
+    pub street: String,
+    pub location: String,
+}
+
+impl ProfileUpdate {
+    fn has_address(&self) -> bool {
+        [&self.postal_code, &self.city, &self.street, &self.location]
+            .iter()
+            .any(|field| !field.trim().is_empty())
+    }
+    /// Country for national phone numbers, the address country if set
+    fn phone_country(&self) -> &str {
+        if self.country.trim().is_empty() {
+            validation::DEFAULT_PHONE_COUNTRY
+        } else {
+            self.country.trim()
+        }
+    }
+}
+
+/// Phone number in E.164 format and address, if they are given
+fn parse_profile(
+    profile: &ProfileUpdate,
+    errors: &mut ValidationErrors,
+) -> (Option<String>, Option<Address>) {
+    errors.check("name", validation::validate_user_name(&profile.name));
+    errors.check("email", validation::normalize_email(&profile.email));
+    let phone = if profile.phone.trim().is_empty() {
+        None
+    } else {
+        errors.check(
+            "phone",
+            validation::normalize_phone(&profile.phone, profile.phone_country()),
+        )
+    };
+    if !profile.has_address() {
+        return (phone, None);
+    }
+    let location = if profile.location.trim().is_empty() {
+        None
+    } else {
+        errors.check("location", GeoLocation::parse(&profile.location))
+    };
+    let address = match Address::new(
+        &profile.country,
+        &profile.postal_code,
+        &profile.city,
+        &profile.street,
+    ) {
+        Ok(address) => Some(match location {
+            Some(location) => address.with_location(location),
+            None => address,
+        }),
+        Err(address_errors) => {
+            errors.merge(address_errors);
+            None
+        }
+    };
+    (phone, address)
+}
+
+/// # Validate profile
+/// Checks every field, and returns all the errors at once.
+pub fn validate_profile(profile: &ProfileUpdate) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    parse_profile(profile, &mut errors);
+    errors
+}
+
+/// # Update profile
+/// The user is changed and saved only if every field is valid. A
+/// changed email address of a verified user is pending until it is
+/// verified. The email address cannot be the address of another user.
+/// National phone numbers are taken as numbers of the address
+/// country.
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::storage;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::profile::{update_profile, ProfileUpdate};
+/// use core_lib::user::User;
+/// let mut users = storage::load_storage::<UserV2>("../data/doc_update_profile").unwrap();
+/// for (id, email) in &[("demo_user", "demo@user.com"), ("other_user", "other@user.com")] {
+///     let mut user = UserV2::new();
+///     user.set_user_id(id).unwrap();
+///     user.set_user_email(email).unwrap();
+///     storage::add_to_storage(&mut users, user).unwrap();
+/// }
+/// let mut profile = ProfileUpdate {
+///     name: "Demo User".to_owned(),
+///     email: "wohoo".to_owned(),
+///     phone: "123".to_owned(),
+///     ..ProfileUpdate::default()
+/// };
+/// let errors = update_profile(&mut users, "demo_user", &profile).unwrap_err();
+/// assert_eq!(errors.by_field().len(), 2);
+/// profile.email = "Other@User.com".to_owned();
+/// profile.phone = String::new();
+/// let errors = update_profile(&mut users, "demo_user", &profile).unwrap_err();
+/// assert!(errors.get("email").is_some());
+/// profile.email = "demo@User.com".to_owned();
+/// profile.phone = "0664 123 4567".to_owned();
+/// profile.country = "AT".to_owned();
+/// profile.postal_code = "1010".to_owned();
+/// profile.city = "Wien".to_owned();
+/// profile.street = "Stephansplatz 1".to_owned();
+/// assert_eq!(update_profile(&mut users, "demo_user", &profile), Ok(()));
+/// let user = storage::get_by_id(&users, "demo_user").unwrap();
+/// assert_eq!(user.get_user_name(), Some("Demo User".to_owned()));
+/// assert_eq!(user.get_user_phone(), Some("+436641234567".to_owned()));
+/// assert_eq!(user.get_user_address().unwrap().get_city(), "Wien");
+/// users.remove();
+/// ```
+pub fn update_profile<T>(
+    users: &mut Storage<T>,
+    user_id: &str,
+    profile: &ProfileUpdate,
+) -> Result<(), ValidationErrors>
+where
+    T: User + StorageObject,
+{
+    let mut errors = ValidationErrors::new();
+    let (phone, address) = parse_profile(profile, &mut errors);
+    if let Ok(email) = validation::normalize_email(&profile.email) {
+        let taken = find_user_by_email(users, &email)
+            .is_some_and(|owner| owner.get_user_id().as_deref() != Some(user_id));
+        if taken {
+            errors.add("email", "This email address is already in use.");
+        }
+    }
+    errors.into_result()?;
+    let mut errors = ValidationErrors::new();
+    let user = match storage::get_mut_by_id(users, user_id) {
+        Some(user) => user,
+        None => {
+            errors.add("", "User not found.");
+            return Err(errors);
+        }
+    };
+    errors.check("name", user.set_user_name(&profile.name));
+    errors.check("email", user.set_user_email(&profile.email));
+    if let Some(phone) = phone {
+        errors.check("phone", user.set_user_phone(&phone));
+    }
+    if let Some(address) = address {
+        errors.check("street", user.set_user_address(address));
+    }
+    errors.into_result()?;
+    user.save().map_err(|msg| {
+        let mut errors = ValidationErrors::new();
+        errors.add("", &msg);
+        errors
+    })
+}
@@ -0,0 +1,373 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email;
+use crate::prelude::*;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::password::validate_password;
+use crate::user::token::{generate_token_with_length, hash_token};
+use crate::user::verification::{self, EmailVerification, VERIFICATION_VALID_HOURS};
+use crate::user::User;
+use crate::validation::ValidationErrors;
+
+/// Length of the random part of generated user IDs
+const USER_ID_RANDOM_LENGTH: usize = 16;
+
+/// # Registration
+/// Fields of the registration form.
+#[derive(Debug, Clone, Default)]
+pub struct Registration {
+    pub name: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// # Generate user ID
+/// Opaque random ID, like `user_x7k2qa0m4bzt9c1e`. It is kept when
+/// the user is erased, so it must not tell anything about the user.
+/// It is unique in `users`.
+fn generate_user_id<T: StorageObject>(users: &Storage<T>) -> Result<String, String> {
+    loop {
+        let random: String = generate_token_with_length(USER_ID_RANDOM_LENGTH * 2)?
+            .to_lowercase()
+            .chars()
+            .filter(|ch| ch.is_ascii_alphanumeric())
+            .take(USER_ID_RANDOM_LENGTH)
+            .collect();
+        if random.len() < USER_ID_RANDOM_LENGTH {
+            continue;
+        }
+        let id = format!("user_{}", random);
+        if storage::get_by_id(users, &id).is_none() {
+            return Ok(id);
+        }
+    }
+}
+
+/// # Find user by email
+/// User whose current email is `email`, case insensitive. Pending
+/// emails do not count, anyone can enter any address until it is
+/// verified, so they cannot block the address for its owner.
+pub(crate) fn find_user_by_email<'a, T: User>(users: &'a Storage<T>, email: &str) -> Option<&'a T> {
+    users.data.iter().find(|user| {
+        user.get_user_email()
+            .is_some_and(|user_email| user_email.eq_ignore_ascii_case(email))
+    })
+}
+
+/// New user from the registration, without ID. Field errors are
+/// added to `errors`.
+fn parse_registration<T: User + New>(
+    registration: &Registration,
+    errors: &mut ValidationErrors,
+) -> T {
+    let mut user = T::new();
+    errors.check("name", user.set_user_name(&registration.name));
+    errors.check("email", user.set_user_email(&registration.email));
+    if errors
+        .check("password", validate_password(&registration.password))
+        .is_some()
+    {
+        // Also checks that the password does not contain the name or email
+        errors.check("password", user.set_password(&registration.password));
+    }
+    user
+}
+
+/// Save the new user with a generated ID
+fn add_user<T>(users: &mut Storage<T>, mut user: T) -> Result<String, ValidationErrors>
+where
+    T: User + StorageObject,
+{
+    let user_id = generate_user_id(users).map_err(form_error)?;
+    user.set_user_id(&user_id).map_err(form_error)?;
+    storage::add_to_storage(users, user).map_err(form_error)?;
+    Ok(user_id)
+}
+
+/// # Create user
+///
+/// Validates the registration, and saves the new user pending email
+/// verification. Returns the generated user ID, or every field error
+/// at once.
+/// ```rust
+/// use core_lib::storage;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::registration::{create_user, Registration};
+/// let mut users = storage::load_storage::<UserV2>("../data/doc_create_user").unwrap();
+/// let registration = Registration {
+///     name: "Kovács Péter".to_owned(),
+///     email: "peter@example.com".to_owned(),
+///     password: "DEmoPassWord1234789".to_owned(),
+/// };
+/// let user_id = create_user(&mut users, &registration).unwrap();
+/// assert_eq!(user_id.starts_with("user_"), true);
+/// let errors = create_user(&mut users, &registration).unwrap_err();
+/// assert_eq!(errors.get("email").is_some(), true);
+/// users.remove();
+/// ```
+pub fn create_user<T>(
+    users: &mut Storage<T>,
+    registration: &Registration,
+) -> Result<String, ValidationErrors>
+where
+    T: User + StorageObject + New,
+{
+    let mut errors = ValidationErrors::new();
+    let user: T = parse_registration(registration, &mut errors);
+    if let Some(email) = user.get_user_email() {
+        if find_user_by_email(users, &email).is_some() {
+            errors.add("email", "This email address is already registered.");
+        }
+    }
+    errors.into_result()?;
+    add_user(users, user)
+}
+
+/// Error not related to a form field
+fn form_error(message: String) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add("", &message);
+    errors
+}
+
+/// # Send welcome email
+/// `link` is the full verification URL containing the token.
+pub fn send_welcome_email(to: &str, name: &str, link: &str) -> Result<(), String> {
+    email::send_email_from_env(
+        to,
+        name,
+        "Welcome! Please verify your email address",
+        &format!(
+            "Hi {}! Thank you for registering. To activate your account, \
+             please confirm your email address by visiting: {}\n\
+             The link is valid for {} hours.",
+            name, link, VERIFICATION_VALID_HOURS
+        ),
+    )
+}
+
+/// # Send already registered email
+/// Sent instead of the welcome email, when someone registers with the
+/// address of an existing account.
+pub fn send_already_registered_email(to: &str, name: &str) -> Result<(), String> {
+    email::send_email_from_env(
+        to,
+        name,
+        "You already have an account",
+        &format!(
+            "Hi {}! Someone tried to register with this email address, but \
+             you already have an account. If it was you, please log in, or \
+             reset your password if you forgot it. Otherwise you can ignore \
+             this email.",
+            name
+        ),
+    )
+}
+
+/// # Registration email
+/// Email to send after `register`, once the storages are unlocked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistrationEmail {
+    /// New user, with the verification token
+    Welcome {
+        user_id: String,
+        email: String,
+        name: String,
+        token: String,
+    },
+    /// The address belongs to an existing user
+    AlreadyRegistered { email: String, name: String },
+}
+
+impl RegistrationEmail {
+    /// # Send registration email
+    /// `link_base` is the verification URL, the token is appended
+    /// to it.
+    pub fn send(&self, link_base: &str) -> Result<(), String> {
+        match self {
+            RegistrationEmail::Welcome {
+                email, name, token, ..
+            } => send_welcome_email(email, name, &format!("{}{}", link_base, token)),
+            RegistrationEmail::AlreadyRegistered { email, name } => {
+                send_already_registered_email(email, name)
+            }
+        }
+    }
+}
+
+/// # Register
+///
+/// Creates the user pending email verification, with its verification
+/// link. If the email address is already registered, nothing is
+/// created, but the result is the same, so the form does not tell
+/// which addresses have an account. The owner of the address gets an
+/// email about it instead.
+///
+/// The email is not sent here, so the storages do not have to be
+/// locked while talking to the mail server. Send it with
+/// `RegistrationEmail::send`, and call `cancel_registration` if it
+/// cannot be sent, so the registration can be retried.
+pub fn register<T>(
+    users: &mut Storage<T>,
+    verifications: &mut Storage<EmailVerification>,
+    registration: &Registration,
+) -> Result<RegistrationEmail, ValidationErrors>
+where
+    T: User + StorageObject + New,
+{
+    let mut errors = ValidationErrors::new();
+    let user: T = parse_registration(registration, &mut errors);
+    errors.into_result()?;
+    let email = user.get_user_email().unwrap_or_default();
+    if let Some(owner) = find_user_by_email(users, &email) {
+        return Ok(RegistrationEmail::AlreadyRegistered {
+            name: owner.get_user_name().unwrap_or_else(|| email.clone()),
+            email,
+        });
+    }
+    let name = user.get_user_name().unwrap_or_default();
+    let user_id = add_user(users, user)?;
+    let token = match storage::get_by_id(users, &user_id) {
+        Some(user) => verification::create_email_verification(verifications, user),
+        None => Err("User not found.".to_owned()),
+    };
+    match token {
+        Ok(token) => Ok(RegistrationEmail::Welcome {
+            user_id,
+            email,
+            name,
+            token,
+        }),
+        Err(msg) => {
+            let _ = storage::remove_from_storage(users, &user_id);
+            Err(form_error(msg))
+        }
+    }
+}
+
+/// # Cancel registration
+/// Remove the user and the verification link created by `register`,
+/// when the welcome email could not be sent.
+pub fn cancel_registration<T>(
+    users: &mut Storage<T>,
+    verifications: &mut Storage<EmailVerification>,
+    registration_email: &RegistrationEmail,
+) -> Result<(), String>
+where
+    T: User + StorageObject,
+{
+    if let RegistrationEmail::Welcome { user_id, token, .. } = registration_email {
+        storage::remove_from_storage(verifications, &hash_token(token))?;
+        storage::remove_from_storage(users, user_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::model::user_v2::UserV2;
+    use crate::user::status::AccountStatus;
+
+    #[test]
+    fn test_create_user() {
+        let mut users = storage::load_storage::<UserV2>("../data/registration_users").unwrap();
+        let errors = create_user(
+            &mut users,
+            &Registration {
+                name: "abc".to_owned(),
+                email: "wohoo".to_owned(),
+                password: "short".to_owned(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(errors.by_field().len(), 3);
+
+        // Password cannot contain the name
+        let mut registration = Registration {
+            name: "Árvíz Tűrő".to_owned(),
+            email: " Demo@Example.com ".to_owned(),
+            password: "DEmoPassWord1234789Árvíz Tűrő".to_owned(),
+        };
+        let errors = create_user(&mut users, &registration).unwrap_err();
+        assert!(errors.get("password").is_some());
+        assert_eq!(users.data.len(), 0);
+
+        registration.password = "SEcretPassWord1234789".to_owned();
+        let user_id = create_user(&mut users, &registration).unwrap();
+        // The ID tells nothing about the user
+        assert!(user_id.starts_with("user_"));
+        assert!(!user_id.contains("arviz"));
+        let user = storage::get_by_id(&users, &user_id).unwrap();
+        assert_eq!(user.get_user_email(), Some("Demo@example.com".to_owned()));
+        assert_eq!(user.get_user_status(), AccountStatus::PendingVerification);
+        assert!(!user.is_user_email_verified());
+
+        // Email is unique, case insensitive
+        registration.email = "demo@EXAMPLE.com".to_owned();
+        let errors = create_user(&mut users, &registration).unwrap_err();
+        assert_eq!(
+            errors.get("email"),
+            Some("This email address is already registered.")
+        );
+        users.remove();
+    }
+
+    #[test]
+    fn test_register() {
+        let mut users = storage::load_storage::<UserV2>("../data/registration_register").unwrap();
+        let mut verifications =
+            storage::load_storage::<EmailVerification>("../data/registration_verifications")
+                .unwrap();
+        std::env::remove_var("E_CLIENT");
+        let mut registration = Registration {
+            name: "Demo User".to_owned(),
+            email: "demo@example.com".to_owned(),
+            password: "SEcretPassWord1234789".to_owned(),
+        };
+        let email = register(&mut users, &mut verifications, &registration).unwrap();
+        assert_eq!(users.data.len(), 1);
+        assert_eq!(verifications.data.len(), 1);
+        // Email settings are missing, so nothing is kept
+        assert!(email.send("/verify_email/").is_err());
+        cancel_registration(&mut users, &mut verifications, &email).unwrap();
+        assert_eq!(users.data.len(), 0);
+        assert_eq!(verifications.data.len(), 0);
+
+        // A registered address gets the same result, without a new user
+        register(&mut users, &mut verifications, &registration).unwrap();
+        registration.name = "Other User".to_owned();
+        registration.email = "Demo@Example.com".to_owned();
+        assert_eq!(
+            register(&mut users, &mut verifications, &registration),
+            Ok(RegistrationEmail::AlreadyRegistered {
+                email: "Demo@example.com".to_owned(),
+                name: "Demo User".to_owned(),
+            })
+        );
+        assert_eq!(users.data.len(), 1);
+        // Field errors are still shown
+        registration.password = "short".to_owned();
+        assert!(register(&mut users, &mut verifications, &registration)
+            .unwrap_err()
+            .get("password")
+            .is_some());
+        users.remove();
+        verifications.remove();
+    }
+}
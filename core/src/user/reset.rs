@@ -0,0 +1,297 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email;
+use crate::storage::relation::Ref;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::login::logout_user;
+use crate::user::model::user_v2::UserV2;
+use crate::user::session::Session;
+use crate::user::signed_token::{revoke_refresh_tokens, RefreshToken};
+use crate::user::token::{generate_token, hash_token};
+use crate::user::User;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Reset link is valid for this many minutes
+pub const RESET_VALID_MINUTES: i64 = 60;
+
+/// # Password reset
+///
+/// Single-use, time-limited reset token. The ID is the hash of the
+/// token, the token itself is only in the email link.
+#[derive(Serialize, Deserialize)]
+pub struct PasswordReset {
+    id: String,
+    path: Option<String>,
+    user: Ref<UserV2>,
+    created: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    used: Option<DateTime<Utc>>,
+}
+
+impl PasswordReset {
+    pub fn get_user(&self) -> &Ref<UserV2> {
+        &self.user
+    }
+    pub fn is_used(&self) -> bool {
+        self.used.is_some()
+    }
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires
+    }
+}
+
+impl StorageObject for PasswordReset {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Create password reset
+/// Returns the reset token, use it in the reset link.
+pub fn create_password_reset<T: User>(
+    resets: &mut Storage<PasswordReset>,
+    user: &T,
+) -> Result<String, String> {
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err("User ID is not set.".to_owned()),
+    };
+    let token = generate_token()?;
+    let created = Utc::now();
+    storage::add_to_storage(
+        resets,
+        PasswordReset {
+            id: hash_token(&token),
+            path: None,
+            user: Ref::new(&user_id),
+            created,
+            expires: created + Duration::minutes(RESET_VALID_MINUTES),
+            used: None,
+        },
+    )?;
+    Ok(token)
+}
+
+/// # Request password reset
+///
+/// Find the user by email, create a reset token and email the link.
+/// `link_base` is the reset URL, the token is appended to it.
+/// Unknown email addresses are not an error, so the reset form does
+/// not tell which email addresses are registered.
+pub fn request_password_reset<T: User>(
+    resets: &mut Storage<PasswordReset>,
+    users: &Storage<T>,
+    email: &str,
+    link_base: &str,
+) -> Result<(), String> {
+    let user = match users.data.iter().find(|user| {
+        user.get_user_email()
+            .is_some_and(|user_email| user_email.eq_ignore_ascii_case(email.trim()))
+    }) {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let token = create_password_reset(resets, user)?;
+    let name = user.get_user_name().unwrap_or_default();
+    email::send_email_from_env(
+        email.trim(),
+        &name,
+        "Password reset",
+        &format!(
+            "Hi {}! To choose a new password, please visit: {}{}\n\
+             The link is valid for {} minutes. If you did not ask for a \
+             password reset, you can ignore this email.",
+            name, link_base, token, RESET_VALID_MINUTES
+        ),
+    )
+}
+
+/// # Reset password
+///
+/// Set the new password using a valid reset token. The password is
+/// validated by `set_password`. Every reset link, session and refresh
+/// token of the user is invalidated. Returns the user ID.
+pub fn reset_password<T>(
+    resets: &mut Storage<PasswordReset>,
+    users: &mut Storage<T>,
+    sessions: &mut Storage<Session>,
+    refresh_tokens: &mut Storage<RefreshToken>,
+    token: &str,
+    new_password: &str,
+) -> Result<String, String>
+where
+    T: User + StorageObject,
+{
+    let user_id = match storage::get_by_id(resets, &hash_token(token)) {
+        Some(reset) if reset.is_used() => {
+            return Err("This password reset link is already used.".to_owned())
+        }
+        Some(reset) if reset.is_expired() => {
+            return Err("This password reset link is expired.".to_owned())
+        }
+        Some(reset) => reset.user.get_id().to_owned(),
+        None => return Err("Invalid password reset link.".to_owned()),
+    };
+    let user = match storage::get_mut_by_id(users, &user_id) {
+        Some(user) => user,
+        None => return Err("User not found.".to_owned()),
+    };
+    user.set_password(new_password)?;
+    user.save()?;
+    let now = Utc::now();
+    for reset in resets
+        .data
+        .iter_mut()
+        .filter(|reset| reset.user.get_id() == user_id && !reset.is_used())
+    {
+        reset.used = Some(now);
+        reset.save()?;
+    }
+    logout_user(sessions, &user_id)?;
+    revoke_refresh_tokens(refresh_tokens, &user_id)?;
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::lockout::LoginAttempts;
+    use crate::user::login::{login, validate_access_token, LoginOutcome};
+    use crate::user::security_log::SecurityEvent;
+    use crate::user::session::ClientInfo;
+    use crate::user::signed_token::{
+        issue_token_pair, refresh_access_token, SigningKey, TokenKeys,
+    };
+    use crate::user::status::AccountStatus;
+
+    #[test]
+    fn test_reset_password() {
+        let mut users = storage::load_storage::<UserV2>("../data/reset_users").unwrap();
+        let mut sessions = storage::load_storage::<Session>("../data/reset_sessions").unwrap();
+        let mut resets = storage::load_storage::<PasswordReset>("../data/reset_tokens").unwrap();
+        let mut attempts =
+            storage::load_storage::<LoginAttempts>("../data/reset_attempts").unwrap();
+        let mut refresh_tokens =
+            storage::load_storage::<RefreshToken>("../data/reset_refresh").unwrap();
+        let mut log = storage::load_storage::<SecurityEvent>("../data/reset_log").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_email("demo@user.com").unwrap();
+        user.set_password("SEcretPassWord1234789").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        let session = match login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "SEcretPassWord1234789",
+            &ClientInfo::default(),
+        ) {
+            Ok(LoginOutcome::LoggedIn(token)) => token,
+            outcome => panic!("Unexpected login outcome: {:?}", outcome),
+        };
+        let keys = TokenKeys::new(vec![SigningKey {
+            kid: "test".to_owned(),
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+        }])
+        .unwrap();
+        let pair = issue_token_pair(&keys, &mut refresh_tokens, &users.data[0], &[]).unwrap();
+
+        let other_token = create_password_reset(&mut resets, &users.data[0]).unwrap();
+        let token = create_password_reset(&mut resets, &users.data[0]).unwrap();
+        // Stored hashed
+        assert!(storage::get_by_id(&resets, &token).is_none());
+        // Weak password is refused, and the token is still usable
+        assert!(reset_password(
+            &mut resets,
+            &mut users,
+            &mut sessions,
+            &mut refresh_tokens,
+            &token,
+            "weak"
+        )
+        .is_err());
+        assert_eq!(
+            reset_password(
+                &mut resets,
+                &mut users,
+                &mut sessions,
+                &mut refresh_tokens,
+                &token,
+                "NEwPassWord42"
+            ),
+            Ok("demo_user".to_owned())
+        );
+        assert!(reset_password(
+            &mut resets,
+            &mut users,
+            &mut sessions,
+            &mut refresh_tokens,
+            &token,
+            "OThErPassWord42"
+        )
+        .is_err());
+        // Other links of the user are invalidated too
+        assert!(reset_password(
+            &mut resets,
+            &mut users,
+            &mut sessions,
+            &mut refresh_tokens,
+            &other_token,
+            "OThErPassWord42"
+        )
+        .is_err());
+        assert!(validate_access_token(&users, &sessions, None, &session).is_err());
+        assert!(
+            refresh_access_token(&keys, &mut refresh_tokens, &users, &pair.refresh_token).is_err()
+        );
+        assert!(login(
+            &mut users,
+            &mut sessions,
+            &mut attempts,
+            &mut log,
+            "demo@user.com",
+            "NEwPassWord42",
+            &ClientInfo::default()
+        )
+        .is_ok());
+        users.remove();
+        sessions.remove();
+        resets.remove();
+        attempts.remove();
+        refresh_tokens.remove();
+        log.remove();
+    }
+}
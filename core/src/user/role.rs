@@ -0,0 +1,141 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::user::User;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// # Permission
+/// Things a user can be allowed to do on the site.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    ViewContent,
+    CreateContent,
+    EditOwnContent,
+    EditAnyContent,
+    DeleteAnyContent,
+    ManageUsers,
+    ViewAdminPage,
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ViewContent" => Ok(Permission::ViewContent),
+            "CreateContent" => Ok(Permission::CreateContent),
+            "EditOwnContent" => Ok(Permission::EditOwnContent),
+            "EditAnyContent" => Ok(Permission::EditAnyContent),
+            "DeleteAnyContent" => Ok(Permission::DeleteAnyContent),
+            "ManageUsers" => Ok(Permission::ManageUsers),
+            "ViewAdminPage" => Ok(Permission::ViewAdminPage),
+            _ => Err(format!("Unknown permission: {}", s.trim())),
+        }
+    }
+}
+
+/// # Role
+/// Named set of permissions, assigned to users.
+///  - Admin: everything
+///  - Editor: manage any content
+///  - Member: view, create and edit own content
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Admin,
+    Editor,
+    Member,
+}
+
+impl Role {
+    /// # Role permissions
+    /// ```rust
+    /// use core_lib::user::role::*;
+    /// assert_eq!(Role::Member.permissions().contains(&Permission::ManageUsers), false);
+    /// assert_eq!(Role::Admin.permissions().contains(&Permission::ManageUsers), true);
+    /// ```
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::Admin => &[
+                Permission::ViewContent,
+                Permission::CreateContent,
+                Permission::EditOwnContent,
+                Permission::EditAnyContent,
+                Permission::DeleteAnyContent,
+                Permission::ManageUsers,
+                Permission::ViewAdminPage,
+            ],
+            Role::Editor => &[
+                Permission::ViewContent,
+                Permission::CreateContent,
+                Permission::EditOwnContent,
+                Permission::EditAnyContent,
+                Permission::DeleteAnyContent,
+            ],
+            Role::Member => &[
+                Permission::ViewContent,
+                Permission::CreateContent,
+                Permission::EditOwnContent,
+            ],
+        }
+    }
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+/// # Has permission
+/// True if any role of the user grants the permission.
+/// ```rust
+/// use core_lib::prelude::New;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::user::role::*;
+/// use core_lib::user::User;
+/// let mut user = UserV2::new();
+/// assert_eq!(has_permission(&user, Permission::ViewContent), false);
+/// user.add_user_role(Role::Editor).unwrap();
+/// assert_eq!(has_permission(&user, Permission::EditAnyContent), true);
+/// assert_eq!(has_permission(&user, Permission::ManageUsers), false);
+/// ```
+pub fn has_permission<T: User>(user: &T, permission: Permission) -> bool {
+    user.get_user_roles()
+        .iter()
+        .any(|role| role.has_permission(permission))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_permissions() {
+        // Admin has every permission the others have
+        for role in &[Role::Editor, Role::Member] {
+            for permission in role.permissions() {
+                assert!(Role::Admin.has_permission(*permission));
+            }
+        }
+        assert!(!Role::Editor.has_permission(Permission::ViewAdminPage));
+        assert!(!Role::Member.has_permission(Permission::EditAnyContent));
+    }
+
+    #[test]
+    fn test_permission_from_str() {
+        assert_eq!(" ManageUsers".parse(), Ok(Permission::ManageUsers));
+        assert!("manage_users".parse::<Permission>().is_err());
+    }
+}
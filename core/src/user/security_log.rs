@@ -0,0 +1,315 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::relation::Ref;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::model::user_v2::UserV2;
+use crate::user::session::ClientInfo;
+use crate::user::token::generate_token_with_length;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// # Security event kind
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityEventKind {
+    Login,
+    SecondFactor,
+    AccountLocked,
+    Logout,
+    PasswordChanged,
+    PasswordResetRequested,
+    PasswordReset,
+    EmailVerified,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    SessionRevoked,
+    ApiKeyCreated,
+    ApiKeyRevoked,
+    DataExported,
+    AccountErased,
+}
+
+/// # Security event outcome
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityEventOutcome {
+    Success,
+    Failure,
+}
+
+/// # Security event
+///
+/// One entry of the append-only security log. Events cannot be
+/// changed once recorded, except anonymisation on account erasure.
+/// The user is unknown e.g. for failed logins with an unregistered
+/// email address, then `detail` tells more.
+/// The time is set when the event is recorded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecurityEvent {
+    id: String,
+    path: Option<String>,
+    kind: SecurityEventKind,
+    outcome: SecurityEventOutcome,
+    user: Option<Ref<UserV2>>,
+    time: DateTime<Utc>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    detail: Option<String>,
+}
+
+impl SecurityEvent {
+    pub fn new(
+        kind: SecurityEventKind,
+        outcome: SecurityEventOutcome,
+        user_id: Option<&str>,
+        client: &ClientInfo,
+    ) -> Self {
+        SecurityEvent {
+            id: String::new(),
+            path: None,
+            kind,
+            outcome,
+            user: user_id.map(Ref::new),
+            time: Utc::now(),
+            ip: client.ip.clone(),
+            user_agent: client.user_agent.clone(),
+            detail: None,
+        }
+    }
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_owned());
+        self
+    }
+    pub fn get_kind(&self) -> SecurityEventKind {
+        self.kind
+    }
+    pub fn get_outcome(&self) -> SecurityEventOutcome {
+        self.outcome
+    }
+    pub fn get_user_id(&self) -> Option<&str> {
+        self.user.as_ref().map(|user| user.get_id())
+    }
+    pub fn get_time(&self) -> DateTime<Utc> {
+        self.time
+    }
+    pub fn get_ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
+    pub fn get_user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+    pub fn get_detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+}
+
+impl StorageObject for SecurityEvent {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Record security event
+/// Append the event to the log.
+/// ```rust
+/// use core_lib::storage::load_storage;
+/// use core_lib::user::security_log::*;
+/// use core_lib::user::session::ClientInfo;
+/// let mut log = load_storage::<SecurityEvent>("../data/doc_security_log").unwrap();
+/// let event = SecurityEvent::new(
+///     SecurityEventKind::Login,
+///     SecurityEventOutcome::Success,
+///     Some("demo_user"),
+///     &ClientInfo::default(),
+/// );
+/// record_security_event(&mut log, event).unwrap();
+/// assert_eq!(get_user_events(&log, "demo_user", 10).len(), 1);
+/// log.remove();
+/// ```
+pub fn record_security_event(
+    log: &mut Storage<SecurityEvent>,
+    mut event: SecurityEvent,
+) -> Result<(), String> {
+    event.time = Utc::now();
+    event.id = format!(
+        "{}_{}",
+        event.time.format("%Y%m%d%H%M%S%6f"),
+        generate_token_with_length(8)?
+    );
+    storage::add_to_storage(log, event)
+}
+
+/// # Anonymise user events
+/// On account erasure the events of the user are kept for security
+/// audit, but without the user, client and detail. Events without user
+/// mentioning `email` in their detail (failed logins) are anonymised
+/// too. Returns the number of changed events.
+pub fn anonymise_user_events(
+    log: &mut Storage<SecurityEvent>,
+    user_id: &str,
+    email: Option<&str>,
+) -> Result<usize, String> {
+    let mentions_email = |event: &SecurityEvent| match (email, event.detail.as_ref()) {
+        (Some(email), Some(detail)) => detail.to_lowercase().contains(&email.to_lowercase()),
+        _ => false,
+    };
+    let mut count = 0;
+    for index in 0..log.data.len() {
+        let event = &mut log.data[index];
+        if event.get_user_id() != Some(user_id) && !mentions_email(event) {
+            continue;
+        }
+        event.user = None;
+        event.ip = None;
+        event.user_agent = None;
+        event.detail = None;
+        event.save()?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// # Security event filter
+/// Every set field must match.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityEventFilter {
+    pub user_id: Option<String>,
+    pub kind: Option<SecurityEventKind>,
+    pub outcome: Option<SecurityEventOutcome>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl SecurityEventFilter {
+    fn matches(&self, event: &SecurityEvent) -> bool {
+        self.user_id.as_ref().map_or(true, |user_id| {
+            event.get_user_id() == Some(user_id.as_str())
+        }) && self.kind.map_or(true, |kind| event.kind == kind)
+            && self
+                .outcome
+                .map_or(true, |outcome| event.outcome == outcome)
+            && self.since.map_or(true, |since| event.time >= since)
+    }
+}
+
+/// # Find security events
+/// Matching events, the newest first, at most `limit` of them.
+pub fn find_security_events<'a>(
+    log: &'a Storage<SecurityEvent>,
+    filter: &SecurityEventFilter,
+    limit: usize,
+) -> Vec<&'a SecurityEvent> {
+    // Stable sort, events with the same time stay in recording order
+    let mut events: Vec<&SecurityEvent> = log
+        .data
+        .iter()
+        .rev()
+        .filter(|event| filter.matches(event))
+        .collect();
+    events.sort_by_key(|event| std::cmp::Reverse(event.time));
+    events.truncate(limit);
+    events
+}
+
+/// # Recent events of a user
+/// For a "recent activity" page, the newest first.
+pub fn get_user_events<'a>(
+    log: &'a Storage<SecurityEvent>,
+    user_id: &str,
+    limit: usize,
+) -> Vec<&'a SecurityEvent> {
+    let filter = SecurityEventFilter {
+        user_id: Some(user_id.to_owned()),
+        ..SecurityEventFilter::default()
+    };
+    find_security_events(log, &filter, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_security_log() {
+        let mut log = storage::load_storage::<SecurityEvent>("../data/security_log").unwrap();
+        let client = ClientInfo {
+            ip: Some("127.0.0.1".to_owned()),
+            user_agent: Some("Firefox".to_owned()),
+        };
+        let events = vec![
+            SecurityEvent::new(
+                SecurityEventKind::Login,
+                SecurityEventOutcome::Failure,
+                None,
+                &client,
+            )
+            .with_detail("unknown@user.com"),
+            SecurityEvent::new(
+                SecurityEventKind::Login,
+                SecurityEventOutcome::Failure,
+                Some("demo_user"),
+                &client,
+            ),
+            SecurityEvent::new(
+                SecurityEventKind::Login,
+                SecurityEventOutcome::Success,
+                Some("demo_user"),
+                &client,
+            ),
+            SecurityEvent::new(
+                SecurityEventKind::PasswordChanged,
+                SecurityEventOutcome::Success,
+                Some("demo_user"),
+                &client,
+            ),
+        ];
+        for event in events {
+            record_security_event(&mut log, event).unwrap();
+        }
+        let recent = get_user_events(&log, "demo_user", 2);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].get_kind(), SecurityEventKind::PasswordChanged);
+        assert_eq!(recent[0].get_ip(), Some("127.0.0.1"));
+
+        let failed_logins = SecurityEventFilter {
+            kind: Some(SecurityEventKind::Login),
+            outcome: Some(SecurityEventOutcome::Failure),
+            ..SecurityEventFilter::default()
+        };
+        assert_eq!(find_security_events(&log, &failed_logins, 10).len(), 2);
+
+        assert_eq!(
+            anonymise_user_events(&mut log, "demo_user", Some("Unknown@User.com")),
+            Ok(4)
+        );
+        assert_eq!(get_user_events(&log, "demo_user", 10).len(), 0);
+        assert!(log.data.iter().all(|event| event.get_ip().is_none()));
+        assert_eq!(find_security_events(&log, &failed_logins, 10).len(), 2);
+        log.remove();
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::relation::Ref;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::model::user_v2::UserV2;
+use crate::user::token::hash_token;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// # Client info
+/// Where a login comes from, stored with the session, so the user
+/// can recognize their sessions.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// # Session
+///
+/// One logged-in client, identified by the access token the client
+/// sends back, e.g. in the `token` cookie. Only the hash of the token
+/// is stored, it is the session ID.
+/// A session waiting for the second login step is not logged in,
+/// its token can only be used to finish the login.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    id: String,
+    path: Option<String>,
+    user: Ref<UserV2>,
+    created: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    #[serde(default)]
+    pending_second_factor: bool,
+    #[serde(default)]
+    client: ClientInfo,
+}
+
+impl Session {
+    pub fn new(token: &str, user_id: &str) -> Self {
+        let now = Utc::now();
+        Session {
+            id: hash_token(token),
+            path: None,
+            user: Ref::new(user_id),
+            created: now,
+            last_seen: now,
+            pending_second_factor: false,
+            client: ClientInfo::default(),
+        }
+    }
+    /// # Set client info
+    /// ```rust
+    /// use core_lib::user::session::{ClientInfo, Session};
+    /// let client = ClientInfo {
+    ///     ip: Some("127.0.0.1".to_owned()),
+    ///     user_agent: Some("Firefox".to_owned()),
+    /// };
+    /// let session = Session::new("token", "demo_user").with_client(&client);
+    /// assert_eq!(session.get_client(), &client);
+    /// ```
+    pub fn with_client(mut self, client: &ClientInfo) -> Self {
+        self.client = client.clone();
+        self
+    }
+    /// # New session waiting for the second login step
+    pub fn new_pending(token: &str, user_id: &str) -> Self {
+        Session {
+            pending_second_factor: true,
+            ..Session::new(token, user_id)
+        }
+    }
+    pub fn is_pending_second_factor(&self) -> bool {
+        self.pending_second_factor
+    }
+    /// # Is it the session of the token?
+    /// ```rust
+    /// use core_lib::user::session::Session;
+    /// let session = Session::new("token", "demo_user");
+    /// assert!(session.is_token("token"));
+    /// assert_ne!(session.get_session_id(), "token");
+    /// ```
+    pub fn is_token(&self, token: &str) -> bool {
+        self.id == hash_token(token)
+    }
+    /// Public ID of the session, e.g. for revoke links. It is the hash
+    /// of the token, so showing it does not leak the token.
+    pub fn get_session_id(&self) -> String {
+        self.id.clone()
+    }
+    pub fn get_client(&self) -> &ClientInfo {
+        &self.client
+    }
+    pub fn get_user(&self) -> &Ref<UserV2> {
+        &self.user
+    }
+    pub fn get_created(&self) -> DateTime<Utc> {
+        self.created
+    }
+    pub fn get_last_seen(&self) -> DateTime<Utc> {
+        self.last_seen
+    }
+    pub fn touch(&mut self) {
+        self.last_seen = Utc::now();
+    }
+}
+
+impl StorageObject for Session {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Sessions of a user
+/// Logged in sessions, login challenges excluded, the newest first.
+pub fn get_user_sessions<'a>(sessions: &'a Storage<Session>, user_id: &str) -> Vec<&'a Session> {
+    let mut result: Vec<&Session> = sessions
+        .data
+        .iter()
+        .filter(|session| session.user.get_id() == user_id && !session.pending_second_factor)
+        .collect();
+    result.sort_by_key(|session| std::cmp::Reverse(session.last_seen));
+    result
+}
+
+/// # Revoke session
+/// Remove one session of the user by its public session ID.
+pub fn revoke_session(
+    sessions: &mut Storage<Session>,
+    user_id: &str,
+    session_id: &str,
+) -> Result<(), String> {
+    match storage::get_by_id(sessions, session_id) {
+        Some(session) if session.user.get_id() == user_id => (),
+        _ => return Err("Session not found.".to_owned()),
+    }
+    storage::remove_from_storage(sessions, session_id).map(|_| ())
+}
+
+/// # Revoke other sessions
+/// Log out everywhere else, keeping the current session.
+/// Returns the number of removed sessions.
+pub fn revoke_other_sessions(
+    sessions: &mut Storage<Session>,
+    user_id: &str,
+    current_token: &str,
+) -> Result<usize, String> {
+    let ids: Vec<String> = sessions
+        .data
+        .iter()
+        .filter(|session| session.user.get_id() == user_id && !session.is_token(current_token))
+        .map(|session| session.id.clone())
+        .collect();
+    for id in &ids {
+        storage::remove_from_storage(sessions, id)?;
+    }
+    Ok(ids.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_sessions() {
+        let mut sessions = storage::load_storage::<Session>("../data/session_sessions").unwrap();
+        let client = ClientInfo {
+            ip: Some("127.0.0.1".to_owned()),
+            user_agent: Some("Firefox".to_owned()),
+        };
+        for token in &["token_a", "token_b", "token_c"] {
+            storage::add_to_storage(
+                &mut sessions,
+                Session::new(token, "demo_user").with_client(&client),
+            )
+            .unwrap();
+        }
+        storage::add_to_storage(&mut sessions, Session::new("token_d", "other_user")).unwrap();
+        storage::add_to_storage(&mut sessions, Session::new_pending("token_e", "demo_user"))
+            .unwrap();
+        assert_eq!(get_user_sessions(&sessions, "demo_user").len(), 3);
+
+        let session_id = hash_token("token_a");
+        assert!(revoke_session(&mut sessions, "other_user", &session_id).is_err());
+        revoke_session(&mut sessions, "demo_user", &session_id).unwrap();
+        assert!(storage::get_by_id(&sessions, &hash_token("token_a")).is_none());
+
+        assert_eq!(
+            revoke_other_sessions(&mut sessions, "demo_user", "token_b"),
+            Ok(2)
+        );
+        assert!(storage::get_by_id(&sessions, &hash_token("token_b")).is_some());
+        assert!(storage::get_by_id(&sessions, &hash_token("token_d")).is_some());
+        sessions.remove();
+    }
+}
@@ -0,0 +1,622 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::relation::Ref;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::model::user_v2::UserV2;
+use crate::user::role::{self, Permission, Role};
+use crate::user::status::AccountStatus;
+use crate::user::token::{generate_token, hash_token};
+use crate::user::User;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+
+/// Signed access token is valid for this many minutes
+pub const ACCESS_TOKEN_VALID_MINUTES: i64 = 15;
+/// Refresh token is valid for this many days
+pub const REFRESH_TOKEN_VALID_DAYS: i64 = 30;
+/// Shortest accepted signing secret in bytes
+pub const MIN_SECRET_LENGTH: usize = 32;
+
+const BASE64: base64::Config = base64::URL_SAFE_NO_PAD;
+
+/// # Token claims
+/// Payload of a signed access token.
+///  - sub: user ID
+///  - roles: user roles when the token was issued
+///  - scopes: scopes of the API key the token was issued for, none
+///    means every permission of the user
+///  - iat, exp: issued and expiry unix time
+///  - jti: unique token ID, used by the revocation list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenClaims {
+    pub sub: String,
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub scopes: Vec<Permission>,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+}
+
+impl TokenClaims {
+    /// The token scopes allow the permission. The user must have
+    /// it as well, see `has_token_permission`.
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.scopes.is_empty() || self.scopes.contains(&permission)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenHeader {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+/// # Signing key
+#[derive(Clone)]
+pub struct SigningKey {
+    pub kid: String,
+    pub secret: Vec<u8>,
+}
+
+/// # Token signing keys
+/// The first key signs new tokens, all of them are accepted, so a
+/// new key can be added before the old one is removed.
+#[derive(Clone)]
+pub struct TokenKeys {
+    keys: Vec<SigningKey>,
+}
+
+impl TokenKeys {
+    pub fn new(keys: Vec<SigningKey>) -> Result<Self, String> {
+        if keys.is_empty() {
+            return Err("No token signing key.".to_owned());
+        }
+        for key in &keys {
+            if key.kid.is_empty() || key.kid.contains(':') || key.kid.contains(',') {
+                return Err(format!("Wrong token signing key ID: {}", key.kid));
+            }
+            if key.secret.len() < MIN_SECRET_LENGTH {
+                return Err(format!(
+                    "Token signing key {} is shorter than {} bytes.",
+                    key.kid, MIN_SECRET_LENGTH
+                ));
+            }
+        }
+        Ok(TokenKeys { keys })
+    }
+    /// # Keys from environment
+    /// Reads the TOKEN_SIGNING_KEYS environment variable, a comma
+    /// separated list of `kid:secret` pairs, newest first.
+    pub fn from_env() -> Result<Self, String> {
+        let value = match env::var("TOKEN_SIGNING_KEYS") {
+            Ok(value) => value,
+            Err(_) => return Err("TOKEN_SIGNING_KEYS is not set.".to_owned()),
+        };
+        let mut keys = Vec::new();
+        for item in value.split(',').filter(|item| !item.trim().is_empty()) {
+            let mut parts = item.trim().splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(kid), Some(secret)) => keys.push(SigningKey {
+                    kid: kid.to_owned(),
+                    secret: secret.as_bytes().to_vec(),
+                }),
+                _ => return Err("Wrong TOKEN_SIGNING_KEYS format.".to_owned()),
+            }
+        }
+        TokenKeys::new(keys)
+    }
+    pub fn current(&self) -> &SigningKey {
+        &self.keys[0]
+    }
+    pub fn get(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys.iter().find(|key| key.kid == kid)
+    }
+}
+
+fn mac(key: &SigningKey, data: &str) -> Result<Hmac<Sha256>, String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key.secret)
+        .map_err(|_| "Wrong token signing key.".to_owned())?;
+    mac.update(data.as_bytes());
+    Ok(mac)
+}
+
+fn to_base64_json<T: Serialize>(value: &T) -> Result<String, String> {
+    match serde_json::to_vec(value) {
+        Ok(json) => Ok(base64::encode_config(json, BASE64)),
+        Err(_) => Err("Error while serializing token.".to_owned()),
+    }
+}
+
+fn from_base64_json<T: serde::de::DeserializeOwned>(value: &str) -> Result<T, String> {
+    let json = base64::decode_config(value, BASE64).map_err(|_| "Invalid token.".to_owned())?;
+    serde_json::from_slice(&json).map_err(|_| "Invalid token.".to_owned())
+}
+
+/// # Sign token
+/// HMAC-SHA256 signed JWT, using the current key.
+pub fn sign_token(keys: &TokenKeys, claims: &TokenClaims) -> Result<String, String> {
+    let key = keys.current();
+    let header = TokenHeader {
+        alg: "HS256".to_owned(),
+        typ: "JWT".to_owned(),
+        kid: key.kid.clone(),
+    };
+    let data = format!("{}.{}", to_base64_json(&header)?, to_base64_json(claims)?);
+    let signature = mac(key, &data)?.finalize().into_bytes();
+    Ok(format!(
+        "{}.{}",
+        data,
+        base64::encode_config(signature, BASE64)
+    ))
+}
+
+/// # Verify token
+/// Checks the signature with the key named in the header, and the
+/// expiry. The revocation list is checked by `validate_signed_token`.
+pub fn verify_token(keys: &TokenKeys, token: &str) -> Result<TokenClaims, String> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 3 {
+        return Err("Invalid token.".to_owned());
+    }
+    let header: TokenHeader = from_base64_json(parts[0])?;
+    if header.alg != "HS256" {
+        return Err("Invalid token.".to_owned());
+    }
+    let key = match keys.get(&header.kid) {
+        Some(key) => key,
+        None => return Err("Invalid token.".to_owned()),
+    };
+    let signature =
+        base64::decode_config(parts[2], BASE64).map_err(|_| "Invalid token.".to_owned())?;
+    // Constant time comparison
+    mac(key, &format!("{}.{}", parts[0], parts[1]))?
+        .verify_slice(&signature)
+        .map_err(|_| "Invalid token.".to_owned())?;
+    let claims: TokenClaims = from_base64_json(parts[1])?;
+    if Utc::now().timestamp() >= claims.exp {
+        return Err("Token is expired.".to_owned());
+    }
+    Ok(claims)
+}
+
+/// # Issue access token
+/// Signed access token of the user limited to the given scopes, valid
+/// for `ACCESS_TOKEN_VALID_MINUTES`.
+pub fn issue_access_token<T: User>(
+    keys: &TokenKeys,
+    user: &T,
+    scopes: &[Permission],
+) -> Result<String, String> {
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err("User ID is not set.".to_owned()),
+    };
+    let now = Utc::now();
+    sign_token(
+        keys,
+        &TokenClaims {
+            sub: user_id,
+            roles: user.get_user_roles(),
+            scopes: scopes.to_vec(),
+            iat: now.timestamp(),
+            exp: (now + Duration::minutes(ACCESS_TOKEN_VALID_MINUTES)).timestamp(),
+            jti: generate_token()?,
+        },
+    )
+}
+
+/// # Revoked token
+///
+/// Entry of the revocation list. The ID is the token `jti`. Entries
+/// can be purged once the token would be expired anyway.
+#[derive(Serialize, Deserialize)]
+pub struct RevokedToken {
+    id: String,
+    path: Option<String>,
+    user: Ref<UserV2>,
+    revoked: DateTime<Utc>,
+    expires: DateTime<Utc>,
+}
+
+impl StorageObject for RevokedToken {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Revoke access token
+/// Put a valid signed token on the revocation list, e.g. on logout.
+pub fn revoke_access_token(
+    revocations: &mut Storage<RevokedToken>,
+    keys: &TokenKeys,
+    token: &str,
+) -> Result<(), String> {
+    let claims = verify_token(keys, token)?;
+    if storage::get_by_id(revocations, &claims.jti).is_some() {
+        return Ok(());
+    }
+    let expires = match Utc.timestamp_opt(claims.exp, 0).single() {
+        Some(expires) => expires,
+        None => return Err("Invalid token.".to_owned()),
+    };
+    storage::add_to_storage(
+        revocations,
+        RevokedToken {
+            id: claims.jti,
+            path: None,
+            user: Ref::new(&claims.sub),
+            revoked: Utc::now(),
+            expires,
+        },
+    )
+}
+
+/// # Purge revocation list
+/// Remove entries of expired tokens. Returns the number of
+/// removed entries.
+pub fn purge_revoked_tokens(revocations: &mut Storage<RevokedToken>) -> Result<usize, String> {
+    let now = Utc::now();
+    let ids: Vec<String> = revocations
+        .data
+        .iter()
+        .filter(|item| item.expires < now)
+        .map(|item| item.id.clone())
+        .collect();
+    for id in &ids {
+        storage::remove_from_storage(revocations, id)?;
+    }
+    Ok(ids.len())
+}
+
+/// # Validate signed token
+/// The token must be valid, not revoked, and its user must be active.
+/// Returns the claims, check the permissions with
+/// `has_token_permission`. A signed token is not a login.
+pub fn validate_signed_token<T>(
+    users: &Storage<T>,
+    revocations: &Storage<RevokedToken>,
+    keys: &TokenKeys,
+    token: &str,
+) -> Result<TokenClaims, String>
+where
+    T: User + StorageObject,
+{
+    let claims = verify_token(keys, token)?;
+    if storage::get_by_id(revocations, &claims.jti).is_some() {
+        return Err("Token is revoked.".to_owned());
+    }
+    match storage::get_by_id(users, &claims.sub) {
+        Some(user) if user.get_user_status() == AccountStatus::Active => Ok(claims),
+        _ => Err("Invalid access token.".to_owned()),
+    }
+}
+
+/// # Signed token permission
+/// Both the user and the token scopes must allow it.
+pub fn has_token_permission<T: User>(
+    user: &T,
+    claims: &TokenClaims,
+    permission: Permission,
+) -> bool {
+    claims.allows(permission) && role::has_permission(user, permission)
+}
+
+/// # Refresh token
+///
+/// Long-lived, single-use token to get a new access token. The ID is
+/// the hash of the token. Every refresh replaces it with a new one,
+/// keeping the scopes, and reusing a replaced token revokes every
+/// refresh token of the user, as it is probably stolen.
+#[derive(Serialize, Deserialize)]
+pub struct RefreshToken {
+    id: String,
+    path: Option<String>,
+    user: Ref<UserV2>,
+    #[serde(default)]
+    scopes: Vec<Permission>,
+    created: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    used: Option<DateTime<Utc>>,
+    revoked: Option<DateTime<Utc>>,
+}
+
+impl RefreshToken {
+    pub fn get_user(&self) -> &Ref<UserV2> {
+        &self.user
+    }
+    pub fn is_valid(&self) -> bool {
+        self.used.is_none() && self.revoked.is_none() && Utc::now() < self.expires
+    }
+}
+
+impl StorageObject for RefreshToken {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Token pair
+/// Response of the token endpoints. `expires_in` is the access token
+/// lifetime in seconds.
+#[derive(Serialize, Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+}
+
+/// # Issue token pair
+/// New access and refresh token for the user, limited to the scopes
+/// of the API key they are issued for.
+/// ```rust
+/// use core_lib::storage::load_storage;
+/// use core_lib::user::model::user_v2::UserV2;
+/// use core_lib::prelude::New;
+/// use core_lib::user::signed_token::*;
+/// use core_lib::user::User;
+/// let keys = TokenKeys::new(vec![SigningKey {
+///     kid: "2019-01".to_owned(),
+///     secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+/// }])
+/// .unwrap();
+/// let mut refresh_tokens = load_storage::<RefreshToken>("../data/doc_refresh_tokens").unwrap();
+/// let mut user = UserV2::new();
+/// user.set_user_id("demo_user").unwrap();
+/// let pair = issue_token_pair(&keys, &mut refresh_tokens, &user, &[]).unwrap();
+/// let claims = verify_token(&keys, &pair.access_token).unwrap();
+/// assert_eq!(claims.sub, "demo_user");
+/// refresh_tokens.remove();
+/// ```
+pub fn issue_token_pair<T: User>(
+    keys: &TokenKeys,
+    refresh_tokens: &mut Storage<RefreshToken>,
+    user: &T,
+    scopes: &[Permission],
+) -> Result<TokenPair, String> {
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err("User ID is not set.".to_owned()),
+    };
+    let access_token = issue_access_token(keys, user, scopes)?;
+    let refresh_token = generate_token()?;
+    let created = Utc::now();
+    storage::add_to_storage(
+        refresh_tokens,
+        RefreshToken {
+            id: hash_token(&refresh_token),
+            path: None,
+            user: Ref::new(&user_id),
+            scopes: scopes.to_vec(),
+            created,
+            expires: created + Duration::days(REFRESH_TOKEN_VALID_DAYS),
+            used: None,
+            revoked: None,
+        },
+    )?;
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        token_type: "Bearer",
+        expires_in: ACCESS_TOKEN_VALID_MINUTES * 60,
+    })
+}
+
+/// # Refresh access token
+/// Exchange a refresh token for a new token pair. The user must
+/// still be active.
+pub fn refresh_access_token<T>(
+    keys: &TokenKeys,
+    refresh_tokens: &mut Storage<RefreshToken>,
+    users: &Storage<T>,
+    refresh_token: &str,
+) -> Result<TokenPair, String>
+where
+    T: User + StorageObject,
+{
+    let token = match storage::get_mut_by_id(refresh_tokens, &hash_token(refresh_token.trim())) {
+        Some(token) => token,
+        None => return Err("Invalid refresh token.".to_owned()),
+    };
+    let user_id = token.user.get_id().to_owned();
+    let scopes = token.scopes.clone();
+    if token.used.is_some() {
+        revoke_refresh_tokens(refresh_tokens, &user_id)?;
+        return Err("Invalid refresh token.".to_owned());
+    }
+    if !token.is_valid() {
+        return Err("Invalid refresh token.".to_owned());
+    }
+    token.used = Some(Utc::now());
+    token.save()?;
+    match storage::get_by_id(users, &user_id) {
+        Some(user) if user.get_user_status() == AccountStatus::Active => {
+            issue_token_pair(keys, refresh_tokens, user, &scopes)
+        }
+        _ => Err("Invalid refresh token.".to_owned()),
+    }
+}
+
+/// # Revoke refresh tokens
+/// Revoke every refresh token of the user, e.g. after a password
+/// change. Returns the number of revoked tokens.
+pub fn revoke_refresh_tokens(
+    refresh_tokens: &mut Storage<RefreshToken>,
+    user_id: &str,
+) -> Result<usize, String> {
+    let mut count = 0;
+    for token in refresh_tokens
+        .data
+        .iter_mut()
+        .filter(|token| token.user.get_id() == user_id && token.revoked.is_none())
+    {
+        token.revoked = Some(Utc::now());
+        token.save()?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::login::validate_access_token;
+    use crate::user::session::Session;
+
+    fn key(kid: &str) -> SigningKey {
+        SigningKey {
+            kid: kid.to_owned(),
+            secret: format!("{}-0123456789abcdef0123456789abcdef", kid).into_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        assert!(TokenKeys::new(vec![SigningKey {
+            kid: "short".to_owned(),
+            secret: b"secret".to_vec(),
+        }])
+        .is_err());
+        let old_keys = TokenKeys::new(vec![key("old")]).unwrap();
+        let keys = TokenKeys::new(vec![key("new"), key("old")]).unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.add_user_role(Role::Editor).unwrap();
+
+        // Tokens of the old key are still accepted after rotation
+        let old_token = issue_access_token(&old_keys, &user, &[]).unwrap();
+        assert_eq!(verify_token(&keys, &old_token).unwrap().sub, "demo_user");
+        let token = issue_access_token(&keys, &user, &[]).unwrap();
+        let claims = verify_token(&keys, &token).unwrap();
+        assert_eq!(claims.roles, vec![Role::Editor]);
+        assert!(verify_token(&old_keys, &token).is_err());
+
+        // Tampered claims
+        let parts: Vec<&str> = token.split('.').collect();
+        let mut forged = claims.clone();
+        forged.sub = "admin".to_owned();
+        let forged = format!(
+            "{}.{}.{}",
+            parts[0],
+            to_base64_json(&forged).unwrap(),
+            parts[2]
+        );
+        assert!(verify_token(&keys, &forged).is_err());
+
+        // Expired
+        let mut expired = claims;
+        expired.exp = Utc::now().timestamp() - 1;
+        assert!(verify_token(&keys, &sign_token(&keys, &expired).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_refresh_and_revoke() {
+        let keys = TokenKeys::new(vec![key("current")]).unwrap();
+        let mut users = storage::load_storage::<UserV2>("../data/signed_token_users").unwrap();
+        let mut refresh_tokens =
+            storage::load_storage::<RefreshToken>("../data/signed_token_refresh").unwrap();
+        let mut revocations =
+            storage::load_storage::<RevokedToken>("../data/signed_token_revoked").unwrap();
+        let sessions = storage::load_storage::<Session>("../data/signed_token_sessions").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        user.add_user_role(Role::Editor).unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+
+        let scopes = [Permission::ViewContent];
+        let pair = issue_token_pair(&keys, &mut refresh_tokens, &users.data[0], &scopes).unwrap();
+        let claims =
+            validate_signed_token(&users, &revocations, &keys, &pair.access_token).unwrap();
+        assert_eq!(claims.sub, "demo_user");
+        assert!(has_token_permission(
+            &users.data[0],
+            &claims,
+            Permission::ViewContent
+        ));
+        // The user could, the token scopes do not allow it
+        assert!(!has_token_permission(
+            &users.data[0],
+            &claims,
+            Permission::CreateContent
+        ));
+        // Signed tokens are access tokens, until they are revoked
+        let signed = Some((&revocations, &keys));
+        assert_eq!(
+            validate_access_token(&users, &sessions, signed, &pair.access_token),
+            Ok("demo_user".to_owned())
+        );
+        assert!(validate_access_token(&users, &sessions, None, &pair.access_token).is_err());
+        revoke_access_token(&mut revocations, &keys, &pair.access_token).unwrap();
+        assert!(validate_signed_token(&users, &revocations, &keys, &pair.access_token).is_err());
+        let signed = Some((&revocations, &keys));
+        assert!(validate_access_token(&users, &sessions, signed, &pair.access_token).is_err());
+        assert_eq!(purge_revoked_tokens(&mut revocations), Ok(0));
+
+        let new_pair =
+            refresh_access_token(&keys, &mut refresh_tokens, &users, &pair.refresh_token).unwrap();
+        let claims =
+            validate_signed_token(&users, &revocations, &keys, &new_pair.access_token).unwrap();
+        // Scopes are kept on refresh
+        assert_eq!(claims.scopes, scopes.to_vec());
+        // Reuse of a used refresh token revokes all of them
+        assert!(
+            refresh_access_token(&keys, &mut refresh_tokens, &users, &pair.refresh_token).is_err()
+        );
+        assert!(
+            refresh_access_token(&keys, &mut refresh_tokens, &users, &new_pair.refresh_token)
+                .is_err()
+        );
+        users.remove();
+        refresh_tokens.remove();
+        revocations.remove();
+        sessions.remove();
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::login::logout_user;
+use crate::user::session::Session;
+use crate::user::signed_token::{revoke_refresh_tokens, RefreshToken};
+use crate::user::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// # Account status
+/// Lifecycle state of a user account.
+///  - PendingVerification: registered, email is not verified yet
+///  - Active: can log in
+///  - Suspended: disabled by an admin, can be reactivated
+///  - Deleted: closed account, final state
+///
+/// Users saved before account statuses existed are active.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AccountStatus {
+    PendingVerification,
+    #[default]
+    Active,
+    Suspended,
+    Deleted,
+}
+
+impl AccountStatus {
+    /// # Allowed status transitions
+    /// PendingVerification -> Active -> Suspended -> Active,
+    /// and any status -> Deleted.
+    /// ```rust
+    /// use core_lib::user::status::AccountStatus;
+    /// assert_eq!(AccountStatus::PendingVerification.can_transition_to(AccountStatus::Active), true);
+    /// assert_eq!(AccountStatus::PendingVerification.can_transition_to(AccountStatus::Suspended), false);
+    /// assert_eq!(AccountStatus::Deleted.can_transition_to(AccountStatus::Active), false);
+    /// ```
+    pub fn can_transition_to(&self, status: AccountStatus) -> bool {
+        match (self, status) {
+            (AccountStatus::Deleted, _) => false,
+            (_, AccountStatus::Deleted) => true,
+            (AccountStatus::PendingVerification, AccountStatus::Active) => true,
+            (AccountStatus::Active, AccountStatus::Suspended) => true,
+            (AccountStatus::Suspended, AccountStatus::Active) => true,
+            _ => false,
+        }
+    }
+}
+
+/// # Status change
+/// One entry of the account status history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatusChange {
+    pub from: AccountStatus,
+    pub to: AccountStatus,
+    pub changed: DateTime<Utc>,
+}
+
+/// # Change status
+/// Check the transition, and record it in the history.
+pub fn change_status(
+    status: &mut AccountStatus,
+    history: &mut Vec<StatusChange>,
+    new_status: AccountStatus,
+) -> Result<(), String> {
+    if !status.can_transition_to(new_status) {
+        return Err(format!(
+            "Account status cannot be changed from {:?} to {:?}.",
+            status, new_status
+        ));
+    }
+    history.push(StatusChange {
+        from: *status,
+        to: new_status,
+        changed: Utc::now(),
+    });
+    *status = new_status;
+    Ok(())
+}
+
+/// # Update user status
+/// Change the status of a stored user, and save it. A Suspended or
+/// Deleted user is logged out everywhere, and its refresh tokens are
+/// revoked, so it cannot get a new access token either.
+pub fn update_user_status<T>(
+    users: &mut Storage<T>,
+    sessions: &mut Storage<Session>,
+    refresh_tokens: &mut Storage<RefreshToken>,
+    user_id: &str,
+    status: AccountStatus,
+) -> Result<(), String>
+where
+    T: User + StorageObject,
+{
+    let user = match storage::get_mut_by_id(users, user_id) {
+        Some(user) => user,
+        None => return Err(format!("User {} not found.", user_id)),
+    };
+    user.set_user_status(status)?;
+    user.save()?;
+    if status == AccountStatus::Suspended || status == AccountStatus::Deleted {
+        logout_user(sessions, user_id)?;
+        revoke_refresh_tokens(refresh_tokens, user_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::New;
+    use crate::user::model::user_v2::UserV2;
+    use crate::user::signed_token::{
+        issue_token_pair, refresh_access_token, SigningKey, TokenKeys,
+    };
+
+    #[test]
+    fn test_change_status() {
+        let mut status = AccountStatus::PendingVerification;
+        let mut history = Vec::new();
+        assert!(change_status(&mut status, &mut history, AccountStatus::Suspended).is_err());
+        assert!(change_status(&mut status, &mut history, AccountStatus::Active).is_ok());
+        assert!(change_status(&mut status, &mut history, AccountStatus::Active).is_err());
+        assert!(change_status(&mut status, &mut history, AccountStatus::Suspended).is_ok());
+        assert!(change_status(&mut status, &mut history, AccountStatus::Active).is_ok());
+        assert!(change_status(&mut status, &mut history, AccountStatus::Deleted).is_ok());
+        assert!(change_status(&mut status, &mut history, AccountStatus::Active).is_err());
+        assert_eq!(status, AccountStatus::Deleted);
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[0].from, AccountStatus::PendingVerification);
+        assert_eq!(history[3].to, AccountStatus::Deleted);
+    }
+
+    #[test]
+    fn test_update_user_status() {
+        let mut users = storage::load_storage::<UserV2>("../data/status_users").unwrap();
+        let mut sessions = storage::load_storage::<Session>("../data/status_sessions").unwrap();
+        let mut refresh_tokens =
+            storage::load_storage::<RefreshToken>("../data/status_refresh").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_status(AccountStatus::Active).unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        let keys = TokenKeys::new(vec![SigningKey {
+            kid: "test".to_owned(),
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+        }])
+        .unwrap();
+        let pair = issue_token_pair(&keys, &mut refresh_tokens, &users.data[0], &[]).unwrap();
+
+        assert!(update_user_status(
+            &mut users,
+            &mut sessions,
+            &mut refresh_tokens,
+            "other_user",
+            AccountStatus::Suspended
+        )
+        .is_err());
+        update_user_status(
+            &mut users,
+            &mut sessions,
+            &mut refresh_tokens,
+            "demo_user",
+            AccountStatus::Suspended,
+        )
+        .unwrap();
+        assert_eq!(users.data[0].get_user_status(), AccountStatus::Suspended);
+        // Reactivation does not bring the refresh token back
+        update_user_status(
+            &mut users,
+            &mut sessions,
+            &mut refresh_tokens,
+            "demo_user",
+            AccountStatus::Active,
+        )
+        .unwrap();
+        assert!(
+            refresh_access_token(&keys, &mut refresh_tokens, &users, &pair.refresh_token).is_err()
+        );
+        users.remove();
+        sessions.remove();
+        refresh_tokens.remove();
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// URL-safe alphabet, as in base64url
+const TOKEN_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Default token length, 43 characters are 258 random bits
+pub const TOKEN_LENGTH: usize = 43;
+
+/// # Generate token
+/// URL-safe random token from the OS CSPRNG, for sessions and links
+/// sent by email.
+/// ```rust
+/// use core_lib::user::token::generate_token;
+/// let token = generate_token().unwrap();
+/// assert_eq!(token.len(), 43);
+/// assert_ne!(token, generate_token().unwrap());
+/// ```
+pub fn generate_token() -> Result<String, String> {
+    generate_token_with_length(TOKEN_LENGTH)
+}
+
+/// # Generate token with length
+/// Every character is 6 random bits.
+pub fn generate_token_with_length(length: usize) -> Result<String, String> {
+    let mut bytes = vec![0u8; length];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|err| format!("Error while generating random token: {}", err))?;
+    // 64 characters, so masking keeps the distribution uniform
+    Ok(bytes
+        .iter()
+        .map(|byte| TOKEN_ALPHABET[(byte & 63) as usize] as char)
+        .collect())
+}
+
+/// # Hash token
+/// Tokens are stored as their SHA-256 hash, so a leaked storage folder
+/// does not contain usable links.
+/// ```rust
+/// use core_lib::user::token::hash_token;
+/// assert_eq!(hash_token("token").len(), 64);
+/// assert_eq!(hash_token("token"), hash_token("token"));
+/// ```
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token_with_length(1000).unwrap();
+        assert!(token
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_'));
+        assert_eq!(generate_token_with_length(0), Ok(String::new()));
+    }
+}
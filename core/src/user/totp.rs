@@ -0,0 +1,326 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::user::password::generator::random_below;
+use crate::user::token::hash_token;
+use crate::user::User;
+use base32::Alphabet;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+/// Time step in seconds
+pub const TOTP_PERIOD: u64 = 30;
+/// Number of digits of a code
+pub const TOTP_DIGITS: u32 = 6;
+/// Accepted time steps before and after the current one
+pub const TOTP_SKEW: u64 = 1;
+/// Number of recovery codes
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const SECRET_ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// # TOTP settings of a user
+/// The secret is stored as base32, recovery codes as their hash.
+/// 2FA is enabled once the first code is confirmed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TotpSettings {
+    pub secret: String,
+    pub enabled: bool,
+    /// Last accepted time step, a code cannot be used twice
+    pub last_step: Option<u64>,
+    pub recovery_codes: Vec<String>,
+}
+
+/// # TOTP enrollment
+/// Show the URI as a QR code, and the secret for manual entry.
+#[derive(Serialize, Debug, Clone)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub uri: String,
+}
+
+/// # Generate TOTP secret
+/// 160 random bits as base32, as RFC 4226 recommends.
+pub fn generate_totp_secret() -> Result<String, String> {
+    let mut bytes = [0u8; 20];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|err| format!("Error while generating TOTP secret: {}", err))?;
+    Ok(base32::encode(SECRET_ALPHABET, &bytes))
+}
+
+/// # HOTP value
+/// RFC 4226, HMAC-SHA1 with dynamic truncation.
+fn hotp(key: &[u8], counter: u64) -> Result<u32, String> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).map_err(|_| "Wrong TOTP key.".to_owned())?;
+    mac.update(&counter.to_be_bytes());
+    let result = mac.finalize().into_bytes();
+    let offset = (result[result.len() - 1] & 0xf) as usize;
+    let value = (u32::from(result[offset]) & 0x7f) << 24
+        | u32::from(result[offset + 1]) << 16
+        | u32::from(result[offset + 2]) << 8
+        | u32::from(result[offset + 3]);
+    Ok(value % 10u32.pow(TOTP_DIGITS))
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>, String> {
+    base32::decode(SECRET_ALPHABET, secret).ok_or_else(|| "Wrong TOTP secret.".to_owned())
+}
+
+/// # TOTP code
+/// RFC 6238 code of the secret at the given unix time.
+/// ```rust
+/// use core_lib::user::totp::totp_code;
+/// // RFC 6238 test secret "12345678901234567890"
+/// let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+/// assert_eq!(totp_code(secret, 59), Ok("287082".to_owned()));
+/// ```
+pub fn totp_code(secret: &str, time: u64) -> Result<String, String> {
+    let code = hotp(&decode_secret(secret)?, time / TOTP_PERIOD)?;
+    Ok(format!("{:0width$}", code, width = TOTP_DIGITS as usize))
+}
+
+/// # Verify TOTP code
+/// Accepts codes of `TOTP_SKEW` time steps around the given time,
+/// later than `last_step`. Returns the matching time step.
+pub fn verify_totp_code(
+    secret: &str,
+    code: &str,
+    time: u64,
+    last_step: Option<u64>,
+) -> Result<Option<u64>, String> {
+    let key = decode_secret(secret)?;
+    let code = code.trim().replace(' ', "");
+    let current = time / TOTP_PERIOD;
+    for step in current.saturating_sub(TOTP_SKEW)..=current + TOTP_SKEW {
+        if last_step.is_some_and(|last_step| step <= last_step) {
+            continue;
+        }
+        let expected = format!(
+            "{:0width$}",
+            hotp(&key, step)?,
+            width = TOTP_DIGITS as usize
+        );
+        if expected == code {
+            return Ok(Some(step));
+        }
+    }
+    Ok(None)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// # otpauth URI
+/// Key URI format understood by authenticator apps.
+/// ```rust
+/// use core_lib::user::totp::otpauth_uri;
+/// assert_eq!(
+///     otpauth_uri("SECRET", "Project A", "demo@user.com"),
+///     "otpauth://totp/Project%20A:demo%40user.com?secret=SECRET&issuer=Project%20A&algorithm=SHA1&digits=6&period=30"
+/// );
+/// ```
+pub fn otpauth_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD
+    )
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|ch| ch.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// # Generate recovery codes
+/// Returns the codes to show once, and their hashes to store.
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..8)
+                .map(|_| RECOVERY_CODE_ALPHABET[random_below(RECOVERY_CODE_ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect();
+    let hashes = codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    (codes, hashes)
+}
+
+/// # Begin TOTP enrollment
+/// Generate a new secret for the user. 2FA is enabled once
+/// `confirm_totp_enrollment` gets a valid code.
+pub fn begin_totp_enrollment<T: User>(
+    user: &mut T,
+    issuer: &str,
+) -> Result<TotpEnrollment, String> {
+    if user.get_user_totp().is_some_and(|totp| totp.enabled) {
+        return Err("Two-factor authentication is already enabled.".to_owned());
+    }
+    let account = match user.get_user_email().or_else(|| user.get_user_id()) {
+        Some(account) => account,
+        None => return Err("User email is not set.".to_owned()),
+    };
+    let secret = generate_totp_secret()?;
+    user.set_user_totp(Some(TotpSettings {
+        secret: secret.clone(),
+        enabled: false,
+        last_step: None,
+        recovery_codes: Vec::new(),
+    }))?;
+    Ok(TotpEnrollment {
+        uri: otpauth_uri(&secret, issuer, &account),
+        secret,
+    })
+}
+
+/// # Confirm TOTP enrollment
+/// Enable 2FA with the first valid code, and return the recovery
+/// codes. Show them to the user once, only their hashes are kept.
+pub fn confirm_totp_enrollment<T: User>(user: &mut T, code: &str) -> Result<Vec<String>, String> {
+    let mut totp = match user.get_user_totp() {
+        Some(ref totp) if totp.enabled => {
+            return Err("Two-factor authentication is already enabled.".to_owned())
+        }
+        Some(totp) => totp,
+        None => return Err("Two-factor enrollment is not started.".to_owned()),
+    };
+    let now = Utc::now().timestamp() as u64;
+    match verify_totp_code(&totp.secret, code, now, None)? {
+        Some(step) => totp.last_step = Some(step),
+        None => return Err("Wrong code.".to_owned()),
+    }
+    let (codes, hashes) = generate_recovery_codes();
+    totp.enabled = true;
+    totp.recovery_codes = hashes;
+    user.set_user_totp(Some(totp))?;
+    Ok(codes)
+}
+
+/// # Disable TOTP
+pub fn disable_totp<T: User>(user: &mut T) -> Result<(), String> {
+    user.set_user_totp(None)
+}
+
+/// # Verify second factor
+/// Accepts a TOTP code, or an unused recovery code. Used recovery
+/// codes are removed. Save the user after it.
+pub fn verify_second_factor<T: User>(user: &mut T, code: &str) -> Result<(), String> {
+    let mut totp = match user.get_user_totp() {
+        Some(totp) if totp.enabled => totp,
+        _ => return Err("Two-factor authentication is not enabled.".to_owned()),
+    };
+    let now = Utc::now().timestamp() as u64;
+    if let Some(step) = verify_totp_code(&totp.secret, code, now, totp.last_step)? {
+        totp.last_step = Some(step);
+        return user.set_user_totp(Some(totp));
+    }
+    let hash = hash_token(&normalize_recovery_code(code));
+    match totp.recovery_codes.iter().position(|item| *item == hash) {
+        Some(index) => {
+            totp.recovery_codes.remove(index);
+            user.set_user_totp(Some(totp))
+        }
+        None => Err("Wrong code.".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::user::model::user_v2::UserV2;
+
+    #[test]
+    fn test_totp_rfc6238() {
+        let secret = base32::encode(SECRET_ALPHABET, b"12345678901234567890");
+        // RFC 6238 SHA1 test vectors, last 6 digits
+        assert_eq!(totp_code(&secret, 59), Ok("287082".to_owned()));
+        assert_eq!(totp_code(&secret, 1111111109), Ok("081804".to_owned()));
+        assert_eq!(totp_code(&secret, 1234567890), Ok("005924".to_owned()));
+        assert_eq!(totp_code(&secret, 2000000000), Ok("279037".to_owned()));
+    }
+
+    #[test]
+    fn test_verify_totp_code() {
+        let secret = generate_totp_secret().unwrap();
+        let time = 1_000_000_000;
+        let code = totp_code(&secret, time).unwrap();
+        let step = time / TOTP_PERIOD;
+        assert_eq!(verify_totp_code(&secret, &code, time, None), Ok(Some(step)));
+        // Clock skew
+        assert_eq!(
+            verify_totp_code(&secret, &code, time + TOTP_PERIOD, None),
+            Ok(Some(step))
+        );
+        assert_eq!(
+            verify_totp_code(&secret, &code, time + 3 * TOTP_PERIOD, None),
+            Ok(None)
+        );
+        // Replay
+        assert_eq!(verify_totp_code(&secret, &code, time, Some(step)), Ok(None));
+    }
+
+    #[test]
+    fn test_enrollment_and_recovery() {
+        let mut user = UserV2::new();
+        user.set_user_email("demo@user.com").unwrap();
+        assert!(verify_second_factor(&mut user, "123456").is_err());
+        let enrollment = begin_totp_enrollment(&mut user, "Project A").unwrap();
+        assert!(enrollment.uri.starts_with("otpauth://totp/"));
+        assert!(confirm_totp_enrollment(&mut user, "000000x").is_err());
+        let now = Utc::now().timestamp() as u64;
+        let code = totp_code(&enrollment.secret, now).unwrap();
+        let recovery_codes = confirm_totp_enrollment(&mut user, &code).unwrap();
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+        // The enrollment code cannot be used again
+        assert!(verify_second_factor(&mut user, &code).is_err());
+        // Recovery codes are single use
+        let recovery_code = recovery_codes[0].to_uppercase();
+        assert!(verify_second_factor(&mut user, &recovery_code).is_ok());
+        assert!(verify_second_factor(&mut user, &recovery_code).is_err());
+        assert_eq!(
+            user.get_user_totp().unwrap().recovery_codes.len(),
+            RECOVERY_CODE_COUNT - 1
+        );
+        disable_totp(&mut user).unwrap();
+        assert_eq!(user.get_user_totp(), None);
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email;
+use crate::storage::relation::Ref;
+use crate::storage::{self, Storage, StorageObject};
+use crate::user::model::user_v2::UserV2;
+use crate::user::registration::find_user_by_email;
+use crate::user::status::AccountStatus;
+use crate::user::token::{generate_token, hash_token};
+use crate::user::User;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Verification link is valid for this many hours
+pub const VERIFICATION_VALID_HOURS: i64 = 48;
+
+/// # Email verification
+///
+/// Single-use, expiring token sent to an email address. The ID is the
+/// hash of the token, the token itself is only in the email link.
+#[derive(Serialize, Deserialize)]
+pub struct EmailVerification {
+    id: String,
+    path: Option<String>,
+    user: Ref<UserV2>,
+    email: String,
+    created: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    used: Option<DateTime<Utc>>,
+}
+
+impl EmailVerification {
+    pub fn get_user(&self) -> &Ref<UserV2> {
+        &self.user
+    }
+    pub fn get_email(&self) -> &str {
+        &self.email
+    }
+    pub fn is_used(&self) -> bool {
+        self.used.is_some()
+    }
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires
+    }
+}
+
+impl StorageObject for EmailVerification {
+    fn get_id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn save(&self) -> Result<(), String> {
+        storage::save_storage_object(self)
+    }
+    fn reload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|path| path.as_ref())
+    }
+    fn set_path(&mut self, path: &str) -> Result<(), String> {
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+/// # Create email verification
+///
+/// Creates a verification for the email address waiting for
+/// confirmation: the changed email, or the unverified email of a new
+/// user. Returns the token, use it in the verification link.
+pub fn create_email_verification<T: User>(
+    verifications: &mut Storage<EmailVerification>,
+    user: &T,
+) -> Result<String, String> {
+    let user_id = match user.get_user_id() {
+        Some(id) => id,
+        None => return Err("User ID is not set.".to_owned()),
+    };
+    let email = match user.get_user_pending_email() {
+        Some(email) => email,
+        None => match user.get_user_email() {
+            Some(ref email) if !user.is_user_email_verified() => email.clone(),
+            _ => return Err("There is no email address to verify.".to_owned()),
+        },
+    };
+    let token = generate_token()?;
+    let created = Utc::now();
+    storage::add_to_storage(
+        verifications,
+        EmailVerification {
+            id: hash_token(&token),
+            path: None,
+            user: Ref::new(&user_id),
+            email,
+            created,
+            expires: created + Duration::hours(VERIFICATION_VALID_HOURS),
+            used: None,
+        },
+    )?;
+    Ok(token)
+}
+
+/// # Send verification email
+/// `link` is the full verification URL containing the token.
+pub fn send_verification_email(to: &str, name: &str, link: &str) -> Result<(), String> {
+    email::send_email_from_env(
+        to,
+        name,
+        "Please verify your email address",
+        &format!(
+            "Hi {}! Please confirm your email address by visiting: {}\n\
+             The link is valid for {} hours.",
+            name, link, VERIFICATION_VALID_HOURS
+        ),
+    )
+}
+
+/// # Verify email
+///
+/// Confirms the email address of the token, and activates accounts
+/// pending verification. The address may have been registered by
+/// someone else since the link was sent, then it is refused. Returns
+/// the user ID.
+pub fn verify_email<T>(
+    verifications: &mut Storage<EmailVerification>,
+    users: &mut Storage<T>,
+    token: &str,
+) -> Result<String, String>
+where
+    T: User + StorageObject,
+{
+    let verification = match storage::get_mut_by_id(verifications, &hash_token(token)) {
+        Some(verification) => verification,
+        None => return Err("Invalid verification link.".to_owned()),
+    };
+    if verification.is_used() {
+        return Err("This verification link is already used.".to_owned());
+    }
+    if verification.is_expired() {
+        return Err("This verification link is expired.".to_owned());
+    }
+    let taken = find_user_by_email(users, &verification.email)
+        .is_some_and(|owner| owner.get_user_id().as_deref() != Some(verification.user.get_id()));
+    if taken {
+        return Err("This email address is already in use.".to_owned());
+    }
+    let user = match storage::get_mut_by_id(users, verification.user.get_id()) {
+        Some(user) => user,
+        None => return Err("User not found.".to_owned()),
+    };
+    user.confirm_user_email(&verification.email)?;
+    if user.get_user_status() == AccountStatus::PendingVerification {
+        user.set_user_status(AccountStatus::Active)?;
+    }
+    user.save()?;
+    verification.used = Some(Utc::now());
+    verification.save()?;
+    Ok(verification.user.get_id().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_verify_email() {
+        let mut users = storage::load_storage::<UserV2>("../data/verification_users").unwrap();
+        let mut verifications =
+            storage::load_storage::<EmailVerification>("../data/verification_tokens").unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("demo_user").unwrap();
+        user.set_user_email("demo@user.com").unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+
+        let token = create_email_verification(&mut verifications, &users.data[0]).unwrap();
+        assert!(verify_email(&mut verifications, &mut users, "wrong_token").is_err());
+        assert_eq!(
+            verify_email(&mut verifications, &mut users, &token),
+            Ok("demo_user".to_owned())
+        );
+        // Single use
+        assert!(verify_email(&mut verifications, &mut users, &token).is_err());
+        let user = &users.data[0];
+        assert!(user.is_user_email_verified());
+        assert_eq!(user.get_user_status(), AccountStatus::Active);
+        assert!(create_email_verification(&mut verifications, user).is_err());
+
+        // Changed email is pending until verified
+        users.data[0].set_user_email("new@user.com").unwrap();
+        assert_eq!(
+            users.data[0].get_user_email(),
+            Some("demo@user.com".to_owned())
+        );
+        let token = create_email_verification(&mut verifications, &users.data[0]).unwrap();
+        verify_email(&mut verifications, &mut users, &token).unwrap();
+        assert_eq!(
+            users.data[0].get_user_email(),
+            Some("new@user.com".to_owned())
+        );
+        assert_eq!(users.data[0].get_user_pending_email(), None);
+
+        // A pending address taken by someone else in the meantime
+        users.data[0].set_user_email("taken@user.com").unwrap();
+        let token = create_email_verification(&mut verifications, &users.data[0]).unwrap();
+        let mut user = UserV2::new();
+        user.set_user_id("other_user").unwrap();
+        user.set_user_email("Taken@User.com").unwrap();
+        storage::add_to_storage(&mut users, user).unwrap();
+        assert!(verify_email(&mut verifications, &mut users, &token).is_err());
+        assert_eq!(
+            users.data[0].get_user_email(),
+            Some("new@user.com".to_owned())
+        );
+        users.remove();
+        verifications.remove();
+    }
+}
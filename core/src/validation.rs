@@ -0,0 +1,417 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/*
+ * Validation DESIGN
+ *
+ * Field validators are plain functions returning the normalized value,
+ * or an error message. Model setters use them one by one, forms use
+ * `ValidationErrors` to run all of them, and show every error next to
+ * its input at once.
+ */
+
+/// Country used for phone numbers without international prefix
+pub const DEFAULT_PHONE_COUNTRY: &str = "HU";
+
+/// Country code, international calling code, national trunk prefix,
+/// and whether the trunk prefix can be left out. In the NANP (US) it
+/// usually is, and area codes never start with 1.
+const CALLING_CODES: &[(&str, &str, &str, bool)] = &[
+    ("HU", "36", "06", false),
+    ("AT", "43", "0", false),
+    ("CZ", "420", "", false),
+    ("DE", "49", "0", false),
+    ("FR", "33", "0", false),
+    ("GB", "44", "0", false),
+    ("HR", "385", "0", false),
+    ("IT", "39", "", false),
+    ("NL", "31", "0", false),
+    ("PL", "48", "", false),
+    ("RO", "40", "0", false),
+    ("RS", "381", "0", false),
+    ("SI", "386", "0", false),
+    ("SK", "421", "0", false),
+    ("UA", "380", "0", false),
+    ("US", "1", "1", true),
+];
+
+/// # Field error
+/// Error message of one form field.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// # Validation errors
+/// All the field errors of a form, in the order they were found.
+/// ```rust
+/// use core_lib::validation::{self, ValidationErrors};
+/// let mut errors = ValidationErrors::new();
+/// let email = errors.check("email", validation::normalize_email(" Demo@Example.COM "));
+/// errors.check("phone", validation::normalize_phone("phn", "HU"));
+/// assert_eq!(email, Some("Demo@example.com".to_owned()));
+/// assert_eq!(errors.get("email"), None);
+/// assert!(errors.get("phone").is_some());
+/// assert!(errors.into_result().is_err());
+/// ```
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        ValidationErrors { errors: Vec::new() }
+    }
+    /// # Add field error
+    pub fn add(&mut self, field: &str, message: &str) {
+        self.errors.push(FieldError {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        });
+    }
+    /// # Check field result
+    /// Records the error of `result` for `field`, and returns the
+    /// value if it is valid.
+    pub fn check<T>(&mut self, field: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.add(field, &message);
+                None
+            }
+        }
+    }
+    /// # Merge errors
+    /// Adds the errors of a nested validation, like an address.
+    pub fn merge(&mut self, other: ValidationErrors) {
+        self.errors.extend(other.errors);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+    pub fn get_errors(&self) -> &[FieldError] {
+        &self.errors
+    }
+    /// # First error of a field
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.errors
+            .iter()
+            .find(|error| error.field == field)
+            .map(|error| error.message.as_str())
+    }
+    /// # Errors by field
+    /// First error message of each field, for form templates.
+    pub fn by_field(&self) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+        for error in &self.errors {
+            fields
+                .entry(error.field.clone())
+                .or_insert_with(|| error.message.clone());
+        }
+        fields
+    }
+    /// # Into result
+    /// Ok if there is no error.
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors: Vec<String> = self
+            .errors
+            .iter()
+            .map(|error| format!("{}: {}", error.field, error.message))
+            .collect();
+        write!(f, "{}", errors.join(" "))
+    }
+}
+
+impl From<ValidationErrors> for String {
+    fn from(errors: ValidationErrors) -> Self {
+        errors.to_string()
+    }
+}
+
+/// # Validate text length
+/// Trimmed text with at least `min` and at most `max` characters.
+pub fn validate_length(text: &str, label: &str, min: usize, max: usize) -> Result<String, String> {
+    let text = text.trim();
+    let length = text.chars().count();
+    if length < min {
+        Err(format!("{} must be at least {} characters.", label, min))
+    } else if length > max {
+        Err(format!("{} must be at most {} characters.", label, max))
+    } else {
+        Ok(text.to_owned())
+    }
+}
+
+/// # Validate user name
+/// 5 to 100 characters, trimmed.
+pub fn validate_user_name(name: &str) -> Result<String, String> {
+    validate_length(name, "User name", 5, 100)
+}
+
+/// Characters allowed in an unquoted local part, besides letters and digits
+const LOCAL_PART_SPECIALS: &str = "!#$%&'*+-/=?^_`{|}~";
+
+fn is_valid_local_part(local: &str) -> bool {
+    if local.is_empty() || local.len() > 64 {
+        return false;
+    }
+    if local.len() >= 2 && local.starts_with('"') && local.ends_with('"') {
+        // Quoted string, printable ASCII, with \ escapes
+        let mut chars = local[1..local.len() - 1].chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => {
+                    if !chars
+                        .next()
+                        .is_some_and(|ch| ch == ' ' || ch.is_ascii_graphic())
+                    {
+                        return false;
+                    }
+                }
+                '"' => return false,
+                ch if ch == ' ' || ch.is_ascii_graphic() => (),
+                _ => return false,
+            }
+        }
+        return true;
+    }
+    // Dot-atom, also allowing international (RFC 6531) letters
+    local.split('.').all(|atom| {
+        !atom.is_empty()
+            && atom
+                .chars()
+                .all(|ch| ch.is_alphanumeric() || LOCAL_PART_SPECIALS.contains(ch))
+    })
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|ch| ch.is_alphanumeric() || ch == '-')
+        })
+        && !labels
+            .last()
+            .is_some_and(|tld| tld.chars().all(|ch| ch.is_ascii_digit()))
+}
+
+/// # Normalize email
+/// Parses an RFC 5321 address (dot-atom or quoted local part, host
+/// name domain), and returns it trimmed with lowercase domain. The
+/// local part keeps its case, as it may be case sensitive.
+/// ```rust
+/// use core_lib::validation::normalize_email;
+/// assert_eq!(normalize_email(" Peter@Example.COM "), Ok("Peter@example.com".to_owned()));
+/// assert!(normalize_email("user.name+tag@mail.example.hu").is_ok());
+/// assert!(normalize_email("user..name@example.com").is_err());
+/// assert!(normalize_email("user@localhost").is_err());
+/// ```
+pub fn normalize_email(email: &str) -> Result<String, String> {
+    let email = email.trim();
+    if email.is_empty() {
+        return Err("Email address is required.".to_owned());
+    }
+    let (local, domain) = match email.rfind('@') {
+        Some(at) => (&email[..at], &email[at + 1..]),
+        None => return Err("Email address must contain @.".to_owned()),
+    };
+    if !is_valid_local_part(local) {
+        return Err("The part before @ is not valid.".to_owned());
+    }
+    let domain = domain.to_lowercase();
+    if !is_valid_domain(&domain) {
+        return Err("The domain after @ is not valid.".to_owned());
+    }
+    if local.len() + 1 + domain.len() > 254 {
+        return Err("Email address is too long.".to_owned());
+    }
+    Ok(format!("{}@{}", local, domain))
+}
+
+/// # Normalize phone number
+/// Parses a phone number into E.164 format, like `+36301234567`.
+/// Spaces, dashes, dots, slashes and brackets are ignored. Numbers
+/// without `+` or `00` prefix are national numbers of `country` (ISO
+/// 3166 code), with its trunk prefix removed.
+/// ```rust
+/// use core_lib::validation::normalize_phone;
+/// assert_eq!(normalize_phone("+36 (30) 123-4567", "HU"), Ok("+36301234567".to_owned()));
+/// assert_eq!(normalize_phone("06 30 123 4567", "HU"), Ok("+36301234567".to_owned()));
+/// assert_eq!(normalize_phone("0664 123 4567", "AT"), Ok("+436641234567".to_owned()));
+/// assert!(normalize_phone("123", "HU").is_err());
+/// ```
+pub fn normalize_phone(phone: &str, country: &str) -> Result<String, String> {
+    let phone = phone.trim();
+    if phone.is_empty() {
+        return Err("Phone number is required.".to_owned());
+    }
+    let (international, rest) = match phone.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, phone),
+    };
+    if !rest
+        .chars()
+        .all(|ch| ch.is_ascii_digit() || " -./()".contains(ch))
+    {
+        return Err("Phone number can contain only digits, spaces and - . / ( ).".to_owned());
+    }
+    let digits: String = rest.chars().filter(|ch| ch.is_ascii_digit()).collect();
+    let number = if international {
+        digits
+    } else if let Some(number) = digits.strip_prefix("00") {
+        number.to_owned()
+    } else {
+        let (_, calling_code, trunk_prefix, optional_trunk_prefix) = match CALLING_CODES
+            .iter()
+            .find(|(code, _, _, _)| code.eq_ignore_ascii_case(country))
+        {
+            Some(entry) => entry,
+            None => return Err(format!("Unknown country for phone numbers: {}.", country)),
+        };
+        let national = if trunk_prefix.is_empty() {
+            digits.as_str()
+        } else {
+            match digits.strip_prefix(trunk_prefix) {
+                Some(national) => national,
+                None if *optional_trunk_prefix => digits.as_str(),
+                None => {
+                    return Err(format!(
+                        "National numbers must start with {}, or use + and the country code.",
+                        trunk_prefix
+                    ))
+                }
+            }
+        };
+        format!("{}{}", calling_code, national)
+    };
+    if number.starts_with('0') {
+        return Err("Country code cannot start with 0.".to_owned());
+    }
+    if number.len() < 8 || number.len() > 15 {
+        return Err("Phone number must have 8 to 15 digits with the country code.".to_owned());
+    }
+    Ok(format!("+{}", number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(
+            normalize_email("Demo.User@Árvíz.HU"),
+            Ok("Demo.User@árvíz.hu".to_owned())
+        );
+        assert_eq!(
+            normalize_email("\"john doe\"@example.com"),
+            Ok("\"john doe\"@example.com".to_owned())
+        );
+        for email in &[
+            "",
+            "wohoo",
+            "@example.com",
+            "user@",
+            ".user@example.com",
+            "user.@example.com",
+            "us er@example.com",
+            "user@example",
+            "user@-example.com",
+            "user@example..com",
+            "user@127.0.0.1",
+            "\"unterminated@example.com",
+        ] {
+            assert!(normalize_email(email).is_err(), "{}", email);
+        }
+        let long_local = format!("{}@example.com", "a".repeat(65));
+        assert!(normalize_email(&long_local).is_err());
+    }
+
+    #[test]
+    fn test_normalize_phone() {
+        assert_eq!(
+            normalize_phone("0036 1 234 5678", "DE"),
+            Ok("+3612345678".to_owned())
+        );
+        assert_eq!(
+            normalize_phone("030 123456", "DE"),
+            Ok("+4930123456".to_owned())
+        );
+        assert_eq!(
+            normalize_phone("06 1 234 5678", "hu"),
+            Ok("+3612345678".to_owned())
+        );
+        // The NANP trunk prefix is optional
+        assert_eq!(
+            normalize_phone("(555) 123-4567", "US"),
+            Ok("+15551234567".to_owned())
+        );
+        assert_eq!(
+            normalize_phone("1 555 123 4567", "US"),
+            Ok("+15551234567".to_owned())
+        );
+        assert!(normalize_phone("30 123 4567", "HU").is_err());
+        assert!(normalize_phone("030 123456", "XX").is_err());
+        assert!(normalize_phone("+36 30 123 4567 ext 1", "HU").is_err());
+        assert!(normalize_phone("+0 301234567", "HU").is_err());
+        assert!(normalize_phone("+36 30 1234 5678 9012", "HU").is_err());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let mut errors = ValidationErrors::new();
+        assert_eq!(
+            errors.check("name", validate_length(" Demo User ", "Name", 5, 100)),
+            Some("Demo User".to_owned())
+        );
+        errors.check("name", validate_length("abc", "Name", 5, 100));
+        errors.check("email", normalize_email("wohoo"));
+        errors.add("name", "Second error.");
+        assert_eq!(errors.get_errors().len(), 3);
+        assert_eq!(
+            errors.get("name"),
+            Some("Name must be at least 5 characters.")
+        );
+        let fields = errors.by_field();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields["name"], "Name must be at least 5 characters.");
+        assert!(String::from(errors.clone()).starts_with("name: "));
+        assert!(errors.into_result().is_err());
+        assert_eq!(ValidationErrors::new().into_result(), Ok(()));
+    }
+}
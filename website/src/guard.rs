@@ -0,0 +1,311 @@
+// Copyright (C) 2019 Peter Mezei
+//
+// This file is part of Project A.
+//
+// Project A is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// Project A is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Project A.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::DataLoad;
+use chrono::{Duration, Utc};
+use core_lib::storage;
+use core_lib::user::api_key::{authenticate_api_key, has_api_permission, API_KEY_PREFIX};
+use core_lib::user::login::{touch_session, validate_access_token};
+use core_lib::user::role::{has_permission, Permission};
+use core_lib::user::session::ClientInfo;
+use core_lib::user::signed_token::{
+    has_token_permission, validate_signed_token, TokenClaims, TokenKeys,
+};
+use core_lib::user::token::hash_token;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request, State};
+use rocket::Outcome;
+use std::env;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+
+/// Last seen time of a session is saved at most this often
+const SESSION_TOUCH_SECONDS: i64 = 60;
+
+/// # Client
+/// Request guard, never fails. Client info of the request, the IP
+/// address is the remote address. The X-Real-IP header is only used
+/// if the request comes from a proxy listed in the comma separated
+/// `TRUSTED_PROXIES` environment variable, anyone else could fake it.
+pub struct Client(pub ClientInfo);
+
+fn client_ip(request: &Request) -> Option<IpAddr> {
+    let remote = request.remote().map(|address| address.ip())?;
+    let trusted = env::var("TRUSTED_PROXIES")
+        .map(|proxies| {
+            proxies
+                .split(',')
+                .any(|proxy| proxy.trim().parse::<IpAddr>().ok() == Some(remote))
+        })
+        .unwrap_or(false);
+    if trusted {
+        request.real_ip().or(Some(remote))
+    } else {
+        Some(remote)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Client {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Client, ()> {
+        Outcome::Success(Client(ClientInfo {
+            ip: client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(|agent| agent.to_owned()),
+        }))
+    }
+}
+
+/// Bearer token of the request, if it is not an API key
+fn bearer_token(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .filter(|token| !token.starts_with(API_KEY_PREFIX))
+        .map(|token| token.trim().to_owned())
+}
+
+/// # Signed token user
+/// Request guard for API clients, reads a signed access token from
+/// the `Authorization: Bearer` header, and validates it. The token is
+/// not a login, use `ApiAuthorized` to check its scopes.
+pub struct TokenUser {
+    pub user_id: String,
+    pub token: String,
+    pub claims: TokenClaims,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for TokenUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<TokenUser, ()> {
+        let token = match bearer_token(request) {
+            Some(token) => token,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let keys = match TokenKeys::from_env() {
+            Ok(keys) => keys,
+            Err(_) => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let data = match request.guard::<State<DataLoad>>() {
+            Outcome::Success(data) => data,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let users = data.users.lock().unwrap();
+        let revoked_tokens = data.revoked_tokens.lock().unwrap();
+        match validate_signed_token(&users, &revoked_tokens, &keys, &token) {
+            Ok(claims) => Outcome::Success(TokenUser {
+                user_id: claims.sub.clone(),
+                token,
+                claims,
+            }),
+            Err(_) => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// # Logged in user
+/// Request guard, reads the access token from the private `token`
+/// cookie and validates it against the session storage.
+pub struct LoginUser {
+    pub user_id: String,
+    pub token: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for LoginUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<LoginUser, ()> {
+        let token = match request.cookies().get_private("token") {
+            Some(cookie) => cookie.value().to_owned(),
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let data = match request.guard::<State<DataLoad>>() {
+            Outcome::Success(data) => data,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let users = data.users.lock().unwrap();
+        let mut sessions = data.sessions.lock().unwrap();
+        // Signed tokens are for the API, see TokenUser
+        let user_id = match validate_access_token(&users, &sessions, None, &token) {
+            Ok(user_id) => user_id,
+            Err(_) => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let stale = storage::get_by_id(&sessions, &hash_token(&token)).is_some_and(|session| {
+            Utc::now() - session.get_last_seen() > Duration::seconds(SESSION_TOUCH_SECONDS)
+        });
+        if stale {
+            let _ = touch_session(&mut sessions, &token);
+        }
+        Outcome::Success(LoginUser { user_id, token })
+    }
+}
+
+/// # Permission a route requires
+/// Implement it on a marker type, and use it with `Authorized`.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct ViewAdminPage;
+
+impl RequiredPermission for ViewAdminPage {
+    const PERMISSION: Permission = Permission::ViewAdminPage;
+}
+
+pub struct ViewContent;
+
+impl RequiredPermission for ViewContent {
+    const PERMISSION: Permission = Permission::ViewContent;
+}
+
+pub struct ManageUsers;
+
+impl RequiredPermission for ManageUsers {
+    const PERMISSION: Permission = Permission::ManageUsers;
+}
+
+/// # Authorized user
+/// Request guard, logged in user having the required permission.
+/// ```rust,ignore
+/// #[get("/admin")]
+/// fn admin(user: Authorized<ViewAdminPage>) -> Template { .. }
+/// ```
+pub struct Authorized<P: RequiredPermission> {
+    pub user_id: String,
+    permission: PhantomData<P>,
+}
+
+impl<'a, 'r, P: RequiredPermission> FromRequest<'a, 'r> for Authorized<P> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Authorized<P>, ()> {
+        let login_user = match request.guard::<LoginUser>() {
+            Outcome::Success(login_user) => login_user,
+            _ => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let data = match request.guard::<State<DataLoad>>() {
+            Outcome::Success(data) => data,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let users = data.users.lock().unwrap();
+        match storage::get_by_id(&users, &login_user.user_id) {
+            Some(user) if has_permission(user, P::PERMISSION) => Outcome::Success(Authorized {
+                user_id: login_user.user_id,
+                permission: PhantomData,
+            }),
+            _ => Outcome::Failure((Status::Forbidden, ())),
+        }
+    }
+}
+
+/// # API key user
+/// Request guard for scripts, reads the API key from the
+/// `Authorization: Bearer <key>` header. The key is not a login,
+/// use `ApiAuthorized` to check its scopes.
+pub struct ApiKeyUser {
+    pub user_id: String,
+    pub key_id: String,
+    pub scopes: Vec<Permission>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ApiKeyUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ApiKeyUser, ()> {
+        let key = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(key) => key.to_owned(),
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let data = match request.guard::<State<DataLoad>>() {
+            Outcome::Success(data) => data,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let users = data.users.lock().unwrap();
+        let mut api_keys = data.api_keys.lock().unwrap();
+        match authenticate_api_key(&mut api_keys, &users, &key) {
+            Ok(api_key) => Outcome::Success(ApiKeyUser {
+                user_id: api_key.get_user().get_id().to_owned(),
+                key_id: api_key.get_key_id().to_owned(),
+                scopes: api_key.get_scopes().to_vec(),
+            }),
+            Err(_) => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// # Authorized API client
+/// Request guard, API key or signed access token whose user and
+/// scopes both have the required permission.
+pub struct ApiAuthorized<P: RequiredPermission> {
+    pub user_id: String,
+    permission: PhantomData<P>,
+}
+
+impl<'a, 'r, P: RequiredPermission> FromRequest<'a, 'r> for ApiAuthorized<P> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ApiAuthorized<P>, ()> {
+        let data = match request.guard::<State<DataLoad>>() {
+            Outcome::Success(data) => data,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let (user_id, allowed) = if bearer_token(request).is_some() {
+            let token_user = match request.guard::<TokenUser>() {
+                Outcome::Success(token_user) => token_user,
+                _ => return Outcome::Failure((Status::Unauthorized, ())),
+            };
+            let users = data.users.lock().unwrap();
+            let allowed = match storage::get_by_id(&users, &token_user.user_id) {
+                Some(user) => has_token_permission(user, &token_user.claims, P::PERMISSION),
+                None => false,
+            };
+            (token_user.user_id, allowed)
+        } else {
+            let api_user = match request.guard::<ApiKeyUser>() {
+                Outcome::Success(api_user) => api_user,
+                _ => return Outcome::Failure((Status::Unauthorized, ())),
+            };
+            let users = data.users.lock().unwrap();
+            let api_keys = data.api_keys.lock().unwrap();
+            let allowed = match (
+                storage::get_by_id(&users, &api_user.user_id),
+                storage::get_by_id(&api_keys, &api_user.key_id),
+            ) {
+                (Some(user), Some(api_key)) => has_api_permission(user, api_key, P::PERMISSION),
+                _ => false,
+            };
+            (api_user.user_id, allowed)
+        };
+        if allowed {
+            Outcome::Success(ApiAuthorized {
+                user_id,
+                permission: PhantomData,
+            })
+        } else {
+            Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
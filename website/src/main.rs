@@ -19,19 +19,131 @@
 
 #[macro_use]
 extern crate rocket;
+extern crate qrcode;
 extern crate serde_derive;
 
+mod guard;
+
 use self::handlebars::{
     Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext,
 };
-use rocket::http::RawStr;
-use rocket::response::{status, NamedFile, Redirect};
-use rocket::Request;
+use chrono::{DateTime, Duration, Utc};
+use core_lib::address::{self, Address};
+use core_lib::organization::invitation::Invitation;
+use core_lib::organization::model::organization_v1::OrganizationV1;
+use core_lib::search::SearchIndex;
+use core_lib::storage::{self, Storage, StorageObject, StorageStats};
+use core_lib::user::api_key::{self, ApiKey};
+use core_lib::user::lockout::{self, LoginAttempts};
+use core_lib::user::login::{self, LoginOutcome};
+use core_lib::user::model::migration::migrate_users;
+use core_lib::user::model::user_v2::UserV2;
+use core_lib::user::password;
+use core_lib::user::privacy::{self, UserData};
+use core_lib::user::profile::{self, ProfileUpdate};
+use core_lib::user::registration::{self, Registration};
+use core_lib::user::reset::{self, PasswordReset};
+use core_lib::user::role::Permission;
+use core_lib::user::security_log::{
+    self, SecurityEvent, SecurityEventFilter, SecurityEventKind, SecurityEventOutcome,
+};
+use core_lib::user::session::{self, Session};
+use core_lib::user::signed_token::{self, RefreshToken, RevokedToken, TokenKeys};
+use core_lib::user::totp;
+use core_lib::user::verification::{self, EmailVerification};
+use core_lib::user::User;
+use core_lib::validation::{self, ValidationErrors};
+use guard::{
+    ApiAuthorized, ApiKeyUser, Authorized, Client, LoginUser, ManageUsers, TokenUser,
+    ViewAdminPage, ViewContent,
+};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rocket::http::{ContentType, Cookie, Cookies, RawStr, Status};
+use rocket::request::{FlashMessage, Form};
+use rocket::response::{content, status, Flash, NamedFile, Redirect};
+use rocket::{Request, Response, State};
 use rocket_contrib::serve::StaticFiles;
 use rocket_contrib::templates::{handlebars, Template};
 use serde::Serialize;
-use std::io;
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Loaded storages, shared between requests
+///
+/// Lock order: a request locking more than one of these must lock them
+/// in the order of the fields below, skipping the ones it does not need,
+/// and must not lock an earlier one while holding a later one. Users
+/// come first, the security log comes last.
+pub struct DataLoad {
+    pub users: Mutex<Storage<UserV2>>,
+    pub sessions: Mutex<Storage<Session>>,
+    pub email_verifications: Mutex<Storage<EmailVerification>>,
+    pub password_resets: Mutex<Storage<PasswordReset>>,
+    pub login_attempts: Mutex<Storage<LoginAttempts>>,
+    pub api_keys: Mutex<Storage<ApiKey>>,
+    pub refresh_tokens: Mutex<Storage<RefreshToken>>,
+    pub revoked_tokens: Mutex<Storage<RevokedToken>>,
+    pub organizations: Mutex<Storage<OrganizationV1>>,
+    pub invitations: Mutex<Storage<Invitation>>,
+    pub user_search: Mutex<SearchIndex<UserV2>>,
+    pub security_log: Mutex<Storage<SecurityEvent>>,
+}
+
+/// # Lock every user data storage
+/// For data export and erasure, in the lock order of `DataLoad`.
+fn with_user_data<R>(data: &DataLoad, f: impl FnOnce(&mut UserData) -> R) -> R {
+    let mut users = data.users.lock().unwrap();
+    let mut sessions = data.sessions.lock().unwrap();
+    let mut email_verifications = data.email_verifications.lock().unwrap();
+    let mut password_resets = data.password_resets.lock().unwrap();
+    let mut login_attempts = data.login_attempts.lock().unwrap();
+    let mut api_keys = data.api_keys.lock().unwrap();
+    let mut refresh_tokens = data.refresh_tokens.lock().unwrap();
+    let mut organizations = data.organizations.lock().unwrap();
+    let mut invitations = data.invitations.lock().unwrap();
+    let mut security_log = data.security_log.lock().unwrap();
+    f(&mut UserData {
+        users: &mut users,
+        sessions: &mut sessions,
+        email_verifications: &mut email_verifications,
+        password_resets: &mut password_resets,
+        login_attempts: &mut login_attempts,
+        api_keys: &mut api_keys,
+        refresh_tokens: &mut refresh_tokens,
+        security_log: &mut security_log,
+        organizations: &mut organizations,
+        invitations: &mut invitations,
+    })
+}
+
+/// Issuer name shown in authenticator apps
+const TOTP_ISSUER: &str = "Project A";
+
+/// # Log security event
+/// Failure events get the error as detail. Logging errors do not
+/// break the request.
+fn log_event(
+    data: &DataLoad,
+    client: &Client,
+    kind: SecurityEventKind,
+    user_id: Option<&str>,
+    error: Option<&str>,
+) {
+    let event = match error {
+        Some(error) => SecurityEvent::new(kind, SecurityEventOutcome::Failure, user_id, &client.0)
+            .with_detail(error),
+        None => SecurityEvent::new(kind, SecurityEventOutcome::Success, user_id, &client.0),
+    };
+    let _ = security_log::record_security_event(&mut data.security_log.lock().unwrap(), event);
+}
+
+/// Public URL of the site, used in email links
+fn site_url() -> String {
+    std::env::var("SITE_URL").unwrap_or_else(|_| "http://localhost:8000".to_owned())
+}
 
 #[derive(Serialize)]
 struct TemplateContext {
@@ -105,32 +217,125 @@ fn about2() -> Template {
 }
 
 #[get["/login"]]
-fn login() -> Template {
+fn login(flash: Option<FlashMessage>) -> Template {
     #[derive(Serialize)]
     struct Data {
         title: &'static str,
-        name: &'static str,
-        age: u32,
+        error: Option<String>,
         parent: &'static str,
     }
     Template::render(
         "login",
         &Data {
             title: "Login",
-            name: "Peter Mezei",
-            age: 30,
+            error: flash.map(|flash| flash.msg().to_owned()),
             parent: "layout",
         },
     )
 }
 
-#[post["/login"]]
-fn login_post() -> Redirect {
-    Redirect::to("/")
+#[derive(FromForm)]
+struct LoginForm {
+    email: String,
+    password: String,
+}
+
+#[post("/login", data = "<form>")]
+fn login_post(
+    form: Form<LoginForm>,
+    mut cookies: Cookies,
+    client: Client,
+    data: State<DataLoad>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let result = login::login(
+        &mut data.users.lock().unwrap(),
+        &mut data.sessions.lock().unwrap(),
+        &mut data.login_attempts.lock().unwrap(),
+        &mut data.security_log.lock().unwrap(),
+        &form.email,
+        &form.password,
+        &client.0,
+    );
+    match result {
+        Ok(LoginOutcome::LoggedIn(token)) => {
+            cookies.add_private(Cookie::new("token", token));
+            Ok(Redirect::to("/"))
+        }
+        Ok(LoginOutcome::SecondFactorRequired(challenge)) => {
+            cookies.add_private(Cookie::new("login_challenge", challenge));
+            Ok(Redirect::to("/login/second_factor"))
+        }
+        Err(error) => Err(Flash::error(Redirect::to("/login"), error.to_string())),
+    }
+}
+
+#[get("/login/second_factor")]
+fn login_second_factor(flash: Option<FlashMessage>) -> Template {
+    Template::render(
+        "login_second_factor",
+        &FormContext {
+            title: "Two-factor authentication",
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    )
+}
+
+#[derive(FromForm)]
+struct CodeForm {
+    code: String,
+}
+
+#[post("/login/second_factor", data = "<form>")]
+fn login_second_factor_post(
+    form: Form<CodeForm>,
+    mut cookies: Cookies,
+    data: State<DataLoad>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let challenge = match cookies.get_private("login_challenge") {
+        Some(cookie) => cookie.value().to_owned(),
+        None => return Ok(Redirect::to("/login")),
+    };
+    let result = login::login_second_factor(
+        &mut data.users.lock().unwrap(),
+        &mut data.sessions.lock().unwrap(),
+        &mut data.login_attempts.lock().unwrap(),
+        &mut data.security_log.lock().unwrap(),
+        &challenge,
+        &form.code,
+    );
+    match result {
+        Ok(token) => {
+            cookies.remove_private(Cookie::named("login_challenge"));
+            cookies.add_private(Cookie::new("token", token));
+            Ok(Redirect::to("/"))
+        }
+        Err(login::LoginError::InvalidSecondFactor) => Err(Flash::error(
+            Redirect::to("/login/second_factor"),
+            login::LoginError::InvalidSecondFactor.to_string(),
+        )),
+        Err(error) => {
+            cookies.remove_private(Cookie::named("login_challenge"));
+            Err(Flash::error(Redirect::to("/login"), error.to_string()))
+        }
+    }
 }
 
 #[get["/logout"]]
-fn logout() -> Template {
+fn logout(mut cookies: Cookies, client: Client, data: State<DataLoad>) -> Template {
+    if let Some(cookie) = cookies.get_private("token") {
+        let result = login::logout(&mut data.sessions.lock().unwrap(), cookie.value());
+        if let Ok(user_id) = result {
+            log_event(
+                &data,
+                &client,
+                SecurityEventKind::Logout,
+                Some(&user_id),
+                None,
+            );
+        }
+        cookies.remove_private(Cookie::named("token"));
+    }
     #[derive(Serialize)]
     struct C {
         title: &'static str,
@@ -145,6 +350,1206 @@ fn logout() -> Template {
     )
 }
 
+#[derive(Serialize)]
+struct FormContext {
+    title: &'static str,
+    message: Option<String>,
+    parent: &'static str,
+}
+
+#[get("/forgot_password")]
+fn forgot_password(flash: Option<FlashMessage>) -> Template {
+    Template::render(
+        "forgot_password",
+        &FormContext {
+            title: "Forgot password",
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    )
+}
+
+#[derive(FromForm)]
+struct ForgotPasswordForm {
+    email: String,
+}
+
+#[post("/forgot_password", data = "<form>")]
+fn forgot_password_post(
+    form: Form<ForgotPasswordForm>,
+    client: Client,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    let user_id = data
+        .users
+        .lock()
+        .unwrap()
+        .data
+        .iter()
+        .find(|user| {
+            user.get_user_email()
+                .is_some_and(|email| email.eq_ignore_ascii_case(form.email.trim()))
+        })
+        .and_then(|user| user.get_user_id());
+    let users = data.users.lock().unwrap();
+    let result = reset::request_password_reset(
+        &mut data.password_resets.lock().unwrap(),
+        &users,
+        &form.email,
+        &format!("{}/reset_password/", site_url()),
+    );
+    drop(users);
+    let error = match (&result, &user_id) {
+        (Err(msg), _) => Some(msg.clone()),
+        (Ok(_), None) => Some(format!("Unknown email address ({}).", form.email.trim())),
+        (Ok(_), Some(_)) => None,
+    };
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::PasswordResetRequested,
+        user_id.as_deref(),
+        error.as_deref(),
+    );
+    match result {
+        Ok(_) => Flash::success(
+            Redirect::to("/forgot_password"),
+            "If the email address is registered, we have sent a password reset link.",
+        ),
+        Err(_) => Flash::error(
+            Redirect::to("/forgot_password"),
+            "Error while sending the email, please try again later.",
+        ),
+    }
+}
+
+#[get("/reset_password/<token>")]
+fn reset_password(token: String, flash: Option<FlashMessage>) -> Template {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        token: String,
+        message: Option<String>,
+        parent: &'static str,
+    };
+    Template::render(
+        "reset_password",
+        &C {
+            title: "Reset password",
+            token,
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    )
+}
+
+#[derive(FromForm)]
+struct ResetPasswordForm {
+    password: String,
+}
+
+#[post("/reset_password/<token>", data = "<form>")]
+fn reset_password_post(
+    token: String,
+    form: Form<ResetPasswordForm>,
+    client: Client,
+    data: State<DataLoad>,
+) -> Result<Redirect, Flash<Redirect>> {
+    // Locks are taken in the DataLoad field order
+    let mut users = data.users.lock().unwrap();
+    let mut sessions = data.sessions.lock().unwrap();
+    let result = reset::reset_password(
+        &mut data.password_resets.lock().unwrap(),
+        &mut users,
+        &mut sessions,
+        &mut data.refresh_tokens.lock().unwrap(),
+        &token,
+        &form.password,
+    );
+    drop(sessions);
+    drop(users);
+    match result {
+        Ok(user_id) => {
+            let kind = SecurityEventKind::PasswordReset;
+            log_event(&data, &client, kind, Some(&user_id), None);
+            Ok(Redirect::to("/login"))
+        }
+        Err(msg) => Err(Flash::error(
+            Redirect::to(format!("/reset_password/{}", token)),
+            msg,
+        )),
+    }
+}
+
+#[derive(FromForm)]
+struct RegisterForm {
+    name: String,
+    email: String,
+    password: String,
+}
+
+/// Register page context, the entered values (but the password)
+#[derive(Serialize)]
+struct RegisterContext {
+    title: &'static str,
+    name: String,
+    email: String,
+    registered: bool,
+    errors: BTreeMap<String, String>,
+    message: Option<String>,
+    parent: &'static str,
+}
+
+#[get("/register")]
+fn register() -> Template {
+    Template::render(
+        "register",
+        &RegisterContext {
+            title: "Register",
+            name: String::new(),
+            email: String::new(),
+            registered: false,
+            errors: BTreeMap::new(),
+            message: None,
+            parent: "layout",
+        },
+    )
+}
+
+#[post("/register", data = "<form>")]
+fn register_post(form: Form<RegisterForm>, data: State<DataLoad>) -> Template {
+    let registration = Registration {
+        name: form.name.clone(),
+        email: form.email.clone(),
+        password: form.password.clone(),
+    };
+    let result = core_lib::user::register(
+        &mut data.users.lock().unwrap(),
+        &mut data.email_verifications.lock().unwrap(),
+        &registration,
+    );
+    // Sent after the storages are unlocked, the mail server can be slow
+    let result = result.and_then(|email| {
+        email
+            .send(&format!("{}/verify_email/", site_url()))
+            .map_err(|msg| {
+                let _ = registration::cancel_registration(
+                    &mut data.users.lock().unwrap(),
+                    &mut data.email_verifications.lock().unwrap(),
+                    &email,
+                );
+                form_error(
+                    "",
+                    &format!(
+                        "We could not send the email, please try again later. ({})",
+                        msg
+                    ),
+                )
+            })
+    });
+    let mut context = RegisterContext {
+        title: "Register",
+        name: form.name.clone(),
+        email: form.email.clone(),
+        registered: result.is_ok(),
+        errors: BTreeMap::new(),
+        message: None,
+        parent: "layout",
+    };
+    match result {
+        // The same message if the email is already registered
+        Ok(_) => {
+            context.message = Some(format!(
+                "Thank you! We sent an email to {}, please open it to continue.",
+                form.email.trim()
+            ))
+        }
+        Err(errors) => {
+            context.message = errors.get("").map(str::to_owned);
+            context.errors = errors.by_field();
+        }
+    }
+    Template::render("register", &context)
+}
+
+#[get("/verify_email/<token>")]
+fn verify_email(token: String, client: Client, data: State<DataLoad>) -> Template {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        error: Option<String>,
+        parent: &'static str,
+    };
+    let mut users = data.users.lock().unwrap();
+    let result = verification::verify_email(
+        &mut data.email_verifications.lock().unwrap(),
+        &mut users,
+        &token,
+    );
+    drop(users);
+    if let Ok(user_id) = &result {
+        let kind = SecurityEventKind::EmailVerified;
+        log_event(&data, &client, kind, Some(user_id), None);
+    }
+    Template::render(
+        "verify_email",
+        &C {
+            title: "Email verification",
+            error: result.err(),
+            parent: "layout",
+        },
+    )
+}
+
+#[derive(FromForm)]
+struct ProfileForm {
+    name: String,
+    email: String,
+    phone: String,
+    country: String,
+    postal_code: String,
+    city: String,
+    street: String,
+    location: String,
+}
+
+impl From<&ProfileForm> for ProfileUpdate {
+    fn from(form: &ProfileForm) -> Self {
+        ProfileUpdate {
+            name: form.name.clone(),
+            email: form.email.clone(),
+            phone: form.phone.clone(),
+            country: form.country.clone(),
+            postal_code: form.postal_code.clone(),
+            city: form.city.clone(),
+            street: form.street.clone(),
+            location: form.location.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CountryOption {
+    code: &'static str,
+    name: &'static str,
+    selected: bool,
+}
+
+/// Profile page context, the form values with the errors of each field
+#[derive(Serialize)]
+struct ProfileContext {
+    title: &'static str,
+    name: String,
+    email: String,
+    pending_email: Option<String>,
+    phone: String,
+    postal_code: String,
+    city: String,
+    street: String,
+    location: String,
+    countries: Vec<CountryOption>,
+    address_lines: Vec<String>,
+    errors: BTreeMap<String, String>,
+    message: Option<String>,
+    parent: &'static str,
+}
+
+impl ProfileContext {
+    fn new(values: ProfileUpdate, pending_email: Option<String>, address: Option<Address>) -> Self {
+        let country = if values.country.is_empty() {
+            validation::DEFAULT_PHONE_COUNTRY.to_owned()
+        } else {
+            values.country
+        };
+        ProfileContext {
+            title: "Profile",
+            name: values.name,
+            email: values.email,
+            pending_email,
+            phone: values.phone,
+            postal_code: values.postal_code,
+            city: values.city,
+            street: values.street,
+            location: values.location,
+            countries: address::get_countries()
+                .into_iter()
+                .map(|(code, name)| CountryOption {
+                    code,
+                    name,
+                    selected: code.eq_ignore_ascii_case(&country),
+                })
+                .collect(),
+            address_lines: address.map_or_else(Vec::new, |address| address.get_lines()),
+            errors: BTreeMap::new(),
+            message: None,
+            parent: "layout",
+        }
+    }
+}
+
+#[get("/profile")]
+fn profile_page(
+    user: LoginUser,
+    flash: Option<FlashMessage>,
+    data: State<DataLoad>,
+) -> Option<Template> {
+    let users = data.users.lock().unwrap();
+    let user = storage::get_by_id(&users, &user.user_id)?;
+    let address = user.get_user_address();
+    let values = ProfileUpdate {
+        name: user.get_user_name().unwrap_or_default(),
+        email: user.get_user_email().unwrap_or_default(),
+        phone: user.get_user_phone().unwrap_or_default(),
+        country: address
+            .as_ref()
+            .map_or_else(String::new, |a| a.get_country().to_owned()),
+        postal_code: address
+            .as_ref()
+            .map_or_else(String::new, |a| a.get_postal_code().to_owned()),
+        city: address
+            .as_ref()
+            .map_or_else(String::new, |a| a.get_city().to_owned()),
+        street: address
+            .as_ref()
+            .map_or_else(String::new, |a| a.get_street().to_owned()),
+        location: address
+            .as_ref()
+            .and_then(|a| a.get_location())
+            .map_or_else(String::new, |location| location.to_string()),
+    };
+    let mut context = ProfileContext::new(values, user.get_user_pending_email(), address);
+    context.message = flash.map(|flash| flash.msg().to_owned());
+    Some(Template::render("profile", &context))
+}
+
+#[post("/profile", data = "<form>")]
+fn profile_post(
+    user: LoginUser,
+    form: Form<ProfileForm>,
+    data: State<DataLoad>,
+) -> Result<Flash<Redirect>, Template> {
+    let update = ProfileUpdate::from(&*form);
+    let mut users = data.users.lock().unwrap();
+    let pending_email = match storage::get_by_id(&users, &user.user_id) {
+        Some(user) => user.get_user_pending_email(),
+        None => return Ok(Flash::error(Redirect::to("/login"), "User not found.")),
+    };
+    let result = profile::update_profile(&mut users, &user.user_id, &update);
+    let user = match storage::get_by_id(&users, &user.user_id) {
+        Some(user) => user,
+        None => return Ok(Flash::error(Redirect::to("/login"), "User not found.")),
+    };
+    if let Err(errors) = result {
+        // Keep the entered values, so they can be corrected
+        let mut context = ProfileContext::new(update, pending_email, user.get_user_address());
+        context.message = errors.get("").map(str::to_owned);
+        context.errors = errors.by_field();
+        return Err(Template::render("profile", &context));
+    }
+    let email = match user.get_user_pending_email() {
+        Some(email) if Some(&email) != pending_email.as_ref() => email,
+        _ => {
+            return Ok(Flash::success(
+                Redirect::to("/profile"),
+                "Profile is saved.",
+            ))
+        }
+    };
+    let name = user.get_user_name().unwrap_or_default();
+    let token = verification::create_email_verification(
+        &mut data.email_verifications.lock().unwrap(),
+        user,
+    );
+    // Do not keep other requests waiting for the mail server
+    drop(users);
+    let sent = token.and_then(|token| {
+        verification::send_verification_email(
+            &email,
+            &name,
+            &format!("{}/verify_email/{}", site_url(), token),
+        )
+    });
+    Ok(match sent {
+        Ok(_) => Flash::success(
+            Redirect::to("/profile"),
+            format!(
+                "Profile is saved. Please verify {}, we sent you a link.",
+                email
+            ),
+        ),
+        Err(msg) => Flash::error(Redirect::to("/profile"), msg),
+    })
+}
+
+/// Validation errors with a single, not field related error
+fn form_error(field: &str, msg: &str) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, msg);
+    errors
+}
+
+#[get("/profile/export")]
+fn profile_export(
+    user: LoginUser,
+    client: Client,
+    data: State<DataLoad>,
+) -> Result<Response<'static>, Flash<Redirect>> {
+    let result = with_user_data(&data, |user_data| {
+        privacy::export_user_data(user_data, &user.user_id)
+    })
+    .and_then(|export| export.to_zip());
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::DataExported,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(zip) => Ok(Response::build()
+            .header(ContentType::new("application", "zip"))
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.zip\"", user.user_id),
+            )
+            .sized_body(Cursor::new(zip))
+            .finalize()),
+        Err(msg) => Err(Flash::error(Redirect::to("/profile"), msg)),
+    }
+}
+
+#[derive(FromForm)]
+struct EraseForm {
+    password: String,
+}
+
+#[post("/profile/erase", data = "<form>")]
+fn profile_erase(
+    user: LoginUser,
+    form: Form<EraseForm>,
+    mut cookies: Cookies,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    // The password is asked again, so a stolen session cannot do it
+    let hash = match storage::get_by_id(&data.users.lock().unwrap(), &user.user_id) {
+        Some(user) => user.get_password_hash(),
+        None => return Flash::error(Redirect::to("/login"), "User not found."),
+    };
+    match hash.map(|hash| password::verify_password_from_hash(&form.password, &hash)) {
+        Some(Ok(true)) => (),
+        _ => return Flash::error(Redirect::to("/profile"), "Wrong password."),
+    }
+    // Erasure records its own event, without client info
+    match with_user_data(&data, |user_data| {
+        privacy::erase_user(user_data, &user.user_id)
+    }) {
+        Ok(_) => {
+            cookies.remove_private(Cookie::named("token"));
+            Flash::success(Redirect::to("/login"), "Your account is erased.")
+        }
+        Err(msg) => Flash::error(Redirect::to("/profile"), msg),
+    }
+}
+
+#[get("/profile/two_factor")]
+fn two_factor(
+    user: LoginUser,
+    flash: Option<FlashMessage>,
+    data: State<DataLoad>,
+) -> Option<Template> {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        enabled: bool,
+        secret: Option<String>,
+        qr: Option<String>,
+        recovery_codes_left: usize,
+        message: Option<String>,
+        parent: &'static str,
+    };
+    let users = data.users.lock().unwrap();
+    let user = storage::get_by_id(&users, &user.user_id)?;
+    let settings = user.get_user_totp();
+    let enabled = settings.as_ref().is_some_and(|totp| totp.enabled);
+    // Enrollment is started, but not confirmed yet
+    let pending = settings.filter(|totp| !totp.enabled);
+    let qr = pending.as_ref().and_then(|totp| {
+        let account = user.get_user_email()?;
+        let uri = totp::otpauth_uri(&totp.secret, TOTP_ISSUER, &account);
+        let code = QrCode::new(uri.as_bytes()).ok()?;
+        Some(code.render::<svg::Color>().min_dimensions(200, 200).build())
+    });
+    Some(Template::render(
+        "two_factor",
+        &C {
+            title: "Two-factor authentication",
+            enabled,
+            recovery_codes_left: user
+                .get_user_totp()
+                .map_or(0, |totp| totp.recovery_codes.len()),
+            secret: pending.map(|totp| totp.secret),
+            qr,
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    ))
+}
+
+#[post("/profile/two_factor/enroll")]
+fn two_factor_enroll(user: LoginUser, data: State<DataLoad>) -> Flash<Redirect> {
+    let mut users = data.users.lock().unwrap();
+    let result = match storage::get_mut_by_id(&mut users, &user.user_id) {
+        Some(user) => totp::begin_totp_enrollment(user, TOTP_ISSUER).and_then(|_| user.save()),
+        None => Err("User not found.".to_owned()),
+    };
+    match result {
+        Ok(_) => Flash::success(
+            Redirect::to("/profile/two_factor"),
+            "Scan the QR code with your authenticator app, then enter the code.",
+        ),
+        Err(msg) => Flash::error(Redirect::to("/profile/two_factor"), msg),
+    }
+}
+
+#[post("/profile/two_factor/confirm", data = "<form>")]
+fn two_factor_confirm(
+    user: LoginUser,
+    form: Form<CodeForm>,
+    client: Client,
+    data: State<DataLoad>,
+) -> Result<Template, Flash<Redirect>> {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        recovery_codes: Vec<String>,
+        parent: &'static str,
+    };
+    let result = match storage::get_mut_by_id(&mut data.users.lock().unwrap(), &user.user_id) {
+        Some(user) => totp::confirm_totp_enrollment(user, &form.code)
+            .and_then(|codes| user.save().map(|_| codes)),
+        None => Err("User not found.".to_owned()),
+    };
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::TwoFactorEnabled,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(recovery_codes) => Ok(Template::render(
+            "recovery_codes",
+            &C {
+                title: "Recovery codes",
+                recovery_codes,
+                parent: "layout",
+            },
+        )),
+        Err(msg) => Err(Flash::error(Redirect::to("/profile/two_factor"), msg)),
+    }
+}
+
+#[post("/profile/two_factor/disable", data = "<form>")]
+fn two_factor_disable(
+    user: LoginUser,
+    form: Form<CodeForm>,
+    client: Client,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    // Disabling needs a valid code, so a stolen session cannot do it
+    let result = match storage::get_mut_by_id(&mut data.users.lock().unwrap(), &user.user_id) {
+        Some(user) => totp::verify_second_factor(user, &form.code)
+            .and_then(|_| totp::disable_totp(user))
+            .and_then(|_| user.save()),
+        None => Err("User not found.".to_owned()),
+    };
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::TwoFactorDisabled,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(_) => Flash::success(
+            Redirect::to("/profile/two_factor"),
+            "Two-factor authentication is disabled.",
+        ),
+        Err(msg) => Flash::error(Redirect::to("/profile/two_factor"), msg),
+    }
+}
+
+#[get("/profile/api_keys")]
+fn api_keys(user: LoginUser, flash: Option<FlashMessage>, data: State<DataLoad>) -> Template {
+    #[derive(Serialize)]
+    struct Key {
+        id: String,
+        name: String,
+        prefix: String,
+        scopes: String,
+        created: String,
+        expires: Option<String>,
+        last_used: Option<String>,
+        revoked: bool,
+        expired: bool,
+    };
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        keys: Vec<Key>,
+        message: Option<String>,
+        parent: &'static str,
+    };
+    let format = |time: DateTime<Utc>| time.format("%Y-%m-%d %H:%M UTC").to_string();
+    let api_keys = data.api_keys.lock().unwrap();
+    let keys = api_key::get_user_api_keys(&api_keys, &user.user_id)
+        .into_iter()
+        .map(|key| Key {
+            id: key.get_key_id().to_owned(),
+            name: key.get_name().to_owned(),
+            prefix: key.get_prefix().to_owned(),
+            scopes: key
+                .get_scopes()
+                .iter()
+                .map(|scope| format!("{:?}", scope))
+                .collect::<Vec<String>>()
+                .join(", "),
+            created: format(key.get_created()),
+            expires: key.get_expires().map(format),
+            last_used: key.get_last_used().map(format),
+            revoked: key.is_revoked(),
+            expired: key.is_expired(),
+        })
+        .collect();
+    Template::render(
+        "api_keys",
+        &C {
+            title: "API keys",
+            keys,
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    )
+}
+
+#[derive(FromForm)]
+struct ApiKeyForm {
+    name: String,
+    /// Comma separated permissions, empty for every permission
+    scopes: String,
+    expires_days: Option<i64>,
+}
+
+#[post("/profile/api_keys", data = "<form>")]
+fn api_key_create(
+    user: LoginUser,
+    form: Form<ApiKeyForm>,
+    client: Client,
+    data: State<DataLoad>,
+) -> Result<Template, Flash<Redirect>> {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        name: String,
+        key: String,
+        parent: &'static str,
+    };
+    let scopes: Result<Vec<Permission>, String> = form
+        .scopes
+        .split(',')
+        .filter(|scope| !scope.trim().is_empty())
+        .map(|scope| scope.parse())
+        .collect();
+    let result = scopes.and_then(|scopes| {
+        let users = data.users.lock().unwrap();
+        let user = match storage::get_by_id(&users, &user.user_id) {
+            Some(user) => user,
+            None => return Err("User not found.".to_owned()),
+        };
+        api_key::create_api_key(
+            &mut data.api_keys.lock().unwrap(),
+            user,
+            &form.name,
+            &scopes,
+            form.expires_days
+                .map(|days| Utc::now() + Duration::days(days)),
+        )
+    });
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::ApiKeyCreated,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(key) => Ok(Template::render(
+            "api_key_created",
+            &C {
+                title: "API key created",
+                name: form.name.trim().to_owned(),
+                key,
+                parent: "layout",
+            },
+        )),
+        Err(msg) => Err(Flash::error(Redirect::to("/profile/api_keys"), msg)),
+    }
+}
+
+#[post("/profile/api_keys/<key_id>/revoke")]
+fn api_key_revoke(
+    user: LoginUser,
+    key_id: String,
+    client: Client,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    let result =
+        api_key::revoke_api_key(&mut data.api_keys.lock().unwrap(), &user.user_id, &key_id);
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::ApiKeyRevoked,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(_) => Flash::success(Redirect::to("/profile/api_keys"), "API key is revoked."),
+        Err(msg) => Flash::error(Redirect::to("/profile/api_keys"), msg),
+    }
+}
+
+/// # Current API user
+/// Example API endpoint, returns the user of the API key.
+#[get("/api/me")]
+fn api_me(
+    user: ApiAuthorized<ViewContent>,
+    data: State<DataLoad>,
+) -> Option<content::Json<String>> {
+    #[derive(Serialize)]
+    struct Me {
+        id: String,
+        name: Option<String>,
+        email: Option<String>,
+    };
+    let users = data.users.lock().unwrap();
+    let user = storage::get_by_id(&users, &user.user_id)?;
+    let me = Me {
+        id: user.get_user_id()?,
+        name: user.get_user_name(),
+        email: user.get_user_email(),
+    };
+    serde_json::to_string(&me).ok().map(content::Json)
+}
+
+/// # Issue signed tokens
+/// Exchange an API key for a signed access token and a refresh token,
+/// both limited to the scopes of the key.
+#[post("/api/token")]
+fn api_token(
+    api_user: ApiKeyUser,
+    data: State<DataLoad>,
+) -> Result<content::Json<String>, status::Custom<String>> {
+    let keys = TokenKeys::from_env().map_err(token_error)?;
+    let users = data.users.lock().unwrap();
+    let user = match storage::get_by_id(&users, &api_user.user_id) {
+        Some(user) => user,
+        None => return Err(token_error("User not found.".to_owned())),
+    };
+    let pair = signed_token::issue_token_pair(
+        &keys,
+        &mut data.refresh_tokens.lock().unwrap(),
+        user,
+        &api_user.scopes,
+    )
+    .map_err(token_error)?;
+    token_json(&pair)
+}
+
+#[derive(FromForm)]
+struct RefreshForm {
+    refresh_token: String,
+}
+
+#[post("/api/token/refresh", data = "<form>")]
+fn api_token_refresh(
+    form: Form<RefreshForm>,
+    data: State<DataLoad>,
+) -> Result<content::Json<String>, status::Custom<String>> {
+    let keys = TokenKeys::from_env().map_err(token_error)?;
+    let users = data.users.lock().unwrap();
+    let pair = signed_token::refresh_access_token(
+        &keys,
+        &mut data.refresh_tokens.lock().unwrap(),
+        &users,
+        &form.refresh_token,
+    )
+    .map_err(|msg| status::Custom(Status::Unauthorized, msg))?;
+    token_json(&pair)
+}
+
+/// # Revoke signed token
+/// Put the Bearer access token on the revocation list, and revoke the
+/// refresh tokens of the user.
+#[post("/api/token/revoke")]
+fn api_token_revoke(
+    user: TokenUser,
+    data: State<DataLoad>,
+) -> Result<status::NoContent, status::Custom<String>> {
+    let keys = TokenKeys::from_env().map_err(token_error)?;
+    signed_token::revoke_access_token(&mut data.revoked_tokens.lock().unwrap(), &keys, &user.token)
+        .map_err(|msg| status::Custom(Status::BadRequest, msg))?;
+    signed_token::revoke_refresh_tokens(&mut data.refresh_tokens.lock().unwrap(), &user.user_id)
+        .map_err(token_error)?;
+    Ok(status::NoContent)
+}
+
+fn token_error(msg: String) -> status::Custom<String> {
+    status::Custom(Status::InternalServerError, msg)
+}
+
+fn token_json(
+    pair: &signed_token::TokenPair,
+) -> Result<content::Json<String>, status::Custom<String>> {
+    serde_json::to_string(pair)
+        .map(content::Json)
+        .map_err(|err| token_error(err.to_string()))
+}
+
+#[get("/profile/sessions")]
+fn sessions(user: LoginUser, flash: Option<FlashMessage>, data: State<DataLoad>) -> Template {
+    #[derive(Serialize)]
+    struct Item {
+        id: String,
+        created: String,
+        last_seen: String,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        current: bool,
+    };
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        sessions: Vec<Item>,
+        message: Option<String>,
+        parent: &'static str,
+    };
+    let format = |time: DateTime<Utc>| time.format("%Y-%m-%d %H:%M UTC").to_string();
+    let sessions = data.sessions.lock().unwrap();
+    let items = session::get_user_sessions(&sessions, &user.user_id)
+        .into_iter()
+        .map(|item| Item {
+            id: item.get_session_id(),
+            created: format(item.get_created()),
+            last_seen: format(item.get_last_seen()),
+            ip: item.get_client().ip.clone(),
+            user_agent: item.get_client().user_agent.clone(),
+            current: item.is_token(&user.token),
+        })
+        .collect();
+    Template::render(
+        "sessions",
+        &C {
+            title: "Sessions",
+            sessions: items,
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    )
+}
+
+#[post("/profile/sessions/<session_id>/revoke")]
+fn session_revoke(
+    user: LoginUser,
+    session_id: String,
+    client: Client,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    let result = session::revoke_session(
+        &mut data.sessions.lock().unwrap(),
+        &user.user_id,
+        &session_id,
+    );
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::SessionRevoked,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(_) => Flash::success(Redirect::to("/profile/sessions"), "Session is revoked."),
+        Err(msg) => Flash::error(Redirect::to("/profile/sessions"), msg),
+    }
+}
+
+/// # Log out everywhere else
+#[post("/profile/sessions/revoke_others")]
+fn sessions_revoke_others(
+    user: LoginUser,
+    client: Client,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    let result = session::revoke_other_sessions(
+        &mut data.sessions.lock().unwrap(),
+        &user.user_id,
+        &user.token,
+    )
+    .and_then(|count| {
+        signed_token::revoke_refresh_tokens(&mut data.refresh_tokens.lock().unwrap(), &user.user_id)
+            .map(|_| count)
+    });
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::SessionRevoked,
+        Some(&user.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(count) => Flash::success(
+            Redirect::to("/profile/sessions"),
+            format!("Logged out from {} other sessions.", count),
+        ),
+        Err(msg) => Flash::error(Redirect::to("/profile/sessions"), msg),
+    }
+}
+
+#[derive(FromForm)]
+struct UserForm {
+    user_id: String,
+}
+
+/// # Revoke every session of a user
+#[post("/admin/revoke_sessions", data = "<form>")]
+fn admin_revoke_sessions(
+    _user: Authorized<ManageUsers>,
+    form: Form<UserForm>,
+    client: Client,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    let result =
+        login::logout_user(&mut data.sessions.lock().unwrap(), &form.user_id).and_then(|count| {
+            signed_token::revoke_refresh_tokens(
+                &mut data.refresh_tokens.lock().unwrap(),
+                &form.user_id,
+            )
+            .map(|_| count)
+        });
+    log_event(
+        &data,
+        &client,
+        SecurityEventKind::SessionRevoked,
+        Some(&form.user_id),
+        result.as_ref().err().map(|msg| msg.as_str()),
+    );
+    match result {
+        Ok(count) => Flash::success(
+            Redirect::to("/admin"),
+            format!("{} sessions of {} are revoked.", count, form.user_id),
+        ),
+        Err(msg) => Flash::error(Redirect::to("/admin"), msg),
+    }
+}
+
+#[get("/admin")]
+fn admin(
+    _user: Authorized<ViewAdminPage>,
+    flash: Option<FlashMessage>,
+    data: State<DataLoad>,
+) -> Template {
+    #[derive(Serialize)]
+    struct Locked {
+        key: String,
+        failures: u32,
+        until: String,
+    };
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        users: Option<StorageStats>,
+        locked: Vec<Locked>,
+        message: Option<String>,
+        parent: &'static str,
+    };
+    let users = data.users.lock().unwrap().stats().ok();
+    let login_attempts = data.login_attempts.lock().unwrap();
+    Template::render(
+        "admin",
+        &C {
+            title: "Admin",
+            users,
+            locked: lockout::get_locked_out(&login_attempts)
+                .into_iter()
+                .map(|item| Locked {
+                    key: item.get_key().to_owned(),
+                    failures: item.get_failures(),
+                    until: item
+                        .get_blocked_until()
+                        .format("%Y-%m-%d %H:%M UTC")
+                        .to_string(),
+                })
+                .collect(),
+            message: flash.map(|flash| flash.msg().to_owned()),
+            parent: "layout",
+        },
+    )
+}
+
+#[get("/admin/users?<q>")]
+fn admin_users(
+    _user: Authorized<ViewAdminPage>,
+    q: Option<String>,
+    data: State<DataLoad>,
+) -> Template {
+    #[derive(Serialize)]
+    struct Row {
+        id: String,
+        name: String,
+        email: String,
+        status: String,
+    };
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        q: String,
+        users: Vec<Row>,
+        parent: &'static str,
+    };
+    let q = q.unwrap_or_default();
+    let users = data.users.lock().unwrap();
+    let mut index = data.user_search.lock().unwrap();
+    // Profile changes are saved directly, rebuild the index if needed
+    index.refresh(&users);
+    Template::render(
+        "admin_users",
+        &C {
+            title: "Users",
+            users: index
+                .search_storage(&users, &q)
+                .into_iter()
+                .map(|user| Row {
+                    id: user.get_user_id().unwrap_or_default(),
+                    name: user.get_user_name().unwrap_or_default(),
+                    email: user.get_user_email().unwrap_or_default(),
+                    status: format!("{:?}", user.get_user_status()),
+                })
+                .collect(),
+            q,
+            parent: "layout",
+        },
+    )
+}
+
+#[derive(FromForm)]
+struct UnlockForm {
+    key: String,
+}
+
+#[post("/admin/unlock", data = "<form>")]
+fn admin_unlock(
+    _user: Authorized<ManageUsers>,
+    form: Form<UnlockForm>,
+    data: State<DataLoad>,
+) -> Flash<Redirect> {
+    match lockout::clear_failed_logins(&mut data.login_attempts.lock().unwrap(), &form.key) {
+        Ok(_) => Flash::success(Redirect::to("/admin"), format!("{} is unlocked.", form.key)),
+        Err(msg) => Flash::error(Redirect::to("/admin"), msg),
+    }
+}
+
+/// Security event as shown on the activity pages
+#[derive(Serialize)]
+struct EventRow {
+    time: String,
+    kind: String,
+    success: bool,
+    user_id: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    detail: Option<String>,
+}
+
+impl From<&SecurityEvent> for EventRow {
+    fn from(event: &SecurityEvent) -> Self {
+        EventRow {
+            time: event.get_time().format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            kind: format!("{:?}", event.get_kind()),
+            success: event.get_outcome() == SecurityEventOutcome::Success,
+            user_id: event.get_user_id().map(str::to_owned),
+            ip: event.get_ip().map(str::to_owned),
+            user_agent: event.get_user_agent().map(str::to_owned),
+            detail: event.get_detail().map(str::to_owned),
+        }
+    }
+}
+
+/// Number of events listed on the activity pages
+const ACTIVITY_EVENTS: usize = 50;
+
+#[get("/profile/activity")]
+fn activity(user: LoginUser, data: State<DataLoad>) -> Template {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        events: Vec<EventRow>,
+        parent: &'static str,
+    };
+    let log = data.security_log.lock().unwrap();
+    Template::render(
+        "activity",
+        &C {
+            title: "Account activity",
+            events: security_log::get_user_events(&log, &user.user_id, ACTIVITY_EVENTS)
+                .into_iter()
+                .map(EventRow::from)
+                .collect(),
+            parent: "layout",
+        },
+    )
+}
+
+#[get("/admin/security_log?<user_id>&<failures>")]
+fn admin_security_log(
+    _user: Authorized<ViewAdminPage>,
+    user_id: Option<String>,
+    failures: Option<bool>,
+    data: State<DataLoad>,
+) -> Template {
+    #[derive(Serialize)]
+    struct C {
+        title: &'static str,
+        user_id: Option<String>,
+        failures: bool,
+        events: Vec<EventRow>,
+        parent: &'static str,
+    };
+    let user_id = user_id.filter(|id| !id.trim().is_empty());
+    let failures = failures.unwrap_or(false);
+    let filter = SecurityEventFilter {
+        user_id: user_id.clone(),
+        outcome: if failures {
+            Some(SecurityEventOutcome::Failure)
+        } else {
+            None
+        },
+        ..Default::default()
+    };
+    let log = data.security_log.lock().unwrap();
+    Template::render(
+        "security_log",
+        &C {
+            title: "Security log",
+            events: security_log::find_security_events(&log, &filter, ACTIVITY_EVENTS)
+                .into_iter()
+                .map(EventRow::from)
+                .collect(),
+            user_id,
+            failures,
+            parent: "layout",
+        },
+    )
+}
+
 #[get("/static/<file..>")]
 pub fn static_file(file: PathBuf) -> Option<NamedFile> {
     NamedFile::open(Path::new("static/").join(file)).ok()
@@ -157,7 +1562,26 @@ fn not_found(req: &Request<'_>) -> Template {
     Template::render("error/404", &map)
 }
 
+#[catch(401)]
+fn unauthorized(_req: &Request<'_>) -> Redirect {
+    Redirect::to("/login")
+}
+
+#[catch(403)]
+fn forbidden(req: &Request<'_>) -> Template {
+    let mut map = std::collections::HashMap::new();
+    map.insert("path", req.uri().path());
+    Template::render("error/403", &map)
+}
+
 fn rocket() -> rocket::Rocket {
+    let users = migrate_users("data/users").unwrap();
+    let mut user_search: SearchIndex<UserV2> = SearchIndex::new(vec![
+        |user| user.get_user_name(),
+        |user| user.get_user_email(),
+        |user| user.get_user_id(),
+    ]);
+    user_search.index_storage(&users);
     rocket::ignite()
         .mount(
             "/",
@@ -171,11 +1595,74 @@ fn rocket() -> rocket::Rocket {
                 about2,
                 submit_order,
                 login,
-                logout
+                login_second_factor,
+                login_second_factor_post,
+                logout,
+                verify_email,
+                forgot_password,
+                forgot_password_post,
+                reset_password,
+                reset_password_post,
+                two_factor,
+                two_factor_enroll,
+                two_factor_confirm,
+                two_factor_disable,
+                api_keys,
+                api_key_create,
+                api_key_revoke,
+                api_me,
+                sessions,
+                session_revoke,
+                sessions_revoke_others,
+                api_token,
+                api_token_refresh,
+                api_token_revoke,
+                admin,
+                admin_users,
+                admin_unlock,
+                admin_revoke_sessions,
+                activity,
+                admin_security_log,
+                profile_page,
+                profile_post,
+                profile_export,
+                profile_erase,
+                register,
+                register_post
             ],
         )
+        .manage(DataLoad {
+            users: Mutex::new(users),
+            sessions: Mutex::new(storage::load_storage::<Session>("data/sessions").unwrap()),
+            email_verifications: Mutex::new(
+                storage::load_storage::<EmailVerification>("data/email_verifications").unwrap(),
+            ),
+            password_resets: Mutex::new(
+                storage::load_storage::<PasswordReset>("data/password_resets").unwrap(),
+            ),
+            login_attempts: Mutex::new(
+                storage::load_storage::<LoginAttempts>("data/login_attempts").unwrap(),
+            ),
+            api_keys: Mutex::new(storage::load_storage::<ApiKey>("data/api_keys").unwrap()),
+            refresh_tokens: Mutex::new(
+                storage::load_storage::<RefreshToken>("data/refresh_tokens").unwrap(),
+            ),
+            revoked_tokens: Mutex::new(
+                storage::load_storage::<RevokedToken>("data/revoked_tokens").unwrap(),
+            ),
+            organizations: Mutex::new(
+                storage::load_storage::<OrganizationV1>("data/organizations").unwrap(),
+            ),
+            invitations: Mutex::new(
+                storage::load_storage::<Invitation>("data/invitations").unwrap(),
+            ),
+            user_search: Mutex::new(user_search),
+            security_log: Mutex::new(
+                storage::load_storage::<SecurityEvent>("data/security_log").unwrap(),
+            ),
+        })
         .attach(Template::fairing())
-        .register(catchers![not_found])
+        .register(catchers![not_found, unauthorized, forbidden])
 }
 
 fn main() {